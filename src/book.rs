@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use enum_map::enum_map;
+
+use crate::chess::{Side, Variant, pgn::{PgnGame, PgnResult}, polyglot::{Book, BookEntry}};
+
+const USAGE: &str = "usage: apophthegm book build [--min-games <n>] [--max-ply <n>] [--min-rating <elo>] --output <book.bin> <games.pgn>...";
+
+/// Which games and moves end up in a book
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BuildOptions {
+    /// Moves that were played in fewer games are left out
+    pub min_games: u32,
+    /// Only this many plies from the start of each game are used
+    pub max_ply: u32,
+    /// Moves by players that are rated lower, or don't have a rating, are left out
+    pub min_rating: Option<u32>,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self { min_games: 3, max_ply: 40, min_rating: None }
+    }
+}
+
+/// How a move did in the games it was played in, from the side of the player that made it
+#[derive(Clone, Copy, Default, Debug)]
+struct MoveStats {
+    games: u32,
+    wins: u32,
+    draws: u32,
+}
+
+/// Collects the moves of many games, and turns them into a Polyglot book
+pub struct BookBuilder {
+    options: BuildOptions,
+    /// By the polyglot key of the position and the encoded move
+    stats: HashMap<(u64, u16), MoveStats>,
+}
+
+impl BookBuilder {
+    pub fn new(options: BuildOptions) -> Self {
+        Self { options, stats: HashMap::new() }
+    }
+
+    /// Adds the moves of the main line. Games that aren't standard chess or don't have a result are skipped, in which case this returns false
+    pub fn add_game(&mut self, game: &PgnGame) -> bool {
+        if game.start.get_variant() != Variant::Standard || game.result == PgnResult::Unknown {
+            return false;
+        }
+        let rating = |tag: &str| game.get_tag(tag).and_then(|elo| elo.parse::<u32>().ok());
+        let ratings = enum_map! { Side::White => rating("WhiteElo"), Side::Black => rating("BlackElo") };
+
+        let mut state = game.start.clone();
+        for pgn_move in game.moves.iter().take(self.options.max_ply as usize) {
+            let side = state.to_move;
+            let rated = match self.options.min_rating {
+                Some(min) => ratings[side].is_some_and(|elo| elo >= min),
+                None => true,
+            };
+            if rated {
+                let stats = self.stats.entry((state.polyglot_key(), BookEntry::encode_move(&state, pgn_move.m))).or_default();
+                stats.games += 1;
+                match (game.result, side) {
+                    (PgnResult::WhiteWins, Side::White) | (PgnResult::BlackWins, Side::Black) => stats.wins += 1,
+                    (PgnResult::Draw, _) => stats.draws += 1,
+                    _ => {},
+                }
+            }
+            state.play(pgn_move.m);
+        }
+        return true;
+    }
+
+    /// The book of every move that was played often enough. Like Polyglot, a move weighs 2 for every win and 1 for every draw,
+    /// so moves that only lost are left out
+    pub fn build(&self) -> Book {
+        let weights: Vec<_> = self.stats.iter()
+            .filter(|(_, stats)| stats.games >= self.options.min_games)
+            .map(|(key_move, stats)| (*key_move, 2 * stats.wins as u64 + stats.draws as u64))
+            .filter(|(_, weight)| *weight > 0)
+            .collect();
+
+        // Weights have to fit in 16 bits, so the moves of a position are scaled down together until the best one does
+        let mut best: HashMap<u64, u64> = HashMap::new();
+        for ((key, _), weight) in &weights {
+            let max = best.entry(*key).or_default();
+            *max = (*max).max(*weight);
+        }
+        let entries = weights.into_iter().map(|((key, raw_move), weight)| {
+            let max = best[&key];
+            let weight = if max > u16::MAX as u64 { (weight * u16::MAX as u64 / max).max(1) } else { weight };
+            BookEntry { key, raw_move, weight: weight as u16, learn: 0 }
+        }).collect();
+        return Book::from_entries(entries);
+    }
+}
+
+/// Runs `apophthegm book <args>`, the only subcommand is `build`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    if args.next().map(String::as_str) != Some("build") {
+        return Err(USAGE.to_owned());
+    }
+
+    let mut options = BuildOptions::default();
+    let mut output = None;
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| args.next().and_then(|n| n.parse::<u32>().ok()).ok_or(format!("{name} needs a number\n{USAGE}"));
+        match arg.as_str() {
+            "--min-games" => options.min_games = number("--min-games")?,
+            "--max-ply" => options.max_ply = number("--max-ply")?,
+            "--min-rating" => options.min_rating = Some(number("--min-rating")?),
+            "--output" | "-o" => output = Some(args.next().ok_or(format!("{arg} needs a path\n{USAGE}"))?),
+            _ => inputs.push(arg),
+        }
+    }
+    let Some(output) = output else { return Err(USAGE.to_owned()); };
+    if inputs.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+
+    let mut builder = BookBuilder::new(options);
+    for input in inputs {
+        let pgn = std::fs::read_to_string(input).map_err(|e| format!("can't read \"{input}\": {e}"))?;
+        let games = PgnGame::parse_all(&pgn).map_err(|e| format!("can't parse \"{input}\": {e}"))?;
+        let used = games.iter().filter(|game| builder.add_game(game)).count();
+        println!("{input}: used {used} of {} games", games.len());
+    }
+    let book = builder.build();
+    book.write(output).map_err(|e| format!("can't write \"{output}\": {e}"))?;
+    println!("Wrote {} entries to {output}", book.len());
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{GameState, Move, pgn::PgnGame};
+
+    use super::{BookBuilder, BuildOptions};
+
+    const GAMES: &str = r#"
+[WhiteElo "2400"]
+[BlackElo "2300"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 1-0
+
+[WhiteElo "2000"]
+[BlackElo "2500"]
+[Result "1/2-1/2"]
+
+1. e4 c5 2. Nf3 1/2-1/2
+
+[WhiteElo "2600"]
+[Result "0-1"]
+
+1. d4 d5 0-1
+
+[Result "*"]
+
+1. c4 *
+"#;
+
+    fn build(options: BuildOptions) -> (BookBuilder, usize) {
+        let mut builder = BookBuilder::new(options);
+        let games = PgnGame::parse_all(GAMES).unwrap();
+        let used = games.iter().filter(|game| builder.add_game(game)).count();
+        return (builder, used);
+    }
+
+    #[test]
+    fn weights() {
+        let (builder, used) = build(BuildOptions { min_games: 1, max_ply: 100, min_rating: None });
+        // The game without a result is skipped
+        assert_eq!(used, 3);
+        let book = builder.build();
+
+        let start = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        // A win and a draw against a loss, which isn't in the book at all
+        assert_eq!(book.moves(&start), vec![(Move::from_str("e2e4"), 3)]);
+        let mut after_e4 = start.clone();
+        after_e4.play(Move::from_str("e2e4"));
+        assert_eq!(book.moves(&after_e4), vec![(Move::from_str("c7c5"), 1)]);
+        let mut after_d4 = start.clone();
+        after_d4.play(Move::from_str("d2d4"));
+        assert_eq!(book.moves(&after_d4), vec![(Move::from_str("d7d5"), 2)]);
+    }
+
+    #[test]
+    fn filters() {
+        let start = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let mut after_e4 = start.clone();
+        after_e4.play(Move::from_str("e2e4"));
+
+        let (builder, _) = build(BuildOptions { min_games: 2, max_ply: 100, min_rating: None });
+        let book = builder.build();
+        assert_eq!(book.moves(&start), vec![(Move::from_str("e2e4"), 3)]);
+        assert_eq!(book.moves(&after_e4), vec![]);
+
+        // Only the first move of white
+        let (builder, _) = build(BuildOptions { min_games: 1, max_ply: 1, min_rating: None });
+        assert_eq!(builder.build().moves(&after_e4), vec![]);
+
+        // The 2000 rated player's e4 and the unrated black player's d5 are left out
+        let (builder, _) = build(BuildOptions { min_games: 1, max_ply: 100, min_rating: Some(2200) });
+        let book = builder.build();
+        assert_eq!(book.moves(&start), vec![(Move::from_str("e2e4"), 2)]);
+        assert_eq!(book.moves(&after_e4), vec![(Move::from_str("c7c5"), 1)]);
+        let mut after_d4 = start.clone();
+        after_d4.play(Move::from_str("d2d4"));
+        assert_eq!(book.moves(&after_d4), vec![]);
+    }
+}
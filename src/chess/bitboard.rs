@@ -0,0 +1,320 @@
+use std::fmt::{Debug, Display};
+
+use super::{board::{self, Board}, Location, Piece, PieceType, Side};
+
+/// A board that keeps a set of squares for every kind of piece, so attacks can be looked up instead of walked.
+/// Bit `y * 8 + x` of a set stands for the square at (x, y).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct BitBoard {
+    /// Indexed by side, and then by the piece type minus one
+    pieces: [[u64; 6]; 2],
+    /// All pieces of a side
+    occupied: [u64; 2],
+}
+
+/// Iterates over the squares of a set, lowest bit first
+pub struct Squares(pub u64);
+
+impl Iterator for Squares {
+    type Item = Location;
+
+    fn next(&mut self) -> Option<Location> {
+        if self.0 == 0 {
+            return None;
+        }
+        let index = self.0.trailing_zeros();
+        self.0 &= self.0 - 1;
+        return Some(Location::new((index % 8) as u8, (index / 8) as u8));
+    }
+}
+
+pub fn square_bit(loc: Location) -> u64 {
+    return 1 << index(loc);
+}
+
+fn index(loc: Location) -> usize {
+    return loc.get_y() as usize * 8 + loc.get_x() as usize;
+}
+
+fn side_index(side: Side) -> usize {
+    return match side {
+        Side::White => 0,
+        Side::Black => 1,
+    };
+}
+
+/// The squares reachable from each square with a single step of one of the offsets
+const fn step_table(offsets: &[(i8, i8)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let mut i = 0;
+        while i < offsets.len() {
+            let x = (square % 8) as i8 + offsets[i].0;
+            let y = (square / 8) as i8 + offsets[i].1;
+            if x >= 0 && x < 8 && y >= 0 && y < 8 {
+                table[square] |= 1 << (y * 8 + x);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+    return table;
+}
+
+/// Everything a slider reaches from each square on an empty board, going in one direction
+const fn ray_table(dx: i8, dy: i8) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut square = 0;
+    while square < 64 {
+        let mut x = (square % 8) as i8 + dx;
+        let mut y = (square / 8) as i8 + dy;
+        while x >= 0 && x < 8 && y >= 0 && y < 8 {
+            table[square] |= 1 << (y * 8 + x);
+            x += dx;
+            y += dy;
+        }
+        square += 1;
+    }
+    return table;
+}
+
+static KNIGHT_ATTACKS: [u64; 64] = step_table(&[(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (-1, 2), (1, -2), (-1, -2)]);
+static KING_ATTACKS: [u64; 64] = step_table(&[(1, 1), (1, 0), (1, -1), (0, 1), (0, -1), (-1, 1), (-1, 0), (-1, -1)]);
+/// Indexed by the side of the pawn
+static PAWN_ATTACKS: [[u64; 64]; 2] = [step_table(&[(-1, 1), (1, 1)]), step_table(&[(-1, -1), (1, -1)])];
+/// The first four directions go towards higher bits, the last four towards lower bits
+static RAYS: [[u64; 64]; 8] = [
+    ray_table(1, 0), ray_table(0, 1), ray_table(1, 1), ray_table(-1, 1),
+    ray_table(-1, 0), ray_table(0, -1), ray_table(-1, -1), ray_table(1, -1),
+];
+const ORTHOGONAL: [usize; 4] = [0, 1, 4, 5];
+const DIAGONAL: [usize; 4] = [2, 3, 6, 7];
+
+/// The squares a slider reaches in one direction, up to and including the first piece in the way
+fn ray_attacks(dir: usize, square: usize, occupied: u64) -> u64 {
+    let ray = RAYS[dir][square];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+    let first = if dir < 4 { blockers.trailing_zeros() } else { 63 - blockers.leading_zeros() };
+    return ray ^ RAYS[dir][first as usize];
+}
+
+fn slider_attacks(dirs: &[usize], loc: Location, occupied: u64) -> u64 {
+    return dirs.iter().fold(0, |attacks, dir| attacks | ray_attacks(*dir, index(loc), occupied));
+}
+
+impl BitBoard {
+    /// The squares that hold this piece
+    pub fn pieces(&self, piece: Piece) -> u64 {
+        return self.pieces[side_index(piece.side)][piece.ty as usize - 1];
+    }
+
+    pub fn occupied_by(&self, side: Side) -> u64 {
+        return self.occupied[side_index(side)];
+    }
+
+    pub fn occupied(&self) -> u64 {
+        return self.occupied[0] | self.occupied[1];
+    }
+
+    /// The squares a piece on `loc` attacks, whether there's a piece on them or not.
+    /// Pawns only attack diagonally, their pushes aren't included.
+    pub fn attacks(&self, piece: Piece, loc: Location) -> u64 {
+        let square = index(loc);
+        return match piece.ty {
+            PieceType::King => KING_ATTACKS[square],
+            PieceType::Horsy => KNIGHT_ATTACKS[square],
+            PieceType::Pawn => PAWN_ATTACKS[side_index(piece.side)][square],
+            PieceType::Rook => slider_attacks(&ORTHOGONAL, loc, self.occupied()),
+            PieceType::Bishop => slider_attacks(&DIAGONAL, loc, self.occupied()),
+            PieceType::Queen => slider_attacks(&ORTHOGONAL, loc, self.occupied()) | slider_attacks(&DIAGONAL, loc, self.occupied()),
+        };
+    }
+
+    /// The set of pieces of either side that attack the square
+    pub fn attackers(&self, loc: Location) -> u64 {
+        let square = index(loc);
+        let of = |ty| self.pieces[0][ty as usize - 1] | self.pieces[1][ty as usize - 1];
+        let straight = of(PieceType::Rook) | of(PieceType::Queen);
+        let diagonal = of(PieceType::Bishop) | of(PieceType::Queen);
+        // A pawn of the other side would attack exactly the squares that pawns of a side attack this square from
+        let pawns = PAWN_ATTACKS[0][square] & self.pieces(Piece::new(Side::Black, PieceType::Pawn))
+            | PAWN_ATTACKS[1][square] & self.pieces(Piece::new(Side::White, PieceType::Pawn));
+        return KNIGHT_ATTACKS[square] & of(PieceType::Horsy)
+            | KING_ATTACKS[square] & of(PieceType::King)
+            | pawns
+            | slider_attacks(&ORTHOGONAL, loc, self.occupied()) & straight
+            | slider_attacks(&DIAGONAL, loc, self.occupied()) & diagonal;
+    }
+}
+
+impl Board for BitBoard {
+    fn new_empty() -> Self {
+        Self { pieces: [[0; 6]; 2], occupied: [0; 2] }
+    }
+
+    fn get(&self, index: Location) -> Option<Piece> {
+        let bit = square_bit(index);
+        let side = match (self.occupied[0] & bit != 0, self.occupied[1] & bit != 0) {
+            (true, _) => Side::White,
+            (_, true) => Side::Black,
+            _ => return None,
+        };
+        let ty = self.pieces[side_index(side)].iter().position(|set| set & bit != 0).unwrap();
+        return Some(Piece::new(side, PieceType::from_triplet(ty as u8 + 1)));
+    }
+
+    fn set(&mut self, index: Location, piece: Option<Piece>) {
+        let bit = square_bit(index);
+        for side in 0..2 {
+            if self.occupied[side] & bit != 0 {
+                self.occupied[side] &= !bit;
+                self.pieces[side].iter_mut().for_each(|set| *set &= !bit);
+            }
+        }
+        if let Some(piece) = piece {
+            let side = side_index(piece.side);
+            self.occupied[side] |= bit;
+            self.pieces[side][piece.ty as usize - 1] |= bit;
+        }
+    }
+
+    fn attackers_of(&self, loc: Location) -> Vec<Location> {
+        return Squares(self.attackers(loc)).collect();
+    }
+
+    fn is_square_attacked(&self, loc: Location, by: Side) -> bool {
+        let square = index(loc);
+        let of = |ty| self.pieces(Piece::new(by, ty));
+        let straight = of(PieceType::Rook) | of(PieceType::Queen);
+        let diagonal = of(PieceType::Bishop) | of(PieceType::Queen);
+        // Checking the cheap lookups first, and only walking the rays if there are sliders
+        return KNIGHT_ATTACKS[square] & of(PieceType::Horsy) != 0
+            || KING_ATTACKS[square] & of(PieceType::King) != 0
+            || PAWN_ATTACKS[side_index(by.opposite())][square] & of(PieceType::Pawn) != 0
+            || straight != 0 && slider_attacks(&ORTHOGONAL, loc, self.occupied()) & straight != 0
+            || diagonal != 0 && slider_attacks(&DIAGONAL, loc, self.occupied()) & diagonal != 0;
+    }
+
+    fn king(&self, side: Side) -> Option<Location> {
+        return Squares(self.pieces(Piece::new(side, PieceType::King))).next();
+    }
+
+    fn checkers(&self, side: Side) -> Vec<Location> {
+        let Some(king) = self.king(side) else { return Vec::new(); };
+        return Squares(self.attackers(king) & self.occupied_by(side.opposite())).collect();
+    }
+
+    fn pinned_pieces(&self, side: Side) -> Vec<Location> {
+        let Some(king) = self.king(side) else { return Vec::new(); };
+        let enemy = |ty| self.pieces(Piece::new(side.opposite(), ty));
+        let mut pinned = Vec::new();
+        for (dirs, sliders) in [(ORTHOGONAL, enemy(PieceType::Rook) | enemy(PieceType::Queen)), (DIAGONAL, enemy(PieceType::Bishop) | enemy(PieceType::Queen))] {
+            for dir in dirs {
+                // The first piece from the king has to be ours, and the next one along the same ray an enemy slider
+                let first = ray_attacks(dir, index(king), self.occupied()) & self.occupied_by(side);
+                let Some(first) = Squares(first).next() else { continue; };
+                if ray_attacks(dir, index(first), self.occupied()) & sliders != 0 {
+                    pinned.push(first);
+                }
+            }
+        }
+        return pinned;
+    }
+
+    fn is_valid(&self, last_moved: Side) -> bool {
+        let kings = |side| self.pieces(Piece::new(side, PieceType::King)).count_ones();
+        if kings(Side::White) != 1 || kings(Side::Black) != 1 {
+            return false;
+        }
+        return !self.in_check(last_moved);
+    }
+}
+
+impl Display for BitBoard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        board::display(self, f)
+    }
+}
+
+impl Debug for BitBoard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        board::display(self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{GameState, Location, Piece, PieceType, Side, StandardBoard, board::{convert, Board}};
+
+    use super::{BitBoard, Squares};
+
+    const FENS: [&str; 5] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    ];
+
+    #[test]
+    fn get_set() {
+        let mut board = BitBoard::new_empty();
+        let rook = Piece::new(Side::White, PieceType::Rook);
+        let knight = Piece::new(Side::Black, PieceType::Horsy);
+        board.set(Location::new(3, 5), Some(rook));
+        assert_eq!(board.get(Location::new(3, 5)), Some(rook));
+        board.set(Location::new(3, 5), Some(knight));
+        assert_eq!(board.get(Location::new(3, 5)), Some(knight));
+        assert_eq!(board.pieces(rook), 0);
+        assert_eq!(board.occupied_by(Side::White), 0);
+        board.set(Location::new(3, 5), None);
+        assert_eq!(board, BitBoard::new_empty());
+
+        for fen in FENS {
+            let standard: StandardBoard = convert(&GameState::from_fen(fen).get_board());
+            let bits: BitBoard = convert(&standard);
+            assert!(Location::all().all(|loc| bits.get(loc) == standard.get(loc)), "{fen}");
+        }
+    }
+
+    #[test]
+    fn attacks() {
+        let board: BitBoard = convert(&GameState::from_fen("4k3/8/8/3p4/8/8/8/R3K3 w - - 0 1").get_board());
+        let rook = Piece::new(Side::White, PieceType::Rook);
+        // Along the first rank up to the king, and up the whole a-file
+        assert_eq!(board.attacks(rook, Location::new(0, 0)).count_ones(), 4 + 7);
+        let queen = Piece::new(Side::White, PieceType::Queen);
+        // From b3 the pawn on d5 stops the diagonal, the file and the other diagonal are open
+        let ahead: Vec<Location> = Squares(board.attacks(queen, Location::new(1, 2))).filter(|loc| loc.get_y() > 2).collect();
+        assert_eq!(ahead.len(), 2 + 5 + 1);
+        assert_eq!(board.attacks(Piece::new(Side::White, PieceType::Horsy), Location::new(0, 0)).count_ones(), 2);
+        assert_eq!(board.attacks(Piece::new(Side::Black, PieceType::Pawn), Location::new(3, 4)), board.attacks(Piece::new(Side::White, PieceType::Pawn), Location::new(3, 2)));
+        assert!(!board.is_square_attacked(Location::new(3, 7), Side::White));
+        assert!(board.is_square_attacked(Location::new(2, 3), Side::Black));
+        assert!(board.is_square_attacked(Location::new(4, 3), Side::Black));
+        assert!(!board.is_square_attacked(Location::new(3, 3), Side::Black));
+    }
+
+    #[test]
+    fn valid_matches_standard() {
+        let pieces = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Horsy, PieceType::Pawn, PieceType::King];
+        for fen in FENS {
+            let start: BitBoard = convert(&GameState::from_fen(fen).get_board());
+            // Dropping every piece on every empty square gives plenty of kings in check
+            for loc in Location::all().filter(|loc| start.get(*loc).is_none()) {
+                for piece in pieces.iter().flat_map(|ty| [Piece::new(Side::White, *ty), Piece::new(Side::Black, *ty)]) {
+                    let mut bits = start;
+                    bits.set(loc, Some(piece));
+                    let standard: StandardBoard = convert(&bits);
+                    for side in [Side::White, Side::Black] {
+                        assert_eq!(bits.is_valid(side), standard.is_valid(side), "{piece:?} on {loc} in {fen}, {side:?} moved");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,12 +2,14 @@ use std::fmt::{Write, Debug};
 use std::ops::{Index, IndexMut};
 use std::{fmt::Display, ascii, mem::size_of, default};
 use bytemuck::{NoUninit, Pod, Zeroable};
-use enum_map::enum_map;
+use enum_map::{enum_map, EnumMap};
 
 use crate::buffers::BufferData;
 
 use super::piece::PieceExt;
+use super::state::{Castles, CastleFiles};
 use super::{Location, Piece, Side, PieceType, Move};
+use super::syzygy::Wdl;
 
 pub trait Board: Display {
     fn new_empty() -> Self;
@@ -16,97 +18,92 @@ pub trait Board: Display {
 
     fn set(&mut self, index: Location, piece: Option<Piece>);
 
-    fn is_valid(&self, last_moved: Side) -> bool {
-        let mut kings = enum_map!{ _ => 0};
-
-        for loc in Location::all() {
-            if let Some(king) = self.get(loc).get_as(PieceType::King) {
-                kings[king.side] += 1;
-                if king.side == last_moved {
-                    // The king that last moved cannot be in check
-                    let horizontal_dirs = [(1, 0), (-1, 0), (0, 1), (0, -1)];
-                    for d in horizontal_dirs {
-                        for i in 1.. {
-                            if let Some(nloc) = loc.try_add(d.0 * i, d.1 * i) {
-                                if let Some(p) = self.get(nloc) {
-                                    if p.side == king.side {
-                                        break;
-                                    } else if p.ty == PieceType::Rook || p.ty == PieceType::Queen {
-                                        return false;
-                                    } else if i == 1 && p.ty == PieceType::King {
-                                        return false;
-                                    } else {
-                                        break;
-                                    }
-                                }
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-    
-                    let horizonotal_dirs = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
-                    for d in horizonotal_dirs {
-                        for i in 1.. {
-                            if let Some(nloc) = loc.try_add(d.0 * i, d.1 * i) {
-                                if let Some(p) = self.get(nloc) {
-                                    if p.side == king.side {
-                                        break;
-                                    } else if p.ty == PieceType::Bishop || p.ty == PieceType::Queen {
-                                        return false;
-                                    } else if i == 1 && p.ty == PieceType::King {
-                                        return false;
-                                    } else {
-                                        break;
-                                    }
-                                }
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-    
-                    let horse_pos = [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (-1, 2), (1, -2), (-1, 2)];
-                    for dloc in horse_pos {
-                        if let Some(nloc) = loc.try_add(dloc.0, dloc.1) {
-                            if let Some(horse) = self.get(nloc).get_as(PieceType::Horsy) {
-                                if horse.side != king.side {
-                                    return false;
-                                }
-                            }
-                        }
+    /// Every piece, of either side, that attacks the square. Pawns only attack diagonally
+    fn attackers_of(&self, loc: Location) -> Vec<Location> {
+        let mut attackers = Vec::new();
+        for (dirs, slider) in [(ORTHOGONAL, PieceType::Rook), (DIAGONAL, PieceType::Bishop)] {
+            for d in dirs {
+                for i in 1.. {
+                    let Some(nloc) = loc.try_add(d.0 * i, d.1 * i) else { break; };
+                    let Some(p) = self.get(nloc) else { continue; };
+                    if p.ty == slider || p.ty == PieceType::Queen || (i == 1 && p.ty == PieceType::King) {
+                        attackers.push(nloc);
                     }
+                    break;
+                }
+            }
+        }
+        for d in HORSE_OFFSETS {
+            if let Some(nloc) = loc.try_add(d.0, d.1) && self.get(nloc).is_some_and(|p| p.ty == PieceType::Horsy) {
+                attackers.push(nloc);
+            }
+        }
+        // White pawns attack upwards, so they're found below the square
+        for (dy, side) in [(-1, Side::White), (1, Side::Black)] {
+            for dx in [-1, 1] {
+                if let Some(nloc) = loc.try_add(dx, dy) && self.get(nloc) == Some(Piece::new(side, PieceType::Pawn)) {
+                    attackers.push(nloc);
+                }
+            }
+        }
+        return attackers;
+    }
 
-                    let dy = if king.side == Side::Black { -1 } else { 1 };
-                    if let Some(nloc) = loc.try_add(1, dy) {
-                        if let Some(pawn) = self.get(nloc).get_as(PieceType::Pawn) {
-                            if pawn.side != king.side {
-                                return false;
-                            }
-                        }
-                    }
-                    if let Some(nloc) = loc.try_add(-1, dy) {
-                        if let Some(pawn) = self.get(nloc).get_as(PieceType::Pawn) {
-                            if pawn.side != king.side {
-                                return false;
-                            }
-                        }
-                    }
+    fn is_square_attacked(&self, loc: Location, by: Side) -> bool {
+        return self.attackers_of(loc).into_iter().any(|a| self.get(a).is_some_and(|p| p.side == by));
+    }
+
+    /// Where the king of `side` is, the first one if there's more than one
+    fn king(&self, side: Side) -> Option<Location> {
+        return Location::all().find(|loc| self.get(*loc) == Some(Piece::new(side, PieceType::King)));
+    }
+
+    /// The pieces that give check to the king of `side`
+    fn checkers(&self, side: Side) -> Vec<Location> {
+        let Some(king) = self.king(side) else { return Vec::new(); };
+        return self.attackers_of(king).into_iter().filter(|a| self.get(*a).is_some_and(|p| p.side != side)).collect();
+    }
+
+    fn in_check(&self, side: Side) -> bool {
+        return self.king(side).is_some_and(|king| self.is_square_attacked(king, side.opposite()));
+    }
+
+    /// The pieces of `side` that can't leave the line between their king and an enemy slider without exposing the king
+    fn pinned_pieces(&self, side: Side) -> Vec<Location> {
+        let Some(king) = self.king(side) else { return Vec::new(); };
+        let mut pinned = Vec::new();
+        for (dirs, slider) in [(ORTHOGONAL, PieceType::Rook), (DIAGONAL, PieceType::Bishop)] {
+            for d in dirs {
+                // Pinned if the first piece from the king is ours, and the one behind it is an enemy slider that moves along this line
+                let mut pieces = (1..).map_while(|i| king.try_add(d.0 * i, d.1 * i)).filter_map(|loc| self.get(loc).map(|p| (loc, p)));
+                if let Some((first, p)) = pieces.next() && p.side == side
+                    && let Some((_, behind)) = pieces.next() && behind.side != side && (behind.ty == slider || behind.ty == PieceType::Queen) {
+                    pinned.push(first);
                 }
             }
         }
+        return pinned;
+    }
 
-        if kings[Side::White] != 1 {
-            return false;
+    /// Whether the position can come up after `last_moved` made a move: both sides have one king, and the one that just moved isn't in check
+    fn is_valid(&self, last_moved: Side) -> bool {
+        let mut kings = enum_map!{ _ => 0};
+        for loc in Location::all() {
+            if let Some(king) = self.get(loc).get_as(PieceType::King) {
+                kings[king.side] += 1;
+            }
         }
-        if kings[Side::Black] != 1 {
+        if kings[Side::White] != 1 || kings[Side::Black] != 1 {
             return false;
         }
-
-        return true;
+        return !self.in_check(last_moved);
     }
 }
 
+const HORSE_OFFSETS: [(i16, i16); 8] = [(2, 1), (2, -1), (-2, 1), (-2, -1), (1, 2), (-1, 2), (1, -2), (-1, -2)];
+const ORTHOGONAL: [(i16, i16); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL: [(i16, i16); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct StandardBoard([Option<Piece>; 64]);
@@ -141,7 +138,7 @@ impl IndexMut<Location> for StandardBoard {
 
 #[repr(transparent)]
 #[derive(Clone, Copy, Pod, Zeroable)]
-pub struct GpuBoard([u32; 9]);
+pub struct GpuBoard([u32; 14]);
 
 impl PartialEq<Self> for GpuBoard {
     fn eq(&self, other: &Self) -> bool {
@@ -155,7 +152,7 @@ impl BufferData for GpuBoard {
 
 impl Board for GpuBoard {
     fn new_empty() -> Self {
-        Self([0; 9])
+        Self([0; 14])
     }
 
     fn get(&self, index: Location) -> Option<Piece> {
@@ -188,10 +185,191 @@ impl Board for GpuBoard {
 }
 
 impl GpuBoard {
+    const META: usize = 9;
+    const MOVE: usize = 10;
+    const TABLEBASE: usize = 11;
+    const HASH_LOW: usize = 12;
+    const HASH_HIGH: usize = 13;
+    // Bits of the metadata word, these have to match the constants in lib.wgsl
+    const WHITE_KINGSIDE: u32 = 0x1;
+    const WHITE_QUEENSIDE: u32 = 0x2;
+    const BLACK_KINGSIDE: u32 = 0x4;
+    const BLACK_QUEENSIDE: u32 = 0x8;
+    /// The file and rank of the en passant square, three bits each. It's never on the first rank, so a rank of 0 means there isn't one
+    const EN_PASSANT_MASK: u32 = 0x3F0;
+    const EN_PASSANT_RANK: u32 = 0x380;
+    /// Where the king, kingside rook and queenside rook files of white start, three bits each. Black's follow after them
+    const CASTLE_FILES_SHIFT: u32 = 10;
+    /// The checks white has given in Three-check, two bits. Black's follow after them
+    const CHECKS_SHIFT: u32 = 28;
+    // Bits of the move word, these have to match the constants in lib.wgsl
+    const MOVE_TO_SHIFT: u32 = 6;
+    const MOVE_PROMOTION_SHIFT: u32 = 12;
+    const MOVE_CAPTURE: u32 = 0x8000;
+    const MOVE_EN_PASSANT: u32 = 0x10000;
+    const MOVE_CASTLE: u32 = 0x20000;
+    const MOVE_DOUBLE_PUSH: u32 = 0x40000;
+
+    /// The zobrist key that was computed on the gpu when this board was created
+    pub fn get_hash(&self) -> u64 {
+        return u32::from_le(self.0[Self::HASH_LOW]) as u64 | (u32::from_le(self.0[Self::HASH_HIGH]) as u64) << 32;
+    }
+
+    pub fn set_hash(&mut self, hash: u64) {
+        self.0[Self::HASH_LOW] = (hash as u32).to_le();
+        self.0[Self::HASH_HIGH] = ((hash >> 32) as u32).to_le();
+    }
+
     // Used for debugging
     pub fn get_prev(&self) -> usize {
         return u32::from_le(self.0[8 as usize]) as usize;
     }
+
+    pub fn get_castles(&self) -> EnumMap<Side, Castles> {
+        let meta = u32::from_le(self.0[Self::META]);
+        return enum_map! {
+            Side::White => Castles { kingside: meta & Self::WHITE_KINGSIDE != 0, queenside: meta & Self::WHITE_QUEENSIDE != 0 },
+            Side::Black => Castles { kingside: meta & Self::BLACK_KINGSIDE != 0, queenside: meta & Self::BLACK_QUEENSIDE != 0 },
+        };
+    }
+
+    pub fn set_castles(&mut self, castles: EnumMap<Side, Castles>) {
+        let mut meta = u32::from_le(self.0[Self::META]);
+        meta &= !(Self::WHITE_KINGSIDE | Self::WHITE_QUEENSIDE | Self::BLACK_KINGSIDE | Self::BLACK_QUEENSIDE);
+        if castles[Side::White].kingside { meta |= Self::WHITE_KINGSIDE; }
+        if castles[Side::White].queenside { meta |= Self::WHITE_QUEENSIDE; }
+        if castles[Side::Black].kingside { meta |= Self::BLACK_KINGSIDE; }
+        if castles[Side::Black].queenside { meta |= Self::BLACK_QUEENSIDE; }
+        self.0[Self::META] = meta.to_le();
+    }
+
+    pub fn get_castle_files(&self) -> EnumMap<Side, CastleFiles> {
+        let meta = u32::from_le(self.0[Self::META]);
+        let files = |shift: u32| {
+            let file = |i: u32| ((meta >> (shift + i * 3)) & 0x7) as u8;
+            CastleFiles { king: file(0), kingside: file(1), queenside: file(2) }
+        };
+        return enum_map! {
+            Side::White => files(Self::CASTLE_FILES_SHIFT),
+            Side::Black => files(Self::CASTLE_FILES_SHIFT + 9),
+        };
+    }
+
+    pub fn set_castle_files(&mut self, files: EnumMap<Side, CastleFiles>) {
+        let mut meta = u32::from_le(self.0[Self::META]);
+        meta &= !(0x3FFFF << Self::CASTLE_FILES_SHIFT);
+        for (side, shift) in [(Side::White, Self::CASTLE_FILES_SHIFT), (Side::Black, Self::CASTLE_FILES_SHIFT + 9)] {
+            let f = files[side];
+            meta |= (f.king as u32 | (f.kingside as u32) << 3 | (f.queenside as u32) << 6) << shift;
+        }
+        self.0[Self::META] = meta.to_le();
+    }
+
+    pub fn get_en_passant(&self) -> Option<Location> {
+        let meta = u32::from_le(self.0[Self::META]);
+        if meta & Self::EN_PASSANT_RANK == 0 {
+            return None;
+        }
+        return Some(Location::new(((meta >> 4) & 0x7) as u8, ((meta >> 7) & 0x7) as u8));
+    }
+
+    pub fn set_en_passant(&mut self, square: Option<Location>) {
+        let mut meta = u32::from_le(self.0[Self::META]);
+        meta &= !Self::EN_PASSANT_MASK;
+        if let Some(square) = square {
+            meta |= (square.get_x() as u32) << 4 | (square.get_y() as u32) << 7;
+        }
+        self.0[Self::META] = meta.to_le();
+    }
+
+    pub fn get_checks(&self) -> EnumMap<Side, u8> {
+        let meta = u32::from_le(self.0[Self::META]);
+        return enum_map! {
+            Side::White => ((meta >> Self::CHECKS_SHIFT) & 0x3) as u8,
+            Side::Black => ((meta >> (Self::CHECKS_SHIFT + 2)) & 0x3) as u8,
+        };
+    }
+
+    pub fn set_checks(&mut self, checks: EnumMap<Side, u8>) {
+        let mut meta = u32::from_le(self.0[Self::META]);
+        meta &= !(0xF << Self::CHECKS_SHIFT);
+        meta |= (checks[Side::White] as u32 | (checks[Side::Black] as u32) << 2) << Self::CHECKS_SHIFT;
+        self.0[Self::META] = meta.to_le();
+    }
+
+    /// How many pieces of either side are on the board
+    pub fn piece_count(&self) -> u32 {
+        return self.0[0..8].iter().map(|row| {
+            let row = u32::from_le(*row);
+            (0..8).filter(|x| (row >> (x * 4)) & 0xF != 0).count() as u32
+        }).sum();
+    }
+
+    /// The tablebase result that the cpu marked the board with, for the side to move on it
+    pub fn get_tablebase(&self) -> Option<Wdl> {
+        return match u32::from_le(self.0[Self::TABLEBASE]) {
+            0 => None,
+            marked => Some(Wdl::from_i32(marked as i32 - 3)),
+        };
+    }
+
+    /// Marks the board with its tablebase result, for the side to move on it. The evaluation shader then scores it
+    /// by that result instead of its material. Children don't inherit the mark
+    pub fn set_tablebase(&mut self, wdl: Option<Wdl>) {
+        self.0[Self::TABLEBASE] = (wdl.map_or(0, |wdl| wdl as i32 + 3) as u32).to_le();
+    }
+
+    /// The move that the expansion shader played to get from the parent to this board.
+    /// Boards that didn't come out of an expansion, like the ones from [`super::GameState::get_gpu_board`], don't have one
+    pub fn get_move(&self) -> Option<MoveRecord> {
+        let record = u32::from_le(self.0[Self::MOVE]);
+        let square = |bits: u32| Location::new((bits & 0x7) as u8, ((bits >> 3) & 0x7) as u8);
+        let from = square(record);
+        let to = square(record >> Self::MOVE_TO_SHIFT);
+        // Every move goes somewhere else, an empty record is the only one where they're the same
+        if from == to {
+            return None;
+        }
+        let promotion = match (record >> Self::MOVE_PROMOTION_SHIFT) & 0x7 {
+            0 => None,
+            ty => Some(PieceType::from_triplet(ty as u8)),
+        };
+        return Some(MoveRecord {
+            from,
+            to,
+            promotion,
+            capture: record & Self::MOVE_CAPTURE != 0,
+            en_passant: record & Self::MOVE_EN_PASSANT != 0,
+            castle: record & Self::MOVE_CASTLE != 0,
+            double_push: record & Self::MOVE_DOUBLE_PUSH != 0,
+        });
+    }
+}
+
+/// A move as the expansion shader stores it next to each child board, see [`GpuBoard::get_move`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MoveRecord {
+    pub from: Location,
+    /// For castling this is the square of the rook, whichever way it's written in uci
+    pub to: Location,
+    pub promotion: Option<PieceType>,
+    /// Also set for en passant, but not for castling
+    pub capture: bool,
+    pub en_passant: bool,
+    pub castle: bool,
+    /// A pawn moving two squares, which leaves an en passant square behind
+    pub double_push: bool,
+}
+
+impl MoveRecord {
+    /// The move like uci expects it, castling is the king moving two squares, or moving onto its own rook when `chess960` is set
+    pub fn to_move(self, chess960: bool) -> Move {
+        if self.castle && !chess960 {
+            let king_to = if self.to.get_x() > self.from.get_x() { 6 } else { 2 };
+            return Move(self.from, self.from.with_x(king_to), None);
+        }
+        return Move(self.from, self.to, self.promotion);
+    }
 }
 
 pub fn convert<T: Board>(input: &impl Board) -> T {
@@ -202,36 +380,58 @@ pub fn convert<T: Board>(input: &impl Board) -> T {
     return out;
 }
 
-pub fn find_move(before: &impl Board, after: &impl Board) -> Result<Move, &'static str> {
-    let mut start_pos = None;
-    for pos in Location::all() {
-        if before.get(pos).is_some() && after.get(pos).is_none() {
-            start_pos = Some(pos);
-            break;
-        }
-    }
-    let Some(start_pos) = start_pos else { return Err("Couldn't find start"); };
+/// The pieces of `side` on `a` that aren't on the same square on `b`
+fn moved_pieces(a: &impl Board, b: &impl Board, side: Side) -> Vec<(Location, Piece)> {
+    return Location::all()
+        .filter_map(|pos| a.get(pos).filter(|p| p.side == side && b.get(pos) != Some(*p)).map(|p| (pos, p)))
+        .collect();
+}
 
-    let mut end_pos = None;
-    for pos in Location::all() {
-        if let Some(after_piece) = after.get(pos) {
-            if before.get(pos) != Some(after_piece) {
-                end_pos = Some(pos);
-                break;
-            }
-        }
+/// Works out which move turns `before` into `after`, for boards that are one move apart.
+/// Castling is written like UCI expects it: the king moving two squares, or the king moving onto its own rook when `chess960` is set.
+pub fn find_move(before: &impl Board, after: &impl Board, chess960: bool) -> Result<Move, &'static str> {
+    // Only the pieces of the side that moved show up on a new square, captured pieces just disappear
+    let Some(side) = Location::all().find_map(|pos| after.get(pos).filter(|p| before.get(pos) != Some(*p))).map(|p| p.side) else {
+        return Err("Couldn't find end");
+    };
+    let left = moved_pieces(before, after, side);
+    let entered = moved_pieces(after, before, side);
+    let find = |pieces: &[(Location, Piece)], ty: PieceType| pieces.iter().find(|(_, p)| p.ty == ty).map(|(pos, _)| *pos);
+
+    // Castling can leave the king or the rook where it was in Chess960, but never both
+    let king = (find(&left, PieceType::King), find(&entered, PieceType::King));
+    let rook = (find(&left, PieceType::Rook), find(&entered, PieceType::Rook));
+    let castle = match (king, rook) {
+        ((Some(king_from), Some(king_to)), (Some(rook_from), Some(_))) => Some((king_from, king_to, rook_from)),
+        ((Some(king_from), Some(king_to)), (None, None)) if u8::abs_diff(king_from.get_x(), king_to.get_x()) == 2 => {
+            let rook_to = king_to.with_x(if king_to.get_x() == 6 { 5 } else { 3 });
+            Some((king_from, king_to, rook_to))
+        },
+        ((None, None), (Some(rook_from), Some(rook_to))) => {
+            // A rook can't pass the king, unless it's castling
+            Location::all()
+                .find(|pos| before.get(*pos) == Some(Piece::new(side, PieceType::King)))
+                .filter(|king| king.get_y() == rook_from.get_y() && (rook_from.get_x().min(rook_to.get_x())..=rook_from.get_x().max(rook_to.get_x())).contains(&king.get_x()))
+                .map(|king| (king, king, rook_from))
+        },
+        _ => None,
+    };
+    if let Some((king_from, king_to, rook_from)) = castle {
+        return Ok(Move(king_from, if chess960 { rook_from } else { king_to }, None));
     }
-    let Some(end_pos) = end_pos else { return Err("Couldn't find end"); };
 
+    let ([(start_pos, moved)], [(end_pos, arrived)]) = (left.as_slice(), entered.as_slice()) else {
+        return Err("Couldn't find start");
+    };
     let mut promote = None;
-    if before.get(start_pos).unwrap().ty == PieceType::Pawn && (end_pos.get_y() == 0 || end_pos.get_y() == 7) {
-        promote = Some(after.get(end_pos).unwrap().ty);
+    if moved.ty == PieceType::Pawn && arrived.ty != PieceType::Pawn {
+        promote = Some(arrived.ty);
     }
 
-    return Ok(Move(start_pos, end_pos, promote));
+    return Ok(Move(*start_pos, *end_pos, promote));
 }
 
-fn display(input: &impl Board, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+pub(super) fn display(input: &impl Board, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     for y in (0..8).into_iter().rev() {
         for x in 0..8 {
             f.write_char(input.get(Location::new(x, y)).map_or('.', |p| p.to_char()))?;
@@ -284,6 +484,24 @@ mod test {
         assert_eq!(b.get(Location::new(1, 0)), Some(piece));
     }
 
+    #[test]
+    fn gpu_metadata() {
+        // The fields share one word, setting one shouldn't touch the others
+        let state = GameState::from_fen("1r2k1r1/8/8/3pP3/8/8/8/1R2K1R1 w GBgb d6 2+0 0 1");
+        let board = state.get_gpu_board();
+        assert_eq!(board.get_castles(), state.get_castles());
+        assert_eq!(board.get_castle_files(), state.get_castle_files());
+        assert_eq!(board.get_en_passant(), state.get_en_passant());
+        assert_eq!(board.get_checks(), state.get_checks());
+        assert_eq!(board.hash(state.to_move), state.hash());
+
+        let mut board = board;
+        board.set_en_passant(None);
+        assert_eq!(board.get_en_passant(), None);
+        assert_eq!(board.get_castle_files(), state.get_castle_files());
+        assert_eq!(board.get_checks(), state.get_checks());
+    }
+
     #[test]
     fn find_move_normal() {
         let mut a = StandardBoard::new_empty();
@@ -292,7 +510,7 @@ mod test {
         a.set(Location::new(0, 1), Some(piece));
         b.set(Location::new(0, 3), Some(piece));
 
-        assert_eq!(find_move(&a, &b), Ok(Move(Location::new(0, 1), Location::new(0, 3), None)));
+        assert_eq!(find_move(&a, &b, false), Ok(Move(Location::new(0, 1), Location::new(0, 3), None)));
     }
 
     #[test]
@@ -304,7 +522,33 @@ mod test {
         a.set(Location::new(0, 6), Some(piece));
         b.set(Location::new(0, 7), Some(piece));
 
-        assert_eq!(find_move(&a, &b), Ok(Move(Location::new(0, 6), Location::new(0, 7), None)));
+        assert_eq!(find_move(&a, &b, false), Ok(Move(Location::new(0, 6), Location::new(0, 7), None)));
+    }
+
+    fn assert_found(fen: &str, m: &str, chess960: bool) {
+        let state = GameState::from_fen(fen);
+        let mut next = state.clone();
+        next.play(Move::from_str(m));
+        assert_eq!(find_move(&state.get_board(), &next.get_board(), chess960), Ok(Move::from_str(m)), "{m} in {fen}");
+    }
+
+    #[test]
+    fn find_move_special() {
+        assert_found("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1", false);
+        assert_found("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1c1", false);
+        assert_found("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8", false);
+        assert_found("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1h1", true);
+        assert_found("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1a1", true);
+        // The king or the rook may already be where it ends up
+        assert_found("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1", "g1h1", true);
+        assert_found("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1", "g1b1", true);
+        assert_found("4k3/8/8/8/8/8/8/1K1R4 w D - 0 1", "b1d1", true);
+        // A rook moving next to the king isn't castling
+        assert_found("4k3/8/8/8/8/8/8/4K2R w - - 0 1", "h1f1", false);
+        // En passant also makes a captured pawn disappear
+        assert_found("k7/8/8/2pP4/8/8/8/K7 w - c6 0 1", "d5c6", false);
+        assert_found("k7/8/8/8/2pP4/8/8/K7 b - d3 0 1", "c4d3", false);
+        assert_found("k6r/6P1/8/8/8/8/8/K7 w - - 0 1", "g7h8n", false);
     }
 
     #[test]
@@ -335,4 +579,59 @@ mod test {
         check("8/1kP3R1/8/8/8/8/8/7K w - - 0 1", true); // White can *not* capture with the rook, a white pawn is in the way
         
     }
+
+    #[test]
+    fn checks_and_pins() {
+        let state = GameState::from_fen("4k3/8/8/1b6/8/3P4/r2NK2q/8 w - - 0 1");
+        let board = state.get_board();
+        let sq = |name: &str| Location::from_letters(name.chars().next().unwrap(), name.chars().nth(1).unwrap());
+        let at = |names: &[&str]| names.iter().map(|name| sq(name)).collect::<Vec<_>>();
+        assert_eq!(board.king(Side::White), Some(sq("e2")));
+        assert!(board.in_check(Side::White));
+        assert!(!board.in_check(Side::Black));
+        assert_eq!(board.checkers(Side::White), at(&["h2"]));
+        // The knight by the rook, the pawn by the bishop
+        assert_eq!(board.pinned_pieces(Side::White), at(&["d2", "d3"]));
+        assert!(board.is_square_attacked(sq("c4"), Side::White));
+        assert!(!board.is_square_attacked(sq("a8"), Side::White));
+        let mut attackers = board.attackers_of(sq("f2"));
+        attackers.sort_by_key(|loc| loc.to_string());
+        assert_eq!(attackers, at(&["e2", "h2"]));
+
+        // Two checks at once
+        let board = GameState::from_fen("4k3/8/8/8/8/5n2/8/4K2r w - - 0 1").get_board();
+        assert_eq!(board.checkers(Side::White).len(), 2);
+    }
+
+    #[test]
+    fn queries_match_default() {
+        // The gpu board uses the default implementations, the bitboard has its own
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "4k3/8/8/1b6/8/3P4/r2NK2q/8 w - - 0 1",
+            "3r4/8/8/3Q4/4k3/8/1B6/3K4 b - - 0 1",
+        ];
+        for fen in fens {
+            let state = GameState::from_fen(fen);
+            let fast = state.get_board();
+            let slow = state.get_gpu_board();
+            let sorted = |mut locs: Vec<Location>| { locs.sort_by_key(|loc| loc.to_string()); locs };
+            for loc in Location::all() {
+                assert_eq!(sorted(fast.attackers_of(loc)), sorted(slow.attackers_of(loc)), "{loc} in {fen}");
+                for side in [Side::White, Side::Black] {
+                    assert_eq!(fast.is_square_attacked(loc, side), slow.is_square_attacked(loc, side), "{loc} in {fen}");
+                }
+            }
+            for side in [Side::White, Side::Black] {
+                assert_eq!(fast.king(side), slow.king(side), "{fen}");
+                assert_eq!(fast.in_check(side), slow.in_check(side), "{fen}");
+                assert_eq!(sorted(fast.checkers(side)), sorted(slow.checkers(side)), "{fen}");
+                assert_eq!(sorted(fast.pinned_pieces(side)), sorted(slow.pinned_pieces(side)), "{fen}");
+                assert_eq!(fast.is_valid(side), slow.is_valid(side), "{fen}");
+            }
+        }
+    }
 }
\ No newline at end of file
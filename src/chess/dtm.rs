@@ -0,0 +1,492 @@
+use std::{collections::HashMap, fmt::Display, io, path::Path};
+
+use super::{Board, GameState, Location, Move, Piece, PieceType, Side, Variant, syzygy::{Material, Wdl, NAME_ORDER}};
+
+/// The most pieces, kings included, that DTM tables are made for. Every piece makes a table 64 times bigger
+pub const MAX_PIECES: usize = 4;
+const MAGIC: [u8; 4] = *b"ADTM";
+const VERSION: u8 = 1;
+const EXTENSION: &str = "dtm";
+/// Results are stored in a byte, half of it for wins and half for losses
+const MAX_MATE_MOVES: u32 = 127;
+
+/// The result of a position with perfect play, counting the plies until mate
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dtm {
+    /// The side to move mates in this many plies, which is always odd
+    Win(u32),
+    /// The side to move gets mated in this many plies, which is always even. 0 is mate on the board
+    Loss(u32),
+    Draw,
+}
+
+impl Dtm {
+    /// The result for the side that made the move which led to this position
+    pub fn before_move(self) -> Self {
+        return match self {
+            Dtm::Win(plies) => Dtm::Loss(plies + 1),
+            Dtm::Loss(plies) => Dtm::Win(plies + 1),
+            Dtm::Draw => Dtm::Draw,
+        };
+    }
+
+    pub fn wdl(self) -> Wdl {
+        return match self {
+            Dtm::Win(_) => Wdl::Win,
+            Dtm::Loss(_) => Wdl::Loss,
+            Dtm::Draw => Wdl::Draw,
+        };
+    }
+
+    /// Orders results from worst to best for the side to move, faster wins and slower losses being better
+    pub fn rank(self) -> i64 {
+        return match self {
+            Dtm::Win(plies) => 1000 - plies as i64,
+            Dtm::Loss(plies) => -1000 + plies as i64,
+            Dtm::Draw => 0,
+        };
+    }
+
+    /// 0 is a draw, then come wins by the moves until mate and losses by the same from 128. `None` if it's too long
+    fn to_byte(self) -> Option<u8> {
+        let (moves, offset) = match self {
+            Dtm::Win(plies) => ((plies + 1) / 2, 0),
+            Dtm::Loss(plies) => (plies / 2, 128),
+            Dtm::Draw => return Some(0),
+        };
+        if moves > MAX_MATE_MOVES || (offset == 0 && moves == 0) {
+            return None;
+        }
+        return Some((offset + moves) as u8);
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        return match byte {
+            0 => Dtm::Draw,
+            1..=127 => Dtm::Win(byte as u32 * 2 - 1),
+            _ => Dtm::Loss((byte as u32 - 128) * 2),
+        };
+    }
+}
+
+/// The pieces of a table, in the order their squares make up an index: the white king, the other white pieces,
+/// the black king and the other black pieces, like in names such as `KQvK`.
+/// Tables are made with the stronger pieces as white, the other way around is looked up with the colours swapped
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Layout {
+    pieces: Vec<Piece>,
+}
+
+impl Layout {
+    /// Parses names like `KRvK`, or `KRK` without the `v`. Either side can come first
+    pub fn from_name(name: &str) -> Option<Self> {
+        let material = match name.contains('v') {
+            true => Material::from_name(name)?,
+            false => {
+                // The second king starts the other side
+                let split = name.char_indices().skip(1).find(|(_, c)| *c == 'K')?.0;
+                Material::from_name(&format!("{}v{}", &name[..split], &name[split..]))?
+            },
+        };
+        return Self::from_material(material);
+    }
+
+    /// The layout for tables with this material, `None` if there are too many pieces or only kings.
+    /// Pawns on both sides aren't supported either, because the generator doesn't know about en passant
+    fn from_material(material: Material) -> Option<Self> {
+        if material.piece_count() > MAX_PIECES || material.piece_count() <= 2 || (material.pawns(0) > 0 && material.pawns(1) > 0) {
+            return None;
+        }
+        let stronger = |m: &Material| (m.0[0].iter().sum::<u8>(), m.0[0]);
+        let material = if stronger(&material.flipped()) > stronger(&material) { material.flipped() } else { material };
+        let pieces = [Side::White, Side::Black].into_iter().enumerate()
+            .flat_map(|(i, side)| NAME_ORDER.iter().zip(material.0[i]).flat_map(move |(ty, n)| (0..n).map(move |_| Piece::new(side, *ty))))
+            .collect();
+        return Some(Self { pieces });
+    }
+
+    pub fn name(&self) -> String {
+        let side = |side| self.pieces.iter().filter(|p| p.side == side).map(|p| p.ty.to_char().to_ascii_uppercase()).collect::<String>();
+        return format!("{}v{}", side(Side::White), side(Side::Black));
+    }
+
+    pub fn pieces(&self) -> &[Piece] {
+        return &self.pieces;
+    }
+
+    /// The amount of indices, with either side to move
+    pub fn size(&self) -> usize {
+        return 2 * 32 * 64usize.pow(self.pieces.len() as u32 - 1);
+    }
+
+    /// The pieces as the gpu stores them, a nibble each from the lowest bits
+    pub fn gpu_pieces(&self) -> u32 {
+        return self.pieces.iter().enumerate().map(|(i, p)| (p.as_nibble() as u32) << (i * 4)).fold(0, |a, b| a | b);
+    }
+
+    /// The layouts that a capture or a promotion leads to, without the ones that only have kings left
+    pub fn successors(&self) -> Vec<Layout> {
+        let mut result: Vec<Layout> = Vec::new();
+        for (i, piece) in self.pieces.iter().enumerate() {
+            let mut changed = Vec::new();
+            if piece.ty != PieceType::King {
+                changed.push(None);
+            }
+            if piece.ty == PieceType::Pawn {
+                changed.extend([PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Horsy].map(Some));
+            }
+            for ty in changed {
+                let mut material = Material([[0; 6]; 2]);
+                for (j, other) in self.pieces.iter().enumerate() {
+                    let ty = if i == j { ty } else { Some(other.ty) };
+                    if let Some(ty) = ty {
+                        material.0[(other.side == Side::Black) as usize][NAME_ORDER.iter().position(|t| *t == ty).unwrap()] += 1;
+                    }
+                }
+                if let Some(layout) = Self::from_material(material) && !result.contains(&layout) {
+                    result.push(layout);
+                }
+            }
+        }
+        return result;
+    }
+
+    /// The index of the position with the pieces on these squares, in the order of the layout and as `y * 8 + x`.
+    /// Positions with the white king on the right half of the board are mirrored, so only the left half is stored
+    pub fn index(&self, squares: &[u8], to_move: Side) -> usize {
+        let mirror = if squares[0] & 7 >= 4 { 7 } else { 0 };
+        let king = squares[0] ^ mirror;
+        let mut index = (to_move == Side::Black) as usize * 32 + (king >> 3) as usize * 4 + (king & 7) as usize;
+        for square in &squares[1..self.pieces.len()] {
+            index = index * 64 + (square ^ mirror) as usize;
+        }
+        return index;
+    }
+
+    /// The squares of the pieces and the side to move at an index, the opposite of [`Layout::index`]
+    pub fn decode(&self, mut index: usize) -> ([u8; MAX_PIECES], Side) {
+        let mut squares = [0; MAX_PIECES];
+        for i in (1..self.pieces.len()).rev() {
+            squares[i] = (index % 64) as u8;
+            index /= 64;
+        }
+        let king = (index % 32) as u8;
+        squares[0] = (king / 4) * 8 + king % 4;
+        let to_move = if index >= 32 { Side::Black } else { Side::White };
+        return (squares, to_move);
+    }
+
+    /// The position at an index, or `None` if it can't come up in a game: with pieces on the same square,
+    /// pawns on the first or last rank, or the side that just moved in check
+    pub fn state(&self, index: usize) -> Option<GameState> {
+        let (squares, to_move) = self.decode(index);
+        let mut state = GameState::default();
+        for (piece, square) in self.pieces.iter().zip(squares) {
+            let loc = Location::new(square & 7, square >> 3);
+            if state.get(loc).is_some() || (piece.ty == PieceType::Pawn && (square >> 3 == 0 || square >> 3 == 7)) {
+                return None;
+            }
+            state.set(loc, Some(*piece));
+        }
+        state.to_move = to_move;
+        if !state.get_board().is_valid(to_move.opposite()) {
+            return None;
+        }
+        return Some(state);
+    }
+
+    /// The index of a position with exactly the pieces of the layout. With `flip` its colours are swapped first,
+    /// and the board is mirrored so pawns keep going the same way
+    fn index_of(&self, state: &GameState, flip: bool) -> usize {
+        let mut occupied: Vec<(u8, Piece)> = Location::all().filter_map(|loc| {
+            let piece = state.get(loc)?;
+            let square = loc.get_y() * 8 + loc.get_x();
+            return Some(if flip { (square ^ 56, Piece::new(piece.side.opposite(), piece.ty)) } else { (square, piece) });
+        }).collect();
+        let mut squares = [0; MAX_PIECES];
+        for (i, piece) in self.pieces.iter().enumerate() {
+            let found = occupied.iter().position(|(_, p)| p == piece).expect("The position has the material of the table");
+            squares[i] = occupied.swap_remove(found).0;
+        }
+        let to_move = if flip { state.to_move.opposite() } else { state.to_move };
+        return self.index(&squares, to_move);
+    }
+}
+
+#[derive(Debug)]
+pub enum DtmError {
+    Io(io::Error),
+    /// The file doesn't start like a DTM table
+    Magic,
+    /// The file was written by a version this one can't read
+    Version(u8),
+    /// The pieces in the file don't make up a table
+    Material,
+    /// The file has a length of this many bytes, which doesn't match its pieces
+    Length(usize),
+    /// The result can't be stored, because the mate is too far away
+    TooLong(Dtm),
+}
+
+impl Display for DtmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DtmError::Io(e) => write!(f, "{e}"),
+            DtmError::Magic => write!(f, "not a DTM table"),
+            DtmError::Version(version) => write!(f, "unknown version {version}"),
+            DtmError::Material => write!(f, "invalid pieces"),
+            DtmError::Length(len) => write!(f, "a length of {len} bytes doesn't match the pieces"),
+            DtmError::TooLong(dtm) => write!(f, "{dtm:?} is longer than {MAX_MATE_MOVES} moves"),
+        }
+    }
+}
+
+/// The result of every position of one material, stored as a byte each after a header with the pieces
+pub struct DtmTable {
+    layout: Layout,
+    data: Vec<u8>,
+}
+
+impl DtmTable {
+    /// A table from the result of every index, see [`Layout::index`]. Indices of positions that can't happen can be anything
+    pub fn new(layout: Layout, results: impl IntoIterator<Item = Dtm>) -> Result<Self, DtmError> {
+        let data = results.into_iter().map(|dtm| dtm.to_byte().ok_or(DtmError::TooLong(dtm))).collect::<Result<Vec<_>, _>>()?;
+        if data.len() != layout.size() {
+            return Err(DtmError::Length(data.len()));
+        }
+        return Ok(Self { layout, data });
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DtmError> {
+        let bytes = std::fs::read(path).map_err(DtmError::Io)?;
+        return Self::from_bytes(&bytes);
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DtmError> {
+        if bytes.len() < 6 || bytes[0..4] != MAGIC {
+            return Err(DtmError::Magic);
+        }
+        if bytes[4] != VERSION {
+            return Err(DtmError::Version(bytes[4]));
+        }
+        let count = bytes[5] as usize;
+        let pieces = bytes.get(6..6 + count).ok_or(DtmError::Length(bytes.len()))?;
+        let pieces: Vec<_> = pieces.iter().map(|nibble| (nibble & 7, if nibble & 8 != 0 { Side::White } else { Side::Black })).collect();
+        let mut material = Material([[0; 6]; 2]);
+        for (ty, side) in &pieces {
+            let ty = NAME_ORDER.iter().position(|t| *t as u8 == *ty).ok_or(DtmError::Material)?;
+            material.0[(*side == Side::Black) as usize][ty] += 1;
+        }
+        let layout = Layout::from_material(material).ok_or(DtmError::Material)?;
+        if layout.pieces.iter().map(|p| (p.ty as u8, p.side)).collect::<Vec<_>>() != pieces {
+            return Err(DtmError::Material);
+        }
+        let data = bytes[6 + count..].to_vec();
+        if data.len() != layout.size() {
+            return Err(DtmError::Length(bytes.len()));
+        }
+        return Ok(Self { layout, data });
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(self.layout.pieces.len() as u8);
+        bytes.extend(self.layout.pieces.iter().map(Piece::as_nibble));
+        bytes.extend_from_slice(&self.data);
+        return bytes;
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        return std::fs::write(path, self.to_bytes());
+    }
+
+    /// The name the table is stored under, like `KQvK.dtm`
+    pub fn file_name(&self) -> String {
+        return format!("{}.{EXTENSION}", self.layout.name());
+    }
+
+    pub fn layout(&self) -> &Layout {
+        return &self.layout;
+    }
+
+    pub fn get(&self, index: usize) -> Dtm {
+        return Dtm::from_byte(self.data[index]);
+    }
+}
+
+/// A set of DTM tables, which are small enough to all be read up front
+#[derive(Default)]
+pub struct DtmTables {
+    /// By the name of their layout
+    tables: HashMap<String, DtmTable>,
+}
+
+impl DtmTables {
+    /// Reads every table in a list of directories, separated like `PATH`
+    pub fn open(paths: &str) -> Result<Self, DtmError> {
+        let mut tables = Self::default();
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        for dir in paths.split(separator).filter(|dir| !dir.is_empty()) {
+            for entry in std::fs::read_dir(dir).map_err(DtmError::Io)? {
+                let path = entry.map_err(DtmError::Io)?.path();
+                if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                    tables.insert(DtmTable::open(&path)?);
+                }
+            }
+        }
+        return Ok(tables);
+    }
+
+    pub fn insert(&mut self, table: DtmTable) {
+        self.tables.insert(table.layout.name(), table);
+    }
+
+    pub fn get(&self, layout: &Layout) -> Option<&DtmTable> {
+        return self.tables.get(&layout.name());
+    }
+
+    pub fn contains(&self, layout: &Layout) -> bool {
+        return self.tables.contains_key(&layout.name());
+    }
+
+    /// How many tables there are
+    pub fn len(&self) -> usize {
+        return self.tables.len();
+    }
+
+    /// The most pieces, kings included, of any table
+    pub fn max_pieces(&self) -> usize {
+        return self.tables.values().map(|table| table.layout.pieces.len()).max().unwrap_or(0);
+    }
+
+    /// The result of the position, or `None` if it isn't in the tables. Positions with castling rights or an en passant square,
+    /// and other variants, aren't. The 50 move rule isn't taken into account
+    pub fn probe(&self, state: &GameState) -> Option<Dtm> {
+        let castles = state.get_castles();
+        if state.get_variant() != Variant::Standard || state.get_en_passant().is_some()
+            || [Side::White, Side::Black].into_iter().any(|side| castles[side].kingside || castles[side].queenside) {
+            return None;
+        }
+        let material = Material::from_state(state);
+        if material.piece_count() == 2 {
+            return Some(Dtm::Draw);
+        }
+        let layout = Layout::from_material(material)?;
+        let table = self.tables.get(&layout.name())?;
+        let flip = material.name() != layout.name();
+        return Some(table.get(layout.index_of(state, flip)));
+    }
+
+    /// The result of the position like the Syzygy tables give it, but only when the mate comes before the 50 move rule from the
+    /// halfmove clock of the position. A later mate can still be won if a capture or promotion on the way resets the clock, which
+    /// the tables don't know about, so those positions return `None`
+    pub fn probe_wdl(&self, state: &GameState) -> Option<Wdl> {
+        let plies_left = 100u32.saturating_sub(state.get_halfmove_clock());
+        return match self.probe(state)? {
+            Dtm::Win(plies) | Dtm::Loss(plies) if plies > plies_left => None,
+            dtm => Some(dtm.wdl()),
+        };
+    }
+
+    /// The legal moves that mate fastest, or hold out longest when there's no escaping it. Returns `None` if the position, or
+    /// any position a move leads to, isn't in the tables
+    pub fn root_moves(&self, state: &GameState) -> Option<Vec<Move>> {
+        self.probe(state)?;
+        let mut ranked = Vec::new();
+        let mut next = state.clone();
+        for m in state.legal_moves() {
+            let undo = next.play(m);
+            let dtm = self.probe(&next);
+            next.unplay(m, undo);
+            ranked.push((m, dtm?.before_move().rank()));
+        }
+        let best = ranked.iter().map(|(_, rank)| *rank).max()?;
+        return Some(ranked.into_iter().filter(|(_, rank)| *rank == best).map(|(m, _)| m).collect());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{GameState, Move, Side, syzygy::Wdl};
+
+    use super::{Dtm, DtmTable, DtmTables, Layout};
+
+    #[test]
+    fn results_as_bytes() {
+        for dtm in [Dtm::Draw, Dtm::Win(1), Dtm::Win(253), Dtm::Loss(0), Dtm::Loss(254)] {
+            assert_eq!(Dtm::from_byte(dtm.to_byte().unwrap()), dtm);
+        }
+        assert_eq!(Dtm::Win(255).to_byte(), None);
+        assert_eq!(Dtm::Loss(0).before_move(), Dtm::Win(1));
+        assert!(Dtm::Win(3).rank() > Dtm::Win(5).rank());
+        assert!(Dtm::Loss(6).rank() > Dtm::Loss(4).rank());
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(Layout::from_name("KQK").unwrap().name(), "KQvK");
+        // The stronger side is always white
+        assert_eq!(Layout::from_name("KvKR").unwrap().name(), "KRvK");
+        assert_eq!(Layout::from_name("KRvKQ").unwrap().name(), "KQvKR");
+        assert_eq!(Layout::from_name("KQRvKR"), None);
+        assert_eq!(Layout::from_name("KvK"), None);
+        // A double push could be taken en passant
+        assert_eq!(Layout::from_name("KPvKP"), None);
+
+        let layout = Layout::from_name("KPvKR").unwrap();
+        assert_eq!(layout.name(), "KRvKP");
+        let successors: Vec<_> = layout.successors().iter().map(Layout::name).collect();
+        assert_eq!(successors, ["KPvK", "KRvK", "KQvKR", "KRvKR", "KRvKB", "KRvKN"]);
+
+        let layout = Layout::from_name("KRvKN").unwrap();
+        assert_eq!(layout.size(), 2 * 32 * 64 * 64 * 64);
+        // The white king on g1 is mirrored onto b1
+        let index = layout.index(&[6, 63, 60, 0], Side::Black);
+        assert_eq!(layout.decode(index), ([1, 56, 59, 7], Side::Black));
+        assert_eq!(layout.index(&[1, 56, 59, 7], Side::Black), index);
+    }
+
+    #[test]
+    fn probe() {
+        let layout = Layout::from_name("KQvK").unwrap();
+        let state = GameState::from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1");
+        let mut mated = state.clone();
+        mated.play(Move::from_str("g1g8"));
+        let slow = GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 0 1");
+        let slow_loss = GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 b - - 0 1");
+        let [index, mated, slow_index, slow_loss_index] = [&state, &mated, &slow, &slow_loss].map(|state| layout.index_of(state, false));
+        let results = (0..layout.size()).map(|i| match i {
+            _ if i == index => Dtm::Win(1),
+            _ if i == mated => Dtm::Loss(0),
+            _ if i == slow_index => Dtm::Win(41),
+            _ if i == slow_loss_index => Dtm::Loss(40),
+            _ => Dtm::Draw,
+        });
+        let table = DtmTable::new(layout, results).unwrap();
+        let table = DtmTable::from_bytes(&table.to_bytes()).unwrap();
+        assert_eq!(table.file_name(), "KQvK.dtm");
+
+        let mut tables = DtmTables::default();
+        tables.insert(table);
+        assert_eq!(tables.probe(&state), Some(Dtm::Win(1)));
+        // The same position with the colours swapped, and mirrored left to right
+        assert_eq!(tables.probe(&GameState::from_fen("1q6/8/8/8/8/6k1/8/7K b - - 0 1")), Some(Dtm::Win(1)));
+        assert_eq!(tables.probe(&GameState::from_fen("k7/8/1K6/8/8/8/8/6Q1 b - - 0 1")), Some(Dtm::Draw));
+        assert_eq!(tables.probe(&GameState::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1")), None);
+        assert_eq!(tables.probe(&GameState::from_fen("k7/8/1K6/8/8/8/8/8 w - - 0 1")), Some(Dtm::Draw));
+        assert_eq!(tables.probe(&GameState::from_fen("k7/1p6/1K6/8/8/8/6P1/8 w - - 0 1")), None);
+
+        // Only the mate is a win, every other move draws
+        let root = tables.root_moves(&state).unwrap();
+        assert_eq!(root, vec![Move::from_str("g1g8")]);
+
+        // Mates that come after the 50 move rule might not be reached in time, which is left to other tables
+        assert_eq!(tables.probe_wdl(&slow), Some(Wdl::Win));
+        assert_eq!(tables.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 59 80")), Some(Wdl::Win));
+        assert_eq!(tables.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 60 80")), None);
+        assert_eq!(tables.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 b - - 60 80")), Some(Wdl::Loss));
+        assert_eq!(tables.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 b - - 61 80")), None);
+        assert_eq!(tables.probe_wdl(&GameState::from_fen("k7/8/1K6/8/8/8/8/6Q1 b - - 99 80")), Some(Wdl::Draw));
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt::Display;
+
+use super::{FenError, GameState, Move, SanError};
+
+/// A single line of an epd file: a position without clocks, followed by operations like `bm Qg6; id "WAC.001";`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EpdRecord {
+    /// The clocks come from the `hmvc` and `fmvn` operations if they're there
+    pub position: GameState,
+    /// The opcodes with their operands, in the order they appear in the line. String operands are stored without quotes
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EpdError {
+    Fen(FenError),
+    /// An operation that isn't formed like `opcode operand...;`, with the column it starts at
+    Operation(usize),
+    /// A `bm` or `am` move that doesn't fit the position, with the move text and the reason
+    Move(String, SanError),
+}
+
+impl Display for EpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EpdError::Fen(e) => write!(f, "invalid position: {e}"),
+            EpdError::Operation(col) => write!(f, "invalid operation at column {col}"),
+            EpdError::Move(san, e) => write!(f, "can't play \"{san}\": {e}"),
+        }
+    }
+}
+
+impl EpdRecord {
+    /// A record without any operations
+    pub fn new(position: GameState) -> Self {
+        Self { position, operations: Vec::new() }
+    }
+
+    pub fn get_operation(&self, opcode: &str) -> Option<&[String]> {
+        return self.operations.iter().find(|(o, _)| o == opcode).map(|(_, operands)| operands.as_slice());
+    }
+
+    /// Replaces the operands of an opcode, or adds it at the end if it isn't there yet
+    pub fn set_operation(&mut self, opcode: &str, operands: Vec<String>) {
+        match self.operations.iter_mut().find(|(o, _)| o == opcode) {
+            Some(operation) => operation.1 = operands,
+            None => self.operations.push((opcode.to_owned(), operands)),
+        }
+    }
+
+    /// The `id` of the position, which test suites use to name it
+    pub fn id(&self) -> Option<&str> {
+        return self.get_operation("id").and_then(|operands| operands.first()).map(|id| id.as_str());
+    }
+
+    /// The moves from the `bm` operation, any of which solves the position
+    pub fn best_moves(&self) -> Result<Vec<Move>, EpdError> {
+        return self.moves("bm");
+    }
+
+    /// The moves from the `am` operation, none of which should be played
+    pub fn avoid_moves(&self) -> Result<Vec<Move>, EpdError> {
+        return self.moves("am");
+    }
+
+    fn moves(&self, opcode: &str) -> Result<Vec<Move>, EpdError> {
+        return self.get_operation(opcode).unwrap_or_default().iter()
+            .map(|san| self.position.parse_san(san).map_err(|e| EpdError::Move(san.clone(), e)))
+            .collect();
+    }
+
+    /// Parses every line of an epd file, skipping empty lines
+    pub fn parse_all(epd: &str) -> Result<Vec<EpdRecord>, EpdError> {
+        return epd.lines().filter(|line| !line.trim().is_empty()).map(Self::parse).collect();
+    }
+
+    /// Parses a single record. The `bm` and `am` moves are checked against the position
+    pub fn parse(line: &str) -> Result<EpdRecord, EpdError> {
+        // The position is the first four fields, the operations start after them
+        let mut fields_end = 0;
+        for _ in 0..4 {
+            let start = fields_end + line[fields_end..].len() - line[fields_end..].trim_start().len();
+            fields_end = line[start..].find(char::is_whitespace).map_or(line.len(), |len| start + len);
+        }
+        let operations = parse_operations(line, fields_end)?;
+        let clock = |opcode: &str, default: &str| operations.iter()
+            .find(|(o, _)| o == opcode)
+            .and_then(|(_, operands)| operands.first().cloned())
+            .unwrap_or(default.to_owned());
+        let fen = format!("{} {} {}", &line[..fields_end], clock("hmvc", "0"), clock("fmvn", "1"));
+        // The clocks were added to the fen here, so errors in them belong to their operations
+        let column = |opcode: &str| line[fields_end..].find(opcode).map_or(fields_end, |i| fields_end + i);
+        let position = GameState::parse_fen(&fen).map_err(|e| match e {
+            FenError::HalfmoveClock(_) | FenError::TrailingData(_) => EpdError::Operation(column("hmvc")),
+            FenError::FullmoveNumber(_) => EpdError::Operation(column("fmvn")),
+            e => EpdError::Fen(e),
+        })?;
+
+        let record = EpdRecord { position, operations };
+        record.best_moves()?;
+        record.avoid_moves()?;
+        return Ok(record);
+    }
+}
+
+/// Splits `opcode operand operand; opcode;` into its parts, starting at `start`
+fn parse_operations(line: &str, start: usize) -> Result<Vec<(String, Vec<String>)>, EpdError> {
+    let mut operations = Vec::new();
+    let mut chars = line[start..].char_indices().map(|(i, c)| (start + i, c)).peekable();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let Some(&(opcode_start, first)) = chars.peek() else { break; };
+        if !first.is_ascii_alphabetic() {
+            return Err(EpdError::Operation(opcode_start));
+        }
+        let mut opcode = String::new();
+        while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+            opcode.push(c);
+        }
+
+        let mut operands = Vec::new();
+        loop {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+            match chars.next() {
+                Some((_, ';')) => break,
+                // The last semicolon is often left out
+                None => break,
+                Some((_, '"')) => {
+                    let mut operand = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, c)) => operand.push(c),
+                            None => return Err(EpdError::Operation(opcode_start)),
+                        }
+                    }
+                    operands.push(operand);
+                },
+                Some((_, c)) => {
+                    let mut operand = c.to_string();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| !c.is_whitespace() && *c != ';') {
+                        operand.push(c);
+                    }
+                    operands.push(operand);
+                },
+            }
+        }
+        operations.push((opcode, operands));
+    }
+    return Ok(operations);
+}
+
+impl Display for EpdRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fen = self.position.to_fen();
+        let fields: Vec<&str> = fen.split(' ').take(4).collect();
+        write!(f, "{}", fields.join(" "))?;
+        for (opcode, operands) in &self.operations {
+            write!(f, " {opcode}")?;
+            for operand in operands {
+                if operand.is_empty() || operand.contains(|c: char| c.is_whitespace() || c == ';' || c == '"') || is_string_opcode(opcode) {
+                    write!(f, " \"{operand}\"")?;
+                } else {
+                    write!(f, " {operand}")?;
+                }
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+/// Opcodes whose operand is a string, those are always quoted
+fn is_string_opcode(opcode: &str) -> bool {
+    let comment = opcode.len() == 2 && opcode.starts_with('c') && opcode.as_bytes()[1].is_ascii_digit();
+    return opcode == "id" || comment;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{FenError, GameState, Move, SanError};
+
+    use super::{EpdRecord, EpdError};
+
+    #[test]
+    fn parse_test_suite_line() {
+        let record = EpdRecord::parse("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";").unwrap();
+        assert_eq!(record.position, GameState::from_fen("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1"));
+        assert_eq!(record.id(), Some("WAC.001"));
+        assert_eq!(record.best_moves(), Ok(vec![Move::from_str("g3g6")]));
+        assert_eq!(record.avoid_moves(), Ok(vec![]));
+    }
+
+    #[test]
+    fn operations() {
+        let line = "r1bqk1r1/1p1p1n2/p1n2pN1/2p1b2Q/2P1Pp2/1PN5/PB4PP/R4RK1 w q - am Qxh7 Rxf4; ce -31; c0 \"two words; and more\"; noop; hmvc 7; fmvn 21;";
+        let record = EpdRecord::parse(line).unwrap();
+        assert_eq!(record.avoid_moves(), Ok(vec![Move::from_str("h5h7"), Move::from_str("f1f4")]));
+        assert_eq!(record.get_operation("ce"), Some(&["-31".to_owned()][..]));
+        assert_eq!(record.get_operation("c0"), Some(&["two words; and more".to_owned()][..]));
+        assert_eq!(record.get_operation("noop"), Some(&[][..]));
+        assert_eq!(record.get_operation("bm"), None);
+        assert_eq!((record.position.get_halfmove_clock(), record.position.get_fullmove_number()), (7, 21));
+    }
+
+    #[test]
+    fn write() {
+        let lines = [
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";",
+            "r1bqk1r1/1p1p1n2/p1n2pN1/2p1b2Q/2P1Pp2/1PN5/PB4PP/R4RK1 w q - am Qxh7 Rxf4; ce -31; c0 \"two words\"; noop;",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3",
+        ];
+        for line in lines {
+            assert_eq!(EpdRecord::parse(line).unwrap().to_string(), line);
+        }
+
+        let mut record = EpdRecord::new(GameState::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1"));
+        record.set_operation("bm", vec!["O-O".to_owned()]);
+        record.set_operation("id", vec!["castle".to_owned()]);
+        record.set_operation("bm", vec!["Rh8+".to_owned()]);
+        assert_eq!(record.to_string(), "4k3/8/8/8/8/8/8/4K2R w K - bm Rh8+; id \"castle\";");
+        assert_eq!(EpdRecord::parse(&record.to_string()), Ok(record));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(EpdRecord::parse("8/8/8/8/8/8/8/8 w - bm Kg1;"), Err(EpdError::Fen(FenError::EnPassant(20))));
+        assert_eq!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - ;"), Err(EpdError::Operation(26)));
+        assert_eq!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - id \"unterminated;"), Err(EpdError::Operation(26)));
+        assert_eq!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - bm Ke3;"), Err(EpdError::Move("Ke3".to_owned(), SanError::NoMatch)));
+        assert_eq!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - hmvc x;"), Err(EpdError::Operation(26)));
+        assert_eq!(EpdRecord::parse("4k3/8/8/8/8/8/8/4K3 w - - id \"x\"; fmvn -1;"), Err(EpdError::Operation(34)));
+    }
+
+    #[test]
+    fn parse_all() {
+        let records = EpdRecord::parse_all("4k3/8/8/8/8/8/8/4K3 w - - id \"1\";\n\n4k3/8/8/8/8/8/8/4K3 b - - id \"2\";\n").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].id(), Some("2"));
+    }
+}
@@ -1,6 +1,17 @@
 pub mod state;
 pub mod board;
+pub mod bitboard;
 pub mod piece;
+pub mod movegen;
+pub mod san;
+pub mod pgn;
+pub mod epd;
+pub mod polyglot;
+pub mod syzygy;
+pub mod dtm;
+pub mod zobrist;
+pub mod status;
+pub mod variant;
 #[cfg(test)]
 pub mod test;
 
@@ -13,9 +24,13 @@ use std::ascii;
 use ::ascii::ToAsciiChar;
 use bytemuck::{Pod, Zeroable};
 use float_ord::FloatOrd;
-pub use state::GameState;
+pub use state::{GameState, FenError, IllegalMove, Undo};
 pub use piece::{Piece, PieceType, Side};
-pub use board::{Board, GpuBoard, StandardBoard};
+pub use board::{Board, GpuBoard, StandardBoard, MoveRecord};
+pub use bitboard::BitBoard;
+pub use san::SanError;
+pub use status::{GameStatus, DrawReason};
+pub use variant::Variant;
 
 use crate::buffers::BufferData;
 
@@ -109,11 +124,27 @@ impl Display for Move {
 impl Move {
     // Parses long algebraic notation, compliant with uci
     pub fn from_str(str: &str) -> Move {
-        assert!(str.len() <= 5);
-        assert!(str.len() >= 4);
+        return Self::try_from_str(str).unwrap_or_else(|| panic!("Invalid move {str}"));
+    }
+
+    /// Like [`Move::from_str`], but returns `None` instead of panicking on malformed input
+    pub fn try_from_str(str: &str) -> Option<Move> {
         let str: Vec<_> = str.chars().collect();
-        let promote = if str.len() == 5 { Some(PieceType::from_char(str[4]))} else {None};
-        return Move(Location::from_letters(str[0], str[1]), Location::from_letters(str[2], str[3]), promote);
+        if str.len() < 4 || str.len() > 5 {
+            return None;
+        }
+        let valid_square = |x: char, y: char| ('a'..='h').contains(&x) && ('1'..='8').contains(&y);
+        if !valid_square(str[0], str[1]) || !valid_square(str[2], str[3]) {
+            return None;
+        }
+        let promote = match str.get(4) {
+            Some(c) => match PieceType::try_from_char(*c)? {
+                PieceType::Pawn | PieceType::King => return None,
+                ty => Some(ty),
+            },
+            None => None,
+        };
+        return Some(Move(Location::from_letters(str[0], str[1]), Location::from_letters(str[2], str[3]), promote));
     }
 }
 
@@ -0,0 +1,173 @@
+use super::{GameState, Location, Move, Piece, PieceType, Side, Board, bitboard::Squares};
+
+const PROMOTIONS: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Horsy];
+
+impl GameState {
+    /// All moves that the side to move can legally play.
+    /// This is the reference implementation, the search itself generates moves on the gpu.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let side = self.to_move;
+        return pseudo_legal_moves(self).into_iter().filter(|m| {
+            let mut next = self.clone();
+            next.play(*m);
+            next.get_board().is_valid(side)
+        }).collect();
+    }
+}
+
+/// Generates every move that follows the movement rules of the pieces, without looking at checks.
+/// Castling is the exception, because castling out of or through check is checked here.
+fn pseudo_legal_moves(state: &GameState) -> Vec<Move> {
+    let side = state.to_move;
+    let board = state.get_board();
+    let own = board.occupied_by(side);
+    let mut moves = Vec::new();
+
+    for from in Squares(own) {
+        let piece = board.get(from).unwrap();
+        match piece.ty {
+            PieceType::Pawn => pawn_moves(state, from, &mut moves),
+            PieceType::King => {
+                moves.extend(Squares(board.attacks(piece, from) & !own).map(|to| Move(from, to, None)));
+                castle_moves(state, from, &mut moves);
+            },
+            _ => moves.extend(Squares(board.attacks(piece, from) & !own).map(|to| Move(from, to, None))),
+        }
+    }
+
+    return moves;
+}
+
+fn is_opponent(state: &GameState, loc: Location) -> bool {
+    state.get(loc).is_some_and(|p| p.side != state.to_move)
+}
+
+fn pawn_moves(state: &GameState, from: Location, moves: &mut Vec<Move>) {
+    let (dy, start_rank, promote_rank) = match state.to_move {
+        Side::White => (1, 1, 7),
+        Side::Black => (-1, 6, 0),
+    };
+
+    let mut push = |to: Location| {
+        if to.get_y() == promote_rank {
+            for promotion in PROMOTIONS {
+                moves.push(Move(from, to, Some(promotion)));
+            }
+        } else {
+            moves.push(Move(from, to, None));
+        }
+    };
+
+    if let Some(to) = from.try_add(0, dy) && state.get(to).is_none() {
+        push(to);
+        if from.get_y() == start_rank && let Some(to2) = to.try_add(0, dy) && state.get(to2).is_none() {
+            push(to2);
+        }
+    }
+
+    for dx in [-1, 1] {
+        if let Some(to) = from.try_add(dx, dy) {
+            if is_opponent(state, to) || state.get_en_passant() == Some(to) {
+                push(to);
+            }
+        }
+    }
+}
+
+fn castle_moves(state: &GameState, from: Location, moves: &mut Vec<Move>) {
+    let side = state.to_move;
+    let files = state.get_castle_files()[side];
+    if from != Location::new(files.king, side.home_rank()) {
+        return;
+    }
+    let castles = state.get_castles()[side];
+    let rook = Some(Piece::new(side, PieceType::Rook));
+
+    // Checks if the king would be safe on the square, by actually moving it there
+    let safe = |x: u8| {
+        let mut board = state.get_board();
+        board.set(from, None);
+        board.set(from.with_x(x), Some(Piece::new(side, PieceType::King)));
+        board.is_valid(side)
+    };
+
+    let wings = [(castles.kingside, files.kingside, 6, 5), (castles.queenside, files.queenside, 2, 3)];
+    for (allowed, rook_from, king_to, rook_to) in wings {
+        if !allowed || state.get(from.with_x(rook_from)) != rook {
+            continue;
+        }
+        // Everything between where the king and rook start and end has to be empty, apart from the king and rook themselves
+        let left = files.king.min(rook_from).min(king_to).min(rook_to);
+        let right = files.king.max(rook_from).max(king_to).max(rook_to);
+        let empty = (left..=right).all(|x| x == files.king || x == rook_from || state.get(from.with_x(x)).is_none());
+        // The king may not castle out of or through check, whether it ends up in check is checked together with the other moves
+        let path_safe = (files.king.min(king_to)..=files.king.max(king_to)).all(safe);
+        if empty && path_safe {
+            let to = if state.castles_onto_rook(side) { rook_from } else { king_to };
+            moves.push(Move(from, from.with_x(to), None));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{GameState, Move};
+
+    fn assert_count(fen: &str, expected: usize) {
+        let moves = GameState::from_fen(fen).legal_moves();
+        assert_eq!(moves.len(), expected, "{fen} has moves {moves:?}");
+    }
+
+    #[test]
+    fn startpos() {
+        assert_count("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 20);
+    }
+
+    #[test]
+    fn known_positions() {
+        assert_count("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 48);
+        assert_count("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 14);
+        assert_count("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 6);
+        assert_count("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 44);
+        assert_count("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 46);
+    }
+
+    #[test]
+    fn castling() {
+        let moves = GameState::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").legal_moves();
+        assert!(moves.contains(&Move::from_str("e1g1")));
+        assert!(moves.contains(&Move::from_str("e1c1")));
+
+        // Not through check
+        let moves = GameState::from_fen("r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1").legal_moves();
+        assert!(!moves.contains(&Move::from_str("e1g1")));
+        assert!(moves.contains(&Move::from_str("e1c1")));
+
+        // Not out of check
+        let moves = GameState::from_fen("r3k2r/8/8/8/1b6/8/8/R3K2R w KQkq - 0 1").legal_moves();
+        assert!(!moves.contains(&Move::from_str("e1g1")));
+        assert!(!moves.contains(&Move::from_str("e1c1")));
+    }
+
+    #[test]
+    fn en_passant() {
+        let moves = GameState::from_fen("k7/8/8/2pP4/8/8/8/K7 w - c6 0 1").legal_moves();
+        assert!(moves.contains(&Move::from_str("d5c6")));
+        // En passant would expose the king
+        let moves = GameState::from_fen("8/8/8/K1pP3q/8/8/8/7k w - c6 0 1").legal_moves();
+        assert!(!moves.contains(&Move::from_str("d5c6")));
+    }
+
+    #[test]
+    fn promotions() {
+        let moves = GameState::from_fen("1n5k/P7/8/8/8/8/8/K7 w - - 0 1").legal_moves();
+        let promotions = moves.iter().filter(|m| m.2.is_some()).count();
+        assert_eq!(promotions, 8);
+    }
+
+    #[test]
+    fn evasion() {
+        // Double check, only the king can move
+        assert_count("4k3/8/8/8/8/5n2/8/r3K3 w - - 0 1", 2);
+    }
+}
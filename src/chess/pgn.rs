@@ -0,0 +1,558 @@
+use std::fmt::Display;
+
+use super::{EvalScore, FenError, GameState, Move, SanError, Side};
+
+const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+/// Lines of movetext are wrapped before they get longer than this
+const LINE_WIDTH: usize = 80;
+
+/// A single game from a pgn file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PgnGame {
+    /// The tag pairs, in the order they appear in the file
+    pub tags: Vec<(String, String)>,
+    /// The position before the first move, taken from the `FEN` tag if there is one
+    pub start: GameState,
+    pub moves: Vec<PgnMove>,
+    pub result: PgnResult,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PgnMove {
+    pub m: Move,
+    /// The comment after the move, without the eval annotation
+    pub comment: Option<String>,
+    /// The `[%eval ...]` annotation in the comment, from white's perspective
+    pub eval: Option<EvalScore>,
+    /// Lines that could have been played instead of this move
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PgnResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+    /// The game is still going, or the result is unknown
+    Unknown,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PgnError {
+    /// A tag pair that isn't formed like `[Name "value"]`, with the line it's on
+    Tag(usize),
+    Fen(FenError),
+    /// A move that couldn't be played, with the move text and the reason
+    Move(String, SanError),
+    /// A token that isn't allowed where it was found, with the line it's on
+    Unexpected(String, usize),
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgnError::Tag(line) => write!(f, "invalid tag pair on line {line}"),
+            PgnError::Fen(e) => write!(f, "invalid FEN tag: {e}"),
+            PgnError::Move(san, e) => write!(f, "can't play \"{san}\": {e}"),
+            PgnError::Unexpected(token, line) => write!(f, "unexpected \"{token}\" on line {line}"),
+        }
+    }
+}
+
+impl PgnResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PgnResult::WhiteWins => "1-0",
+            PgnResult::BlackWins => "0-1",
+            PgnResult::Draw => "1/2-1/2",
+            PgnResult::Unknown => "*",
+        }
+    }
+
+    fn from_str(str: &str) -> Option<Self> {
+        match str {
+            "1-0" => Some(PgnResult::WhiteWins),
+            "0-1" => Some(PgnResult::BlackWins),
+            "1/2-1/2" => Some(PgnResult::Draw),
+            "*" => Some(PgnResult::Unknown),
+            _ => None,
+        }
+    }
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> Self {
+        Self { m, comment: None, eval: None, variations: Vec::new() }
+    }
+}
+
+impl PgnGame {
+    /// An empty game from the given position, with the seven required tags filled in with placeholders.
+    /// The `SetUp` and `FEN` tags are added if it isn't the standard starting position.
+    pub fn new(start: GameState) -> Self {
+        let mut tags: Vec<(String, String)> = [("Event", "?"), ("Site", "?"), ("Date", "????.??.??"), ("Round", "?"), ("White", "?"), ("Black", "?"), ("Result", "*")]
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        let fen = start.to_fen();
+        if fen != STARTPOS {
+            tags.push(("SetUp".to_owned(), "1".to_owned()));
+            tags.push(("FEN".to_owned(), fen));
+        }
+        return Self { tags, start, moves: Vec::new(), result: PgnResult::Unknown };
+    }
+
+    pub fn get_tag(&self, name: &str) -> Option<&str> {
+        return self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+    }
+
+    /// Replaces the value of a tag, or adds it at the end if it isn't there yet
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_owned(),
+            None => self.tags.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// Appends a move to the main line
+    pub fn push(&mut self, m: Move) {
+        self.moves.push(PgnMove::new(m));
+    }
+
+    /// Every position of the main line, starting with the start position and ending with the position after the last move
+    pub fn positions(&self) -> Vec<GameState> {
+        let mut state = self.start.clone();
+        let mut positions = vec![state.clone()];
+        for m in &self.moves {
+            state.play(m.m);
+            positions.push(state.clone());
+        }
+        return positions;
+    }
+
+    /// Parses every game in a pgn file
+    pub fn parse_all(pgn: &str) -> Result<Vec<PgnGame>, PgnError> {
+        let mut tokens = tokenize(pgn)?.into_iter().peekable();
+        let mut games = Vec::new();
+        loop {
+            // Comments between games don't belong to either of them
+            while tokens.next_if(|(token, _)| matches!(token, Token::Comment(_))).is_some() {}
+            if tokens.peek().is_none() {
+                break;
+            }
+            games.push(parse_game(&mut tokens)?);
+        }
+        return Ok(games);
+    }
+
+    /// Parses a pgn file that should contain exactly one game
+    pub fn parse(pgn: &str) -> Result<PgnGame, PgnError> {
+        let mut tokens = tokenize(pgn)?.into_iter().peekable();
+        let game = parse_game(&mut tokens)?;
+        if let Some((token, line)) = tokens.next() {
+            return Err(PgnError::Unexpected(token.to_string(), line));
+        }
+        return Ok(game);
+    }
+}
+
+impl Display for PgnGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+
+        let mut words = Vec::new();
+        write_line(&self.start, &self.moves, &mut words);
+        words.push(self.result.as_str().to_owned());
+
+        // Words can contain spaces themselves, those are allowed to wrap as well
+        let mut line = String::new();
+        for word in words.iter().flat_map(|w| w.split(' ')) {
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH {
+                writeln!(f, "{line}")?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        return writeln!(f, "{line}");
+    }
+}
+
+/// Writes the movetext of a line as separate words, so they can be wrapped
+fn write_line(start: &GameState, moves: &[PgnMove], words: &mut Vec<String>) {
+    let mut state = start.clone();
+    // Black moves need their number repeated after anything that interrupts the move list
+    let mut interrupted = true;
+    for m in moves {
+        let number = state.get_fullmove_number();
+        let san = state.to_san(m.m);
+        match state.to_move {
+            Side::White => words.push(format!("{number}. {san}")),
+            Side::Black if interrupted => words.push(format!("{number}... {san}")),
+            Side::Black => words.push(san),
+        }
+        interrupted = false;
+
+        if m.comment.is_some() || m.eval.is_some() {
+            let mut comment = Vec::new();
+            if let Some(eval) = m.eval {
+                comment.push(format!("[%eval {:.2}]", eval.to_centipawn() as f64 / 100.0));
+            }
+            if let Some(text) = &m.comment {
+                comment.push(text.clone());
+            }
+            words.push(format!("{{{}}}", comment.join(" ")));
+            interrupted = true;
+        }
+
+        for variation in &m.variations {
+            let mut variation_words = Vec::new();
+            write_line(&state, variation, &mut variation_words);
+            if let Some(first) = variation_words.first_mut() {
+                first.insert(0, '(');
+            }
+            if let Some(last) = variation_words.last_mut() {
+                last.push(')');
+            }
+            words.append(&mut variation_words);
+            interrupted = true;
+        }
+
+        state.play(m.m);
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Open,
+    Close,
+    Result(PgnResult),
+    San(String),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Tag(name, value) => write!(f, "[{name} \"{value}\"]"),
+            Token::Comment(text) => write!(f, "{{{text}}}"),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Result(result) => write!(f, "{}", result.as_str()),
+            Token::San(san) => write!(f, "{san}"),
+        }
+    }
+}
+
+/// Splits a pgn file into tokens, each with the (1-based) line it starts on.
+/// Move numbers, NAGs and escaped lines are dropped here.
+fn tokenize(pgn: &str) -> Result<Vec<(Token, usize)>, PgnError> {
+    let chars: Vec<char> = pgn.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+        match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+                // Lines starting with % are escaped from the pgn syntax
+                if chars.get(i) == Some(&'%') {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+            },
+            '%' if i == 0 => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            _ if c.is_whitespace() || c == '.' => i += 1,
+            '[' => {
+                let end = (i..chars.len()).find(|j| chars[*j] == '\n').unwrap_or(chars.len());
+                let tag: String = chars[i..end].iter().collect();
+                let (name, value, len) = parse_tag(&tag).ok_or(PgnError::Tag(line))?;
+                tokens.push((Token::Tag(name, value), start_line));
+                i += len;
+            },
+            '{' => {
+                let end = (i..chars.len()).find(|j| chars[*j] == '}').ok_or(PgnError::Unexpected("{".to_owned(), line))?;
+                let text: String = chars[i + 1..end].iter().collect();
+                line += text.matches('\n').count();
+                tokens.push((Token::Comment(text.split_whitespace().collect::<Vec<_>>().join(" ")), start_line));
+                i = end + 1;
+            },
+            ';' => {
+                let end = (i..chars.len()).find(|j| chars[*j] == '\n').unwrap_or(chars.len());
+                let text: String = chars[i + 1..end].iter().collect();
+                tokens.push((Token::Comment(text.trim().to_owned()), start_line));
+                i = end;
+            },
+            '(' => {
+                tokens.push((Token::Open, start_line));
+                i += 1;
+            },
+            ')' => {
+                tokens.push((Token::Close, start_line));
+                i += 1;
+            },
+            '$' => {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            },
+            '*' => {
+                tokens.push((Token::Result(PgnResult::Unknown), start_line));
+                i += 1;
+            },
+            _ if c.is_ascii_alphanumeric() => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "+#=:-/_!?".contains(chars[i])) {
+                    i += 1;
+                }
+                let symbol: String = chars[start..i].iter().collect();
+                if let Some(result) = PgnResult::from_str(&symbol) {
+                    tokens.push((Token::Result(result), start_line));
+                } else if !symbol.chars().all(|c| c.is_ascii_digit()) {
+                    tokens.push((Token::San(symbol), start_line));
+                }
+            },
+            _ => return Err(PgnError::Unexpected(c.to_string(), line)),
+        }
+    }
+    return Ok(tokens);
+}
+
+/// Parses a `[Name "value"]` tag at the start of the string, returning the amount of chars it took up
+fn parse_tag(str: &str) -> Option<(String, String, usize)> {
+    let mut chars = str.chars().enumerate().skip(1).peekable();
+    let mut name = String::new();
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    if name.is_empty() || chars.next()?.1 != '"' {
+        return None;
+    }
+
+    let mut value = String::new();
+    loop {
+        match chars.next()?.1 {
+            '\\' => value.push(chars.next()?.1),
+            '"' => break,
+            c => value.push(c),
+        }
+    }
+    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    let (i, c) = chars.next()?;
+    if c != ']' {
+        return None;
+    }
+    return Some((name, value, i + 1));
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<(Token, usize)>>;
+
+fn parse_game(tokens: &mut Tokens) -> Result<PgnGame, PgnError> {
+    let mut tags = Vec::new();
+    while let Some((Token::Tag(name, value), _)) = tokens.peek() {
+        tags.push((name.clone(), value.clone()));
+        tokens.next();
+    }
+
+    let start = match tags.iter().find(|(name, _)| name == "FEN") {
+        Some((_, fen)) => GameState::parse_fen(fen).map_err(PgnError::Fen)?,
+        None => GameState::from_fen(STARTPOS),
+    };
+    let moves = parse_line(tokens, start.clone(), false)?;
+
+    // A missing result at the end of the file is allowed
+    let result = match tokens.next() {
+        Some((Token::Result(result), _)) => result,
+        Some((token, line)) => return Err(PgnError::Unexpected(token.to_string(), line)),
+        None => PgnResult::Unknown,
+    };
+    return Ok(PgnGame { tags, start, moves, result });
+}
+
+/// Parses moves until the end of the line, which is a result token for the main line and a closing parenthesis for variations.
+/// The closing parenthesis is consumed, the result token isn't.
+fn parse_line(tokens: &mut Tokens, mut state: GameState, variation: bool) -> Result<Vec<PgnMove>, PgnError> {
+    let mut moves: Vec<PgnMove> = Vec::new();
+    // The position before the last move, which is where its variations start from
+    let mut previous = state.clone();
+
+    loop {
+        let Some((token, line)) = tokens.peek().cloned() else {
+            if variation {
+                return Err(PgnError::Unexpected("end of file".to_owned(), 0));
+            }
+            return Ok(moves);
+        };
+        match token {
+            Token::San(san) => {
+                let m = state.parse_san(&san).map_err(|e| PgnError::Move(san.clone(), e))?;
+                previous = state.clone();
+                state.play(m);
+                moves.push(PgnMove::new(m));
+            },
+            Token::Comment(text) => {
+                // Comments before the first move have nothing to be attached to
+                if let Some(last) = moves.last_mut() {
+                    let (eval, text) = split_eval(&text);
+                    last.eval = last.eval.or(eval);
+                    if !text.is_empty() {
+                        last.comment = Some(match last.comment.take() {
+                            Some(prev) => format!("{prev} {text}"),
+                            None => text,
+                        });
+                    }
+                }
+            },
+            Token::Open => {
+                let Some(last) = moves.last_mut() else { return Err(PgnError::Unexpected(token.to_string(), line)); };
+                tokens.next();
+                let alternative = parse_line(tokens, previous.clone(), true)?;
+                last.variations.push(alternative);
+                continue;
+            },
+            Token::Close if variation => {
+                tokens.next();
+                return Ok(moves);
+            },
+            Token::Result(_) | Token::Tag(_, _) if !variation => return Ok(moves),
+            _ => return Err(PgnError::Unexpected(token.to_string(), line)),
+        }
+        tokens.next();
+    }
+}
+
+/// Takes the `[%eval 0.35]` annotation out of a comment
+fn split_eval(comment: &str) -> (Option<EvalScore>, String) {
+    let Some(start) = comment.find("[%eval ") else { return (None, comment.to_owned()); };
+    let Some(len) = comment[start..].find(']') else { return (None, comment.to_owned()); };
+    let value = &comment[start + "[%eval ".len()..start + len];
+    // Mate scores (#3) aren't representable as centipawns, so they stay part of the comment
+    let Ok(pawns) = value.trim().parse::<f64>() else { return (None, comment.to_owned()); };
+    let eval = Some(EvalScore::from((pawns * 100.0).round() as i32));
+    let rest = format!("{} {}", &comment[..start], &comment[start + len + 1..]);
+    return (eval, rest.split_whitespace().collect::<Vec<_>>().join(" "));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{EvalScore, GameState, Move, SanError};
+
+    use super::{PgnGame, PgnResult, PgnError, PgnMove};
+
+    const GAME: &str = r#"[Event "F/S Return Match"]
+[Site "Belgrade, Serbia JUG"]
+[Date "1992.11.04"]
+[Round "29"]
+[White "Fischer, Robert J."]
+[Black "Spassky, Boris V."]
+[Result "1/2-1/2"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 {This opening is called the Ruy Lopez.} 3... a6
+4. Ba4 Nf6 5. O-O Be7 6. Re1 b5 7. Bb3 d6 8. c3 O-O 9. h3 Nb8 10. d4 Nbd7
+11. c4 c6 12. cxb5 axb5 13. Nc3 Bb7 14. Bg5 b4 15. Nb1 h6 16. Bh4 c5 17. dxe5
+Nxe4 18. Bxe7 Qxe7 19. exd6 Qf6 20. Nbd2 Nxd6 21. Nc4 Nxc4 22. Bxc4 Nb6
+23. Ne5 Rae8 24. Bxf7+ Rxf7 25. Nxf7 Rxe1+ 26. Qxe1 Kxf7 27. Qe3 Qg5 28. Qxg5
+hxg5 29. b3 Ke6 30. a3 Kd6 31. axb4 cxb4 32. Ra5 Nd5 33. f3 Bc8 34. Kf2 Bf5
+35. Ra7 g6 36. Ra6+ Kc5 37. Ke1 Nf4 38. g3 Nxh3 39. Kd2 Kb5 40. Rd6 Kc5 41. Ra6
+Nf2 42. g4 Bd3 43. Re6 1/2-1/2
+"#;
+
+    #[test]
+    fn parse_game() {
+        let game = PgnGame::parse(GAME).unwrap();
+        assert_eq!(game.get_tag("White"), Some("Fischer, Robert J."));
+        assert_eq!(game.tags.len(), 7);
+        assert_eq!(game.moves.len(), 85);
+        assert_eq!(game.result, PgnResult::Draw);
+        assert_eq!(game.moves[4].comment.as_deref(), Some("This opening is called the Ruy Lopez."));
+        assert_eq!(game.moves[8].m, Move::from_str("e1g1"));
+
+        let positions = game.positions();
+        assert_eq!(positions.len(), 86);
+        assert_eq!(positions.last().unwrap().to_fen(), "8/8/4R1p1/2k3p1/1p4P1/1P1b1P2/3K1n2/8 b - - 2 43");
+    }
+
+    #[test]
+    fn round_trip() {
+        let game = PgnGame::parse(GAME).unwrap();
+        let written = game.to_string();
+        assert!(written.lines().all(|l| l.len() <= 80));
+        assert!(written.contains("{This opening is called the Ruy Lopez.} 3... a6"));
+        assert_eq!(PgnGame::parse(&written).unwrap(), game);
+    }
+
+    #[test]
+    fn variations_and_evals() {
+        let pgn = "1. e4 {[%eval 0.3]} e5 (1... c5 2. Nf3 (2. c3) d6 {Najdorf coming [%eval foo]}) (1... e6) 2. Nf3 $1 {[%eval -1.25] a blunder?} *";
+        let game = PgnGame::parse(pgn).unwrap();
+        assert_eq!(game.result, PgnResult::Unknown);
+        assert_eq!(game.moves.len(), 3);
+        assert_eq!(game.moves[0].eval, Some(EvalScore::from(30)));
+        assert_eq!(game.moves[2].eval, Some(EvalScore::from(-125)));
+        assert_eq!(game.moves[2].comment.as_deref(), Some("a blunder?"));
+
+        let variations = &game.moves[1].variations;
+        assert_eq!(variations.len(), 2);
+        assert_eq!(variations[0].len(), 3);
+        assert_eq!(variations[0][0].m, Move::from_str("c7c5"));
+        assert_eq!(variations[0][1].variations, vec![vec![PgnMove::new(Move::from_str("c2c3"))]]);
+        assert_eq!(variations[0][2].comment.as_deref(), Some("Najdorf coming [%eval foo]"));
+        assert_eq!(variations[1][0].m, Move::from_str("e7e6"));
+
+        assert_eq!(game.to_string(), "\n1. e4 {[%eval 0.30]} 1... e5 (1... c5 2. Nf3 (2. c3) 2... d6 {Najdorf coming\n[%eval foo]}) (1... e6) 2. Nf3 {[%eval -1.25] a blunder?} *\n");
+        assert_eq!(PgnGame::parse(&game.to_string()).unwrap(), game);
+    }
+
+    #[test]
+    fn multiple_games() {
+        let pgn = format!("{GAME}\n[Event \"Second\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n\n1... Kd7 2. e4 0-1\n\n; a comment without a game\n%escaped\n");
+        let games = PgnGame::parse_all(&pgn).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[1].get_tag("Event"), Some("Second"));
+        assert_eq!(games[1].result, PgnResult::BlackWins);
+        assert_eq!(games[1].positions().last().unwrap().to_fen(), "8/3k4/8/8/4P3/8/8/4K3 b - e3 0 2");
+    }
+
+    #[test]
+    fn write_new_game() {
+        let mut game = PgnGame::new(GameState::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1"));
+        game.set_tag("White", "apophthegm \"dev\"");
+        game.push(Move::from_str("e8d7"));
+        game.push(Move::from_str("e2e4"));
+        game.result = PgnResult::Draw;
+        game.set_tag("Result", game.result.as_str());
+        let written = game.to_string();
+        assert!(written.contains("[White \"apophthegm \\\"dev\\\"\"]\n"));
+        assert!(written.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n"));
+        assert!(written.ends_with("\n\n1... Kd7 2. e4 1/2-1/2\n"));
+        assert_eq!(PgnGame::parse(&written).unwrap(), game);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(PgnGame::parse("[Event \"x]\n1. e4 *"), Err(PgnError::Tag(1)));
+        assert_eq!(PgnGame::parse("1. e4 e4 *"), Err(PgnError::Move("e4".to_owned(), SanError::NoMatch)));
+        assert_eq!(PgnGame::parse("1. e4 (1. d4 *"), Err(PgnError::Unexpected("*".to_owned(), 1)));
+        assert_eq!(PgnGame::parse("1. e4 *\n)"), Err(PgnError::Unexpected(")".to_owned(), 2)));
+        assert_eq!(PgnGame::parse("(1. e4) *"), Err(PgnError::Unexpected("(".to_owned(), 1)));
+        assert!(matches!(PgnGame::parse("[FEN \"8/8\"]\n*"), Err(PgnError::Fen(_))));
+    }
+}
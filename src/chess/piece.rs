@@ -14,9 +14,13 @@ impl Piece {
     }
 
     pub fn from_fen_char(char: char) -> Self {
+        return Self::try_from_fen_char(char).unwrap_or_else(|| panic!("Invalid char {}", char));
+    }
+
+    pub fn try_from_fen_char(char: char) -> Option<Self> {
         let side = if char.is_ascii_uppercase() { Side::White } else { Side::Black };
-        let piece = PieceType::from_char(char);
-        return Piece::new(side, piece);
+        let piece = PieceType::try_from_char(char)?;
+        return Some(Piece::new(side, piece));
     }
 
     pub fn as_nibble(&self) -> u8 {
@@ -62,6 +66,14 @@ impl Side {
             Side::White => Side::Black,
         };
     }
+
+    /// The rank the king and rooks start on
+    pub fn home_rank(&self) -> u8 {
+        return match self {
+            Side::Black => 7,
+            Side::White => 0,
+        };
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -114,14 +126,18 @@ impl PieceType {
     }
 
     pub fn from_char(char: char) -> Self {
+        return Self::try_from_char(char).unwrap_or_else(|| panic!("Invalid char {}", char));
+    }
+
+    pub fn try_from_char(char: char) -> Option<Self> {
         match char.to_ascii_uppercase() {
-            'P' => PieceType::Pawn,
-            'N' => PieceType::Horsy,
-            'B' => PieceType::Bishop,
-            'R' => PieceType::Rook,
-            'Q' => PieceType::Queen,
-            'K' => PieceType::King,
-            _ => panic!("Invalid char {}", char),
+            'P' => Some(PieceType::Pawn),
+            'N' => Some(PieceType::Horsy),
+            'B' => Some(PieceType::Bishop),
+            'R' => Some(PieceType::Rook),
+            'Q' => Some(PieceType::Queen),
+            'K' => Some(PieceType::King),
+            _ => None,
         }
     }
 }
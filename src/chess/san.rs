@@ -0,0 +1,238 @@
+use std::fmt::Display;
+
+use super::{Board, GameState, Location, Move, PieceType, state::Wing};
+
+/// Describes why a move in standard algebraic notation couldn't be parsed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SanError {
+    /// The text isn't valid notation
+    Syntax,
+    /// The notation is valid, but no legal move matches it
+    NoMatch,
+    /// More than one legal move matches, the notation needs more disambiguation
+    Ambiguous,
+}
+
+impl Display for SanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SanError::Syntax => write!(f, "invalid notation"),
+            SanError::NoMatch => write!(f, "no legal move matches"),
+            SanError::Ambiguous => write!(f, "more than one legal move matches"),
+        }
+    }
+}
+
+impl GameState {
+    /// Whether the king of the side to move is attacked
+    pub fn in_check(&self) -> bool {
+        return self.get_board().in_check(self.to_move);
+    }
+
+    /// Formats a legal move in standard algebraic notation, like `Nbd7`, `exd5`, `O-O` or `e8=Q+`
+    pub fn to_san(&self, m: Move) -> String {
+        let piece = self.get(m.0).expect("No piece to move");
+        let mut san = String::new();
+
+        if let Some(wing) = self.castle_wing(m) {
+            san.push_str(match wing {
+                Wing::Kingside => "O-O",
+                Wing::Queenside => "O-O-O",
+            });
+        } else {
+            let capture = self.get(m.1).is_some() || (piece.ty == PieceType::Pawn && m.0.get_x() != m.1.get_x());
+            if piece.ty == PieceType::Pawn {
+                if capture {
+                    san.push(m.0.x_as_char().to_char());
+                }
+            } else {
+                san.push(piece.ty.to_char());
+                san.push_str(&self.disambiguation(m, piece.ty));
+            }
+            if capture {
+                san.push('x');
+            }
+            san.push_str(&m.1.to_string());
+            if let Some(promotion) = m.2 {
+                san.push('=');
+                san.push(promotion.to_char());
+            }
+        }
+
+        let mut next = self.clone();
+        next.play(m);
+        if next.in_check() {
+            if next.legal_moves().is_empty() {
+                san.push('#');
+            } else {
+                san.push('+');
+            }
+        }
+        return san;
+    }
+
+    /// The part of the origin square that's needed to tell `m` apart from other moves of the same type of piece
+    fn disambiguation(&self, m: Move, ty: PieceType) -> String {
+        let others: Vec<Location> = self.legal_moves().into_iter()
+            .filter(|o| o.1 == m.1 && o.0 != m.0 && self.get(o.0).is_some_and(|p| p.ty == ty))
+            .map(|o| o.0)
+            .collect();
+
+        if others.is_empty() {
+            return String::new();
+        }
+        let file = m.0.x_as_char().to_char();
+        let rank = (b'1' + m.0.get_y()) as char;
+        if others.iter().all(|o| o.get_x() != m.0.get_x()) {
+            return file.to_string();
+        }
+        if others.iter().all(|o| o.get_y() != m.0.get_y()) {
+            return rank.to_string();
+        }
+        return format!("{file}{rank}");
+    }
+
+    /// Parses a move in standard algebraic notation, relative to this position.
+    /// Check and annotation suffixes are accepted but not verified, and `0-0` is accepted for castling.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal = self.legal_moves();
+
+        if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+            let wing = if san.len() == 3 { Wing::Kingside } else { Wing::Queenside };
+            return legal.into_iter().find(|m| self.castle_wing(*m) == Some(wing)).ok_or(SanError::NoMatch);
+        }
+
+        let mut chars: Vec<char> = san.chars().collect();
+
+        let ty = match chars.first() {
+            Some(c) if c.is_ascii_uppercase() => {
+                let ty = PieceType::try_from_char(*c).ok_or(SanError::Syntax)?;
+                chars.remove(0);
+                ty
+            },
+            _ => PieceType::Pawn,
+        };
+
+        let mut promotion = None;
+        if let Some(c) = chars.last() && c.is_ascii_uppercase() {
+            promotion = Some(PieceType::try_from_char(*c).ok_or(SanError::Syntax)?);
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+
+        if chars.len() < 2 {
+            return Err(SanError::Syntax);
+        }
+        let to = parse_square(chars[chars.len() - 2], chars[chars.len() - 1]).ok_or(SanError::Syntax)?;
+        chars.truncate(chars.len() - 2);
+        if chars.last() == Some(&'x') {
+            chars.pop();
+        }
+
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match c {
+                'a'..='h' if from_file.is_none() && from_rank.is_none() => from_file = Some(c as u8 - b'a'),
+                '1'..='8' if from_rank.is_none() => from_rank = Some(c as u8 - b'1'),
+                _ => return Err(SanError::Syntax),
+            }
+        }
+
+        let mut matches = legal.into_iter().filter(|m| {
+            m.1 == to
+                && m.2 == promotion
+                && self.get(m.0).is_some_and(|p| p.ty == ty)
+                && from_file.map_or(true, |x| m.0.get_x() == x)
+                && from_rank.map_or(true, |y| m.0.get_y() == y)
+        });
+        let Some(m) = matches.next() else { return Err(SanError::NoMatch); };
+        if matches.next().is_some() {
+            return Err(SanError::Ambiguous);
+        }
+        return Ok(m);
+    }
+}
+
+fn parse_square(x: char, y: char) -> Option<Location> {
+    if !('a'..='h').contains(&x) || !('1'..='8').contains(&y) {
+        return None;
+    }
+    return Some(Location::new(x as u8 - b'a', y as u8 - b'1'));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{GameState, Move};
+
+    use super::SanError;
+
+    fn assert_san(fen: &str, uci: &str, san: &str) {
+        let state = GameState::from_fen(fen);
+        let m = Move::from_str(uci);
+        assert_eq!(state.to_san(m), san, "{uci} in {fen}");
+        assert_eq!(state.parse_san(san), Ok(m), "{san} in {fen}");
+    }
+
+    #[test]
+    fn simple_moves() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_san(start, "e2e4", "e4");
+        assert_san(start, "g1f3", "Nf3");
+        assert_san("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2", "e4d5", "exd5");
+        assert_san("k7/8/8/2pP4/8/8/8/K7 w - c6 0 1", "d5c6", "dxc6");
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_san(fen, "e1g1", "O-O");
+        assert_san(fen, "e1c1", "O-O-O");
+        assert_eq!(GameState::from_fen(fen).parse_san("0-0-0"), Ok(Move::from_str("e1c1")));
+
+        // In Chess960 the king moves onto the rook, here it already stands where it ends up
+        let fen = "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1";
+        assert_san(fen, "g1h1", "O-O");
+        assert_san(fen, "g1b1", "O-O-O");
+    }
+
+    #[test]
+    fn promotion_and_check() {
+        assert_san("3k4/4P3/8/8/8/8/8/K7 w - - 0 1", "e7e8Q", "e8=Q+");
+        assert_san("3k4/4P3/8/8/8/8/8/K7 w - - 0 1", "e7e8N", "e8=N");
+        assert_san("3k4/8/8/8/8/8/4p3/K7 b - - 0 1", "e2e1R", "e1=R+");
+    }
+
+    #[test]
+    fn mate() {
+        assert_san("6k1/5ppp/8/8/8/8/8/3RR1K1 w - - 0 1", "d1d8", "Rd8#");
+        // Both rooks can take on d1
+        assert_san("k2r4/8/8/8/8/8/5PPP/r2N2K1 b - - 0 1", "a1d1", "Raxd1#");
+        assert_san("k2r4/8/8/8/8/8/5PPP/r2N2K1 b - - 0 1", "d8d1", "Rdxd1#");
+        assert_san("6k1/5ppp/8/8/8/8/5PPP/R2r1RK1 w - - 0 1", "a1d1", "Raxd1");
+    }
+
+    #[test]
+    fn disambiguation() {
+        // Knights on b1 and f3 can both reach d2
+        assert_san("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1", "b1d2", "Nbd2");
+        // Rooks on the same file
+        assert_san("4k3/R7/8/8/8/8/8/R3K3 w - - 0 1", "a1a4", "R1a4");
+        // Queens that need both file and rank
+        assert_san("1k6/8/8/8/4Q2Q/8/8/K6Q w - - 0 1", "h4e1", "Qh4e1");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let state = GameState::from_fen("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1");
+        assert_eq!(state.parse_san("Nd2"), Err(SanError::Ambiguous));
+        assert_eq!(state.parse_san("Nd5"), Err(SanError::NoMatch));
+        assert_eq!(state.parse_san("Xd2"), Err(SanError::Syntax));
+        assert_eq!(state.parse_san("N"), Err(SanError::Syntax));
+        assert_eq!(state.parse_san("Nj2"), Err(SanError::Syntax));
+        assert_eq!(state.parse_san("Nfd2+"), Ok(Move::from_str("f3d2")));
+    }
+}
@@ -1,125 +1,572 @@
 
+use std::fmt::Display;
+
 use enum_map::{EnumMap, enum_map};
 use crate::chess::board::Board;
 
-use super::{Location, board::StandardBoard, Piece, Side, Move, PieceType};
+use super::{Location, board, BitBoard, Piece, Side, Move, PieceType, GpuBoard, zobrist, Variant};
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, Debug)]
 pub struct GameState {
-    pieces: StandardBoard,
+    pieces: BitBoard,
     pub to_move: Side,
     en_passant_sq: Option<Location>,
-    castles: EnumMap<Side, Castles>
+    castles: EnumMap<Side, Castles>,
+    /// Where the king and rooks started, only different from the standard files in Chess960
+    castle_files: EnumMap<Side, CastleFiles>,
+    /// Whether castling is written as the king moving onto its own rook, and Shredder-FEN is used for the castling rights.
+    /// Set by Shredder-FEN and the `UCI_Chess960` option
+    chess960: bool,
+    variant: Variant,
+    /// How many times each side has given check, only counted in Three-check
+    checks: EnumMap<Side, u8>,
+    /// The amount of halfmoves since the last capture or pawn move
+    halfmove_clock: u32,
+    /// Starts at 1, and is incremented after black moves
+    fullmove_number: u32,
+    /// The zobrist key of the position, kept up to date by `set` and `play`
+    hash: u64,
+    /// The hashes of the positions since the last capture or pawn move, oldest first and not including the current one
+    history: Vec<u64>,
 }
 
+/// The history is left out, two states are equal if they describe the same position
+impl PartialEq for GameState {
+    fn eq(&self, other: &Self) -> bool {
+        self.pieces == other.pieces
+            && self.to_move == other.to_move
+            && self.en_passant_sq == other.en_passant_sq
+            && self.castles == other.castles
+            && [Side::White, Side::Black].into_iter().all(|side| self.castle_files[side] == other.castle_files[side] || self.castles[side] == Castles::NONE)
+            && self.variant == other.variant
+            && self.checks == other.checks
+            && self.halfmove_clock == other.halfmove_clock
+            && self.fullmove_number == other.fullmove_number
+    }
+}
+
+impl Eq for GameState {}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Castles {
-    kingside: bool,
-    queenside: bool,
+    pub kingside: bool,
+    pub queenside: bool,
+}
+
+impl Castles {
+    pub const NONE: Castles = Castles { kingside: false, queenside: false };
+}
+
+/// The files the king and the castling rooks of one side start on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CastleFiles {
+    pub king: u8,
+    pub kingside: u8,
+    pub queenside: u8,
+}
+
+impl Default for CastleFiles {
+    fn default() -> Self {
+        Self { king: 4, kingside: 7, queenside: 0 }
+    }
+}
+
+/// The side of the board a castling move goes to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Wing {
+    Kingside,
+    Queenside,
+}
+
+/// Describes which part of a fen couldn't be parsed.
+/// Each variant holds the column (the 0-based index into the string) where the problem was found.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FenError {
+    Pieces(usize),
+    Side(usize),
+    Castling(usize),
+    EnPassant(usize),
+    /// The remaining checks of Three-check, written like `3+3`
+    Checks(usize),
+    HalfmoveClock(usize),
+    FullmoveNumber(usize),
+    /// There's more text after the fullmove number
+    TrailingData(usize),
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::Pieces(col) => write!(f, "invalid piece placement at column {col}"),
+            FenError::Side(col) => write!(f, "invalid side to move at column {col}"),
+            FenError::Castling(col) => write!(f, "invalid castling rights at column {col}"),
+            FenError::EnPassant(col) => write!(f, "invalid en passant square at column {col}"),
+            FenError::Checks(col) => write!(f, "invalid remaining checks at column {col}"),
+            FenError::HalfmoveClock(col) => write!(f, "invalid halfmove clock at column {col}"),
+            FenError::FullmoveNumber(col) => write!(f, "invalid fullmove number at column {col}"),
+            FenError::TrailingData(col) => write!(f, "unexpected data at column {col}"),
+        }
+    }
+}
+
+/// A move that isn't legal in the position it was played in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IllegalMove(pub Move);
+
+impl Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "illegal move {}", self.0)
+    }
+}
+
+/// Everything [`GameState::play`] overwrites, so [`GameState::unplay`] can put it back
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Undo {
+    /// The piece that was taken, also for en passant
+    captured: Option<Piece>,
+    castle: Option<Wing>,
+    castles: EnumMap<Side, Castles>,
+    en_passant_sq: Option<Location>,
+    checks: EnumMap<Side, u8>,
+    halfmove_clock: u32,
+    hash: u64,
+    /// Only filled in when the move cleared the history, otherwise the last entry is popped
+    history: Vec<u64>,
+}
+
+/// Splits a fen on whitespace, keeping track of where each field starts
+fn fen_fields(str: &str) -> Vec<(usize, &str)> {
+    let mut fields = Vec::new();
+    let mut start = None;
+    for (i, c) in str.char_indices() {
+        if c.is_ascii_whitespace() {
+            if let Some(s) = start.take() {
+                fields.push((s, &str[s..i]));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        fields.push((s, &str[s..]));
+    }
+    return fields;
 }
 
 impl Default for GameState {
     fn default() -> Self {
-        Self { pieces: StandardBoard::new_empty(), to_move: Side::White, en_passant_sq: None, castles: enum_map! { _ => Castles { kingside: false, queenside: false }} }
+        Self { pieces: BitBoard::new_empty(), to_move: Side::White, en_passant_sq: None, castles: enum_map! { _ => Castles::NONE }, castle_files: enum_map! { _ => CastleFiles::default() }, chess960: false, variant: Variant::Standard, checks: enum_map! { _ => 0 }, halfmove_clock: 0, fullmove_number: 1, hash: 0, history: Vec::new() }
     }
 }
 
 impl GameState {
+    /// Parses a fen, panicking if it's invalid. Use [`Self::parse_fen`] for input that can't be trusted
     pub fn from_fen(str: &str) -> Self {
+        return Self::parse_fen(str).unwrap_or_else(|e| panic!("Invalid fen \"{str}\": {e}"));
+    }
+
+    /// Parses a fen. The halfmove clock and fullmove number may be left out, like in EPD.
+    /// The remaining checks of Three-check can follow the en passant square, that also switches to the variant
+    pub fn parse_fen(str: &str) -> Result<Self, FenError> {
         let mut state = Self::default();
-        let mut fen = str.chars();
+        let mut fields = fen_fields(str).into_iter().peekable();
+        let end = str.len();
 
         {
-            let pieces = fen.by_ref().take_while(|c| *c != ' ');
+            let (start, pieces) = fields.next().ok_or(FenError::Pieces(end))?;
             let mut y = 7;
             let mut x = 0;
-            for piece_char in pieces {
+            for (i, piece_char) in pieces.char_indices() {
+                let column = start + i;
                 if piece_char == '/' {
+                    if x != 8 || y == 0 {
+                        return Err(FenError::Pieces(column));
+                    }
                     y -= 1;
                     x = 0;
-                    if y == 8 {
-                        panic!("Fen has too many ranks");
-                    }
                     continue;
                 }
-                if piece_char.is_ascii_digit() {
-                    x += piece_char.as_ascii().unwrap().to_u8() - b'0';
+                if let Some(skip) = piece_char.to_digit(10) && (1..=8).contains(&skip) {
+                    x += skip as u8;
+                    if x > 8 {
+                        return Err(FenError::Pieces(column));
+                    }
                     continue;
                 }
-                state.set(Location::new(x, y), Some(Piece::from_fen_char(piece_char)));
+                let Some(piece) = Piece::try_from_fen_char(piece_char) else { return Err(FenError::Pieces(column)) };
+                if x >= 8 {
+                    return Err(FenError::Pieces(column));
+                }
+                state.set(Location::new(x, y), Some(piece));
                 x += 1;
             }
+            if x != 8 || y != 0 {
+                return Err(FenError::Pieces(start + pieces.len()));
+            }
         }
 
         {
-            let active_colour: Vec<_> = fen.by_ref().take_while(|c| *c != ' ').collect();
-            assert_eq!(active_colour.len(), 1);
-            state.to_move = match active_colour[0] {
-                'w' => Side::White,
-                'b' => Side::Black,
-                _ => panic!("Invalid side {}", active_colour[0])
+            let (start, active_colour) = fields.next().ok_or(FenError::Side(end))?;
+            state.to_move = match active_colour {
+                "w" => Side::White,
+                "b" => Side::Black,
+                _ => return Err(FenError::Side(start)),
             }
         }
 
         {
-            let castles = fen.by_ref().take_while(|c| *c != ' ');
-            for i in castles {
-                if i == '-' { continue; }
-                let side = if i.is_uppercase() { Side::White } else { Side::Black };
-                if i.to_ascii_uppercase() == 'K' {
-                    state.castles[side].kingside = true;
-                } else {
-                    state.castles[side].queenside = true;
+            // Both the standard letters and Shredder-FEN are accepted. For `KQkq` the outermost rook on that wing is used, like in X-FEN
+            let (start, castles) = fields.next().ok_or(FenError::Castling(end))?;
+            if castles != "-" {
+                for (i, c) in castles.char_indices() {
+                    let side = if c.is_ascii_uppercase() { Side::White } else { Side::Black };
+                    let rank = side.home_rank();
+                    let rook_files: Vec<u8> = (0..8).filter(|x| state.get(Location::new(*x, rank)) == Some(Piece::new(side, PieceType::Rook))).collect();
+                    let king_file = (0..8).find(|x| state.get(Location::new(*x, rank)) == Some(Piece::new(side, PieceType::King)));
+                    let files = &mut state.castle_files[side];
+                    if let Some(king) = king_file {
+                        files.king = king;
+                    }
+                    match c.to_ascii_uppercase() {
+                        'K' => {
+                            state.castles[side].kingside = true;
+                            if let Some(rook) = rook_files.iter().rev().find(|x| **x > files.king) {
+                                files.kingside = *rook;
+                            }
+                        },
+                        'Q' => {
+                            state.castles[side].queenside = true;
+                            if let Some(rook) = rook_files.iter().find(|x| **x < files.king) {
+                                files.queenside = *rook;
+                            }
+                        },
+                        'A'..='H' => {
+                            let rook = c.to_ascii_uppercase() as u8 - b'A';
+                            if rook > files.king {
+                                state.castles[side].kingside = true;
+                                files.kingside = rook;
+                            } else if rook < files.king {
+                                state.castles[side].queenside = true;
+                                files.queenside = rook;
+                            } else {
+                                return Err(FenError::Castling(start + i));
+                            }
+                            state.chess960 = true;
+                        },
+                        _ => return Err(FenError::Castling(start + i)),
+                    }
                 }
-                // TODO check for invalid letters
             }
         }
 
         {
-            let en_passant: Vec<_> = fen.by_ref().take_while(|c| *c != ' ').collect();
-            if en_passant.len() > 1 {
-                state.en_passant_sq = Some(Location::from_letters(en_passant[0], en_passant[1]));
+            let (start, en_passant) = fields.next().ok_or(FenError::EnPassant(end))?;
+            if en_passant != "-" {
+                let chars: Vec<_> = en_passant.chars().collect();
+                if chars.len() != 2 || !('a'..='h').contains(&chars[0]) || !['3', '6'].contains(&chars[1]) {
+                    return Err(FenError::EnPassant(start));
+                }
+                state.en_passant_sq = Some(Location::from_letters(chars[0], chars[1]));
             }
         }
 
-        return state;
+        if let Some((start, checks)) = fields.next_if(|(_, field)| field.contains('+')) {
+            let remaining: Vec<_> = checks.split('+').map(|n| n.parse::<u8>().ok().filter(|n| *n <= 3)).collect();
+            let [Some(white), Some(black)] = remaining[..] else { return Err(FenError::Checks(start)); };
+            state.checks = enum_map! { Side::White => 3 - white, Side::Black => 3 - black };
+            state.variant = Variant::ThreeCheck;
+        }
+
+        {
+            if let Some((start, halfmove)) = fields.next() {
+                state.halfmove_clock = halfmove.parse().map_err(|_| FenError::HalfmoveClock(start))?;
+            }
+            if let Some((start, fullmove)) = fields.next() {
+                state.fullmove_number = fullmove.parse().map_err(|_| FenError::FullmoveNumber(start))?;
+            }
+            if let Some((start, _)) = fields.next() {
+                return Err(FenError::TrailingData(start));
+            }
+        }
+
+        state.hash = zobrist::hash(&state.pieces, state.to_move, state.castles, state.en_passant_sq, state.checks);
+        return Ok(state);
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for y in (0..8).rev() {
+            let mut empty = 0;
+            for x in 0..8 {
+                match self.get(Location::new(x, y)) {
+                    None => empty += 1,
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece.to_char());
+                    }
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push(' ');
+        fen.push(match self.to_move {
+            Side::White => 'w',
+            Side::Black => 'b',
+        });
+
+        fen.push(' ');
+        let castles_start = fen.len();
+        for side in [Side::White, Side::Black] {
+            let castles = self.castles[side];
+            let files = self.castle_files[side];
+            let (kingside, queenside) = match self.castles_onto_rook(side) {
+                // Shredder-FEN names the file of the rook
+                true => ((b'A' + files.kingside) as char, (b'A' + files.queenside) as char),
+                false => ('K', 'Q'),
+            };
+            let case = |c: char| if side == Side::White { c } else { c.to_ascii_lowercase() };
+            if castles.kingside { fen.push(case(kingside)); }
+            if castles.queenside { fen.push(case(queenside)); }
+        }
+        if fen.len() == castles_start {
+            fen.push('-');
+        }
+
+        fen.push(' ');
+        match self.en_passant_sq {
+            Some(sq) => fen.push_str(&sq.to_string()),
+            None => fen.push('-'),
+        }
+
+        if self.variant == Variant::ThreeCheck {
+            fen.push_str(&format!(" {}+{}", 3 - self.checks[Side::White], 3 - self.checks[Side::Black]));
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        return fen;
+    }
+
+    pub fn get_halfmove_clock(&self) -> u32 {
+        return self.halfmove_clock;
+    }
+
+    pub fn get_fullmove_number(&self) -> u32 {
+        return self.fullmove_number;
     }
 
     pub fn get(&self, loc: Location) -> Option<Piece> {
-        return self.pieces[loc];
+        return self.pieces.get(loc);
     }
 
     pub fn set(&mut self, loc: Location, piece: Option<Piece>) {
-        self.pieces[loc] = piece;
+        if let Some(old) = self.pieces.get(loc) {
+            self.hash ^= zobrist::piece_key(old, loc);
+        }
+        if let Some(new) = piece {
+            self.hash ^= zobrist::piece_key(new, loc);
+        }
+        self.pieces.set(loc, piece);
+    }
+
+    /// The zobrist key of the position, see [`zobrist`] for what it covers
+    pub fn hash(&self) -> u64 {
+        return self.hash;
+    }
+
+    /// The hashes of the earlier positions that can still be repeated, oldest first
+    pub fn get_history(&self) -> &[u64] {
+        return &self.history;
+    }
+
+    /// How many times the current position has occurred, counting the current one
+    pub fn repetitions(&self) -> usize {
+        return 1 + self.history.iter().filter(|h| **h == self.hash).count();
+    }
+
+    pub fn get_castles(&self) -> EnumMap<Side, Castles> {
+        return self.castles;
+    }
+
+    pub fn get_castle_files(&self) -> EnumMap<Side, CastleFiles> {
+        return self.castle_files;
     }
 
-    pub fn get_board(&self) -> impl Board {
+    pub fn get_en_passant(&self) -> Option<Location> {
+        return self.en_passant_sq;
+    }
+
+    pub fn is_chess960(&self) -> bool {
+        return self.chess960;
+    }
+
+    /// Switches between writing castling as the king moving two squares and as the king moving onto its own rook
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    pub fn get_variant(&self) -> Variant {
+        return self.variant;
+    }
+
+    /// Switches to the rules of another variant, the position itself stays the same
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
+    /// How many times each side has given check. Only counted in Three-check, where the third one wins
+    pub fn get_checks(&self) -> EnumMap<Side, u8> {
+        return self.checks;
+    }
+
+    /// Whether castling for `side` is written as the king moving onto its own rook.
+    /// That's the case in Chess960 mode, and whenever the king or rooks didn't start on the standard files.
+    pub fn castles_onto_rook(&self, side: Side) -> bool {
+        return self.chess960 || self.castle_files[side] != CastleFiles::default();
+    }
+
+    /// Returns the wing that `m` castles to, or `None` if it isn't castling.
+    /// The king moving onto its own rook is always castling, the king moving two squares only if [`Self::castles_onto_rook`] is false.
+    pub fn castle_wing(&self, m: Move) -> Option<Wing> {
+        let king = self.get(m.0)?;
+        let files = self.castle_files[king.side];
+        if king.ty != PieceType::King || m.0 != Location::new(files.king, king.side.home_rank()) || m.1.get_y() != m.0.get_y() {
+            return None;
+        }
+        let onto_rook = self.get(m.1) == Some(Piece::new(king.side, PieceType::Rook));
+        if !onto_rook && (self.castles_onto_rook(king.side) || u8::abs_diff(m.0.get_x(), m.1.get_x()) != 2) {
+            return None;
+        }
+        return Some(if m.1.get_x() > m.0.get_x() { Wing::Kingside } else { Wing::Queenside });
+    }
+
+    pub fn get_board(&self) -> BitBoard {
         self.pieces
     }
 
-    pub fn play(&mut self, m: Move) {
+    /// Converts the position into a board that can be uploaded to the gpu, including the castling rights, en passant square and hash
+    pub fn get_gpu_board(&self) -> GpuBoard {
+        let mut board: GpuBoard = board::convert(&self.pieces);
+        board.set_castles(self.castles);
+        board.set_castle_files(self.castle_files);
+        board.set_en_passant(self.en_passant_sq);
+        board.set_checks(self.checks);
+        board.set_hash(self.hash);
+        return board;
+    }
+
+    /// The position on a board that came back from the gpu, in a game of `variant`. Boards don't keep the clocks or the history,
+    /// so those start over
+    pub fn from_gpu_board(board: &GpuBoard, to_move: Side, variant: Variant) -> Self {
+        let mut state = Self::default();
+        state.variant = variant;
+        state.pieces = board::convert(board);
+        state.to_move = to_move;
+        state.castles = board.get_castles();
+        state.castle_files = board.get_castle_files();
+        state.en_passant_sq = board.get_en_passant();
+        state.checks = board.get_checks();
+        state.hash = zobrist::hash(&state.pieces, state.to_move, state.castles, state.en_passant_sq, state.checks);
+        return state;
+    }
+
+    /// Like [`GameState::play`], but checks that the move is in the list of legal moves first.
+    /// The state is left untouched if it isn't.
+    pub fn try_play(&mut self, m: Move) -> Result<(), IllegalMove> {
+        if !self.legal_moves().contains(&m) {
+            return Err(IllegalMove(m));
+        }
+        self.play(m);
+        return Ok(());
+    }
+
+    /// Removes the castling right that belongs to the rook starting square at `loc`, if there is one
+    fn clear_corner_castle(&mut self, loc: Location) {
+        let side = match loc.get_y() {
+            0 => Side::White,
+            7 => Side::Black,
+            _ => return,
+        };
+        let files = self.castle_files[side];
+        if loc.get_x() == files.queenside {
+            self.castles[side].queenside = false;
+        }
+        if loc.get_x() == files.kingside {
+            self.castles[side].kingside = false;
+        }
+    }
+
+    /// Plays the move without checking if it's legal, see [`GameState::try_play`] for that.
+    /// The returned record can be passed to [`GameState::unplay`] to take the move back.
+    pub fn play(&mut self, m: Move) -> Undo {
+        let castle = self.castle_wing(m);
+        let mut undo = Undo {
+            captured: if castle.is_none() { self.get(m.1) } else { None },
+            castle,
+            castles: self.castles,
+            en_passant_sq: self.en_passant_sq,
+            checks: self.checks,
+            halfmove_clock: self.halfmove_clock,
+            hash: self.hash,
+            history: Vec::new(),
+        };
+        let position_before = self.hash;
+        // The pieces are updated through `set`, the rest is swapped out as a whole at the start and the end
+        self.hash ^= zobrist::state_key(&self.pieces, self.to_move, self.castles, self.en_passant_sq, self.checks);
+        if self.to_move == Side::Black {
+            self.fullmove_number += 1;
+        }
         self.to_move = self.to_move.opposite();
         let prev = self.get(m.0);
 
-        if let Some(king) = self.get(m.0) && king.ty == PieceType::King {
-            if u8::abs_diff(m.0.get_x(), m.1.get_x()) == 2 {
-                // Castling!
-                let castle_state = &mut self.castles[king.side];
-                if m.1.get_x() < m.0.get_x() {
-                    castle_state.queenside = false;
-                    self.set(m.0.with_x(0), None);
-                    self.set(m.0.with_x(3), Some(Piece { ty: PieceType::Rook, side: king.side }));
-                } else {
-                    castle_state.kingside = false;
-                    self.set(m.0.with_x(7), None);
-                    self.set(m.0.with_x(5), Some(Piece { ty: PieceType::Rook, side: king.side }));
-                }
-            }
+        // In Chess960 castling looks like the king capturing its own rook, that doesn't count
+        if prev.is_some_and(|p| p.ty == PieceType::Pawn) || (castle.is_none() && self.get(m.1).is_some()) {
+            // None of the earlier positions can come back after this
+            self.halfmove_clock = 0;
+            undo.history = std::mem::take(&mut self.history);
+        } else {
+            self.halfmove_clock += 1;
+            self.history.push(position_before);
         }
 
         let old_en_passant_sq = self.en_passant_sq;
         self.en_passant_sq = None;
 
+        if let Some(wing) = castle {
+            // Castling! The king and rook end up on the same squares as in normal chess, wherever they started
+            let king = prev.unwrap();
+            let files = self.castle_files[king.side];
+            let (rook_from, king_to, rook_to) = match wing {
+                Wing::Kingside => (files.kingside, 6, 5),
+                Wing::Queenside => (files.queenside, 2, 3),
+            };
+            self.set(m.0, None);
+            self.set(m.0.with_x(rook_from), None);
+            self.set(m.0.with_x(king_to), Some(king));
+            self.set(m.0.with_x(rook_to), Some(Piece { ty: PieceType::Rook, side: king.side }));
+            self.castles[king.side] = Castles::NONE;
+            self.count_check();
+            self.hash ^= zobrist::state_key(&self.pieces, self.to_move, self.castles, self.en_passant_sq, self.checks);
+            return undo;
+        }
+
+        if let Some(king) = prev && king.ty == PieceType::King {
+            // Any king move gives up both rights
+            self.castles[king.side] = Castles::NONE;
+        }
+
+        // A rook moving away from its starting square, or being captured there
+        self.clear_corner_castle(m.0);
+        self.clear_corner_castle(m.1);
+
         if let Some(piece) = prev && piece.ty == PieceType::Pawn {
             if Some(m.1) == old_en_passant_sq {
                 // En-passant was played, need to remove the capture pawn
@@ -127,6 +574,7 @@ impl GameState {
                     Side::Black => old_en_passant_sq.unwrap() + (0, 1),
                     Side::White => old_en_passant_sq.unwrap() + (0, -1),
                 };
+                undo.captured = self.get(captured_location);
                 self.set(captured_location, None);
             }
             if u8::abs_diff(m.0.get_y(), m.1.get_y()) == 2 {
@@ -145,6 +593,61 @@ impl GameState {
                 self.set(m.1, Some(Piece { ty: promotion, side: prev_piece.side}));
             }
         }
+        self.count_check();
+        self.hash ^= zobrist::state_key(&self.pieces, self.to_move, self.castles, self.en_passant_sq, self.checks);
+        return undo;
+    }
+
+    /// Counts it if the side that just moved gave check, in variants where that matters
+    fn count_check(&mut self) {
+        if self.variant == Variant::ThreeCheck && self.pieces.in_check(self.to_move) {
+            let checks = &mut self.checks[self.to_move.opposite()];
+            *checks = u8::min(*checks + 1, 3);
+        }
+    }
+
+    /// Takes back `m`, which has to be the last move that was played, using the record [`GameState::play`] returned for it
+    pub fn unplay(&mut self, m: Move, undo: Undo) {
+        self.to_move = self.to_move.opposite();
+        if self.to_move == Side::Black {
+            self.fullmove_number -= 1;
+        }
+
+        if let Some(wing) = undo.castle {
+            let files = self.castle_files[self.to_move];
+            let (rook_from, king_to, rook_to) = match wing {
+                Wing::Kingside => (files.kingside, 6, 5),
+                Wing::Queenside => (files.queenside, 2, 3),
+            };
+            self.pieces.set(m.0.with_x(king_to), None);
+            self.pieces.set(m.0.with_x(rook_to), None);
+            self.pieces.set(m.0, Some(Piece::new(self.to_move, PieceType::King)));
+            self.pieces.set(m.0.with_x(rook_from), Some(Piece::new(self.to_move, PieceType::Rook)));
+        } else {
+            let moved = match m.2 {
+                Some(_) => Some(Piece::new(self.to_move, PieceType::Pawn)),
+                None => self.get(m.1),
+            };
+            let en_passant = moved.is_some_and(|p| p.ty == PieceType::Pawn) && Some(m.1) == undo.en_passant_sq;
+            if en_passant {
+                self.pieces.set(m.1, None);
+                self.pieces.set(m.1.with_y(m.0.get_y()), undo.captured);
+            } else {
+                self.pieces.set(m.1, undo.captured);
+            }
+            self.pieces.set(m.0, moved);
+        }
+
+        if self.halfmove_clock == 0 {
+            self.history = undo.history;
+        } else {
+            self.history.pop();
+        }
+        self.castles = undo.castles;
+        self.en_passant_sq = undo.en_passant_sq;
+        self.checks = undo.checks;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.hash = undo.hash;
     }
 }
 
@@ -153,13 +656,15 @@ mod test {
 
     use crate::chess::Move;
 
-    use super::GameState;
+    use crate::chess::Side;
+
+    use super::{GameState, FenError, IllegalMove, CastleFiles};
 
     #[test]
     fn play_normal_move() {
         let mut state = GameState::from_fen("8/8/8/8/8/8/1K5k/8 w - - 0 1");
         state.play(Move::from_str("b2c2"));
-        assert_eq!(state, GameState::from_fen("8/8/8/8/8/8/2K4k/8 b - - 0 1"));
+        assert_eq!(state, GameState::from_fen("8/8/8/8/8/8/2K4k/8 b - - 1 1"));
     }
 
     #[test]
@@ -176,61 +681,99 @@ mod test {
         assert_eq!(state, GameState::from_fen("1Q6/8/8/8/8/8/5K1k/8 b - - 0 1"));
     }
 
-    // Testcases for Chess960
-    // #[test]
-    // fn play_castle_white_short() {
-    //     let mut state = GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQkq - 0 1");
-    //     state.play(Move::from_str("e1h1"));
-    //     assert_eq!(state, GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQ1RK1 b Qkq - 0 1"));
-    // }
-
-    // #[test]
-    // fn play_castle_black_short() {
-    //     let mut state = GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R b KQkq - 0 1");
-    //     state.play(Move::from_str("e8h8"));
-    //     assert_eq!(state, GameState::from_fen("rnbq1rk1/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQq - 0 1"));
-    // }
-
-    // #[test]
-    // fn play_castle_white_long() {
-    //     let mut state = GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/R3KBNR w KQkq - 0 1");
-    //     state.play(Move::from_str("e1a1"));
-    //     assert_eq!(state, GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b Kkq - 0 1"));
-    // }
-
-    // #[test]
-    // fn play_castle_black_long() {
-    //     let mut state = GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b Kkq - 0 1");
-    //     state.play(Move::from_str("e8a8"));
-    //     assert_eq!(state, GameState::from_fen("2kr1bnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR w Kk - 0 1"));
-    // }
-    
+    // Castling written as the king moving onto its rook, like in Chess960
+    #[test]
+    fn play_castle_white_short_960() {
+        let mut state = GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQkq - 0 1");
+        state.play(Move::from_str("e1h1"));
+        assert_eq!(state, GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQ1RK1 b kq - 1 1"));
+    }
+
+    #[test]
+    fn play_castle_black_short_960() {
+        let mut state = GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R b KQkq - 0 1");
+        state.play(Move::from_str("e8h8"));
+        assert_eq!(state, GameState::from_fen("rnbq1rk1/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQ - 1 2"));
+    }
+
+    #[test]
+    fn play_castle_white_long_960() {
+        let mut state = GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/R3KBNR w KQkq - 0 1");
+        state.play(Move::from_str("e1a1"));
+        assert_eq!(state, GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b kq - 1 1"));
+    }
+
+    #[test]
+    fn play_castle_black_long_960() {
+        let mut state = GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b Kkq - 0 1");
+        state.play(Move::from_str("e8a8"));
+        assert_eq!(state, GameState::from_fen("2kr1bnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR w K - 1 2"));
+    }
+
+    #[test]
+    fn play_castle_shuffled() {
+        // The king is already on g1, only the rook moves
+        let mut state = GameState::from_fen("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1");
+        state.play(Move::from_str("g1h1"));
+        assert_eq!(state.to_fen(), "1r4kr/8/8/8/8/8/8/1R3RK1 b hb - 1 1");
+        state.play(Move::from_str("g8b8"));
+        assert_eq!(state.to_fen(), "2kr3r/8/8/8/8/8/8/1R3RK1 w - - 2 2");
+    }
+
+    #[test]
+    fn fen_castling_files() {
+        // Shredder-FEN names the rook files
+        let state = GameState::from_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9");
+        assert!(state.is_chess960());
+        assert_eq!(state.get_castle_files()[Side::White], CastleFiles { king: 6, kingside: 7, queenside: 5 });
+        assert_eq!(state.to_fen(), "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9");
+
+        // X-FEN uses the standard letters for the outermost rooks
+        let state = GameState::from_fen("rk2r3/8/8/8/8/8/8/RK2R3 w KQkq - 0 1");
+        assert!(state.castles_onto_rook(Side::White));
+        assert_eq!(state.get_castle_files()[Side::Black], CastleFiles { king: 1, kingside: 4, queenside: 0 });
+        assert_eq!(state.to_fen(), "rk2r3/8/8/8/8/8/8/RK2R3 w EAea - 0 1");
+        assert_eq!(state, GameState::from_fen("rk2r3/8/8/8/8/8/8/RK2R3 w EAea - 0 1"));
+
+        // The standard files don't switch to Shredder-FEN, unless asked to
+        let mut state = GameState::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert!(!state.is_chess960());
+        assert_eq!(state, GameState::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1"));
+        state.set_chess960(true);
+        assert_eq!(state.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1");
+        assert!(state.legal_moves().contains(&Move::from_str("e1h1")));
+        assert!(!state.legal_moves().contains(&Move::from_str("e1g1")));
+
+        // The rook can't be on the king's file
+        assert_eq!(GameState::parse_fen("4k3/8/8/8/8/8/8/4K3 w E - 0 1"), Err(FenError::Castling(22)));
+    }
+
     #[test]
     fn play_castle_white_short() {
         let mut state = GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQkq - 0 1");
         state.play(Move::from_str("e1g1"));
-        assert_eq!(state, GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQ1RK1 b Qkq - 0 1"));
+        assert_eq!(state, GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQ1RK1 b kq - 1 1"));
     }
 
     #[test]
     fn play_castle_black_short() {
         let mut state = GameState::from_fen("rnbqk2r/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R b KQkq - 0 1");
         state.play(Move::from_str("e8g8"));
-        assert_eq!(state, GameState::from_fen("rnbq1rk1/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQq - 0 1"));
+        assert_eq!(state, GameState::from_fen("rnbq1rk1/pppp1ppp/7n/4p3/1b2P3/3B1N2/PPPP1PPP/RNBQK2R w KQ - 1 2"));
     }
 
     #[test]
     fn play_castle_white_long() {
         let mut state = GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/R3KBNR w KQkq - 0 1");
         state.play(Move::from_str("e1c1"));
-        assert_eq!(state, GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b Kkq - 0 1"));
+        assert_eq!(state, GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b kq - 1 1"));
     }
 
     #[test]
     fn play_castle_black_long() {
         let mut state = GameState::from_fen("r3kbnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR b Kkq - 0 1");
         state.play(Move::from_str("e8c8"));
-        assert_eq!(state, GameState::from_fen("2kr1bnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR w Kk - 0 1"));
+        assert_eq!(state, GameState::from_fen("2kr1bnr/ppp1qppp/n2p4/4p3/4P1Q1/2NPB3/PPP2PPP/2KR1BNR w K - 1 2"));
     }
 
     #[test]
@@ -241,4 +784,188 @@ mod test {
         state.play(Move::from_str("c4b3"));
         assert_eq!(state, GameState::from_fen("8/8/8/8/8/1p6/5K1k/8 w - - 0 2"));
     }
+
+    #[test]
+    fn castle_rights_bookkeeping() {
+        let start = GameState::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+
+        let mut state = start.clone();
+        state.play(Move::from_str("e1e2"));
+        assert_eq!(state.to_fen(), "r3k2r/8/8/8/8/8/4K3/R6R b kq - 1 1");
+
+        let mut state = start.clone();
+        state.play(Move::from_str("h1h5"));
+        assert_eq!(state.to_fen(), "r3k2r/8/8/7R/8/8/8/R3K3 b Qkq - 1 1");
+
+        let mut state = start.clone();
+        state.play(Move::from_str("a1a8"));
+        assert_eq!(state.to_fen(), "R3k2r/8/8/8/8/8/8/4K2R b Kk - 0 1");
+
+        let mut state = start.clone();
+        state.play(Move::from_str("h1h2"));
+        state.play(Move::from_str("h8h2"));
+        assert_eq!(state.to_fen(), "r3k3/8/8/8/8/8/7r/R3K3 w Qq - 0 2");
+    }
+
+    #[test]
+    fn try_play() {
+        let mut state = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let before = state.clone();
+        assert_eq!(state.try_play(Move::from_str("e3e4")), Err(IllegalMove(Move::from_str("e3e4"))));
+        assert_eq!(state.try_play(Move::from_str("e7e5")), Err(IllegalMove(Move::from_str("e7e5"))));
+        assert_eq!(state.try_play(Move::from_str("e1g1")), Err(IllegalMove(Move::from_str("e1g1"))));
+        assert_eq!(state, before);
+        assert_eq!(state.try_play(Move::from_str("e2e4")), Ok(()));
+        assert_eq!(state.try_play(Move::from_str("e7e5")), Ok(()));
+        assert_eq!(state.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+
+        // Promotions have to name the piece
+        let mut state = GameState::from_fen("7k/P7/8/8/8/8/8/K7 w - - 0 1");
+        assert!(state.try_play(Move::from_str("a7a8")).is_err());
+        assert_eq!(state.try_play(Move::from_str("a7a8q")), Ok(()));
+    }
+
+    #[test]
+    fn parse_moves() {
+        assert_eq!(Move::try_from_str("e2e4"), Some(Move::from_str("e2e4")));
+        assert_eq!(Move::try_from_str("a7a8n"), Some(Move::from_str("a7a8n")));
+        assert_eq!(Move::try_from_str("e2e"), None);
+        assert_eq!(Move::try_from_str("e2e9"), None);
+        assert_eq!(Move::try_from_str("i2e4"), None);
+        assert_eq!(Move::try_from_str("a7a8k"), None);
+        assert_eq!(Move::try_from_str("a7a8x"), None);
+        assert_eq!(Move::try_from_str("e2e4qq"), None);
+    }
+
+    /// Also compares what `PartialEq` leaves out
+    fn assert_identical(a: &GameState, b: &GameState, context: &str) {
+        assert_eq!(a, b, "{context}");
+        assert_eq!(a.hash(), b.hash(), "{context}");
+        assert_eq!(a.get_history(), b.get_history(), "{context}");
+        assert_eq!(a.to_fen(), b.to_fen(), "{context}");
+    }
+
+    #[test]
+    fn unplay_special_moves() {
+        let cases = [
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 3 10", "e1g1"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 3 10", "e8c8"),
+            ("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1", "g1h1"),
+            ("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1", "g1b1"),
+            ("k7/8/8/2pP4/8/8/8/K7 w - c6 0 1", "d5c6"),
+            ("k6r/6P1/8/8/8/8/8/K7 w - - 5 1", "g7h8n"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "a1a8"),
+        ];
+        for (fen, m) in cases {
+            let before = GameState::from_fen(fen);
+            let mut state = before.clone();
+            let m = Move::from_str(m);
+            let undo = state.play(m);
+            state.unplay(m, undo);
+            assert_identical(&state, &before, &format!("{m} in {fen}"));
+        }
+    }
+
+    #[test]
+    fn unplay_random_games() {
+        // xorshift, so the games are the same on every run
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut random = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        ];
+        for fen in fens {
+            for _ in 0..10 {
+                let mut state = GameState::from_fen(fen);
+                let mut played = Vec::new();
+                for _ in 0..100 {
+                    let moves = state.legal_moves();
+                    if moves.is_empty() {
+                        break;
+                    }
+                    let m = moves[random(moves.len())];
+                    let before = state.clone();
+                    let undo = state.play(m);
+                    played.push((m, undo, before));
+                }
+                while let Some((m, undo, before)) = played.pop() {
+                    state.unplay(m, undo);
+                    assert_identical(&state, &before, &format!("{m} in {}", before.to_fen()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clocks() {
+        let mut state = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        state.play(Move::from_str("g1f3"));
+        assert_eq!((state.get_halfmove_clock(), state.get_fullmove_number()), (1, 1));
+        state.play(Move::from_str("g8f6"));
+        assert_eq!((state.get_halfmove_clock(), state.get_fullmove_number()), (2, 2));
+        state.play(Move::from_str("e2e4"));
+        assert_eq!((state.get_halfmove_clock(), state.get_fullmove_number()), (0, 2));
+        state.play(Move::from_str("b8c6"));
+        state.play(Move::from_str("f1b5"));
+        assert_eq!((state.get_halfmove_clock(), state.get_fullmove_number()), (2, 3));
+        state.play(Move::from_str("f6e4"));
+        assert_eq!((state.get_halfmove_clock(), state.get_fullmove_number()), (0, 4));
+        assert_eq!(state.to_fen(), "r1bqkb1r/pppppppp/2n5/1B6/4n3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4");
+    }
+
+    #[test]
+    fn fen_errors() {
+        assert_eq!(GameState::parse_fen(""), Err(FenError::Pieces(0)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/P/8/8 w - - 0 1"), Err(FenError::Pieces(11)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/2p6/8/8 w - - 0 1"), Err(FenError::Pieces(12)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8/8 w - - 0 1"), Err(FenError::Pieces(15)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8 w - - 0 1"), Err(FenError::Pieces(13)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/7x w - - 0 1"), Err(FenError::Pieces(15)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8"), Err(FenError::Side(15)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8 x - - 0 1"), Err(FenError::Side(16)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8 w KQkx - 0 1"), Err(FenError::Castling(21)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8 w - e4 0 1"), Err(FenError::EnPassant(20)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8 w - - x 1"), Err(FenError::HalfmoveClock(22)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8 w - - 0 -1"), Err(FenError::FullmoveNumber(24)));
+        assert_eq!(GameState::parse_fen("8/8/8/8/8/8/8/8 w - - 0 1 moves"), Err(FenError::TrailingData(26)));
+    }
+
+    #[test]
+    fn fen_without_clocks() {
+        let state = GameState::parse_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3").unwrap();
+        assert_eq!(state, GameState::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"));
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "8/8/8/8/1Pp5/8/8/8 b - b3 0 1",
+            "8/8/8/2pP4/8/8/8/8 w - c6 0 1",
+            "r3k3/8/8/8/8/8/8/R3K2R w KQq - 0 1",
+            "k3r3/8/8/8/8/8/4B3/4K3 w - - 0 1",
+            "8/8/2b5/4p3/8/2Q5/8/8 w - - 0 1",
+            "8/8/8/2n5/8/2R3b1/8/8 w - - 0 1",
+        ];
+        for fen in fens {
+            assert_eq!(GameState::from_fen(fen).to_fen(), fen);
+        }
+
+        let mut state = GameState::from_fen("8/8/8/8/1Pp5/8/8/8 b - b3 7 31");
+        assert_eq!(GameState::from_fen(&state.to_fen()), state);
+        state.play(Move::from_str("c4b3"));
+        assert_eq!(GameState::from_fen(&state.to_fen()), state);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,277 @@
+use std::fmt::Display;
+
+use super::{GameState, Location, PieceType, Side, Variant, pgn::PgnResult};
+
+/// Whether the game is still going, and how it ended if it isn't
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate { winner: Side },
+    /// Won by a rule of the variant, like reaching the centre in King of the Hill
+    VariantWin { winner: Side, variant: Variant },
+    /// The side to move has no legal moves, but isn't in check
+    Stalemate,
+    Draw(DrawReason),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DrawReason {
+    /// The position has occurred three times, a draw can be claimed
+    ThreefoldRepetition,
+    /// The position has occurred five times
+    FivefoldRepetition,
+    /// 100 halfmoves have been played without a capture or a pawn move, a draw can be claimed
+    FiftyMoveRule,
+    /// 150 halfmoves have been played without a capture or a pawn move
+    SeventyFiveMoveRule,
+    /// Neither side can ever checkmate
+    InsufficientMaterial,
+}
+
+impl DrawReason {
+    /// Whether the game ends by itself, instead of one of the players having to claim the draw
+    pub fn is_automatic(&self) -> bool {
+        match self {
+            DrawReason::ThreefoldRepetition | DrawReason::FiftyMoveRule => false,
+            DrawReason::FivefoldRepetition | DrawReason::SeventyFiveMoveRule | DrawReason::InsufficientMaterial => true,
+        }
+    }
+}
+
+impl GameStatus {
+    /// Whether the game has ended. Draws that still have to be claimed don't count.
+    pub fn is_over(&self) -> bool {
+        match self {
+            GameStatus::Ongoing => false,
+            GameStatus::Draw(reason) => reason.is_automatic(),
+            GameStatus::Checkmate { .. } | GameStatus::VariantWin { .. } | GameStatus::Stalemate => true,
+        }
+    }
+
+    /// The result token that a pgn of the game should end with
+    pub fn pgn_result(&self) -> PgnResult {
+        match self {
+            GameStatus::Ongoing => PgnResult::Unknown,
+            GameStatus::Checkmate { winner: Side::White } | GameStatus::VariantWin { winner: Side::White, .. } => PgnResult::WhiteWins,
+            GameStatus::Checkmate { winner: Side::Black } | GameStatus::VariantWin { winner: Side::Black, .. } => PgnResult::BlackWins,
+            GameStatus::Stalemate | GameStatus::Draw(_) => PgnResult::Draw,
+        }
+    }
+}
+
+impl Display for GameStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameStatus::Ongoing => write!(f, "ongoing"),
+            GameStatus::Checkmate { winner: Side::White } => write!(f, "checkmate, white wins"),
+            GameStatus::Checkmate { winner: Side::Black } => write!(f, "checkmate, black wins"),
+            GameStatus::VariantWin { winner: Side::White, variant } => write!(f, "{variant}, white wins"),
+            GameStatus::VariantWin { winner: Side::Black, variant } => write!(f, "{variant}, black wins"),
+            GameStatus::Stalemate => write!(f, "stalemate"),
+            GameStatus::Draw(reason) => write!(f, "draw by {reason}"),
+        }
+    }
+}
+
+impl Display for DrawReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DrawReason::ThreefoldRepetition => write!(f, "threefold repetition"),
+            DrawReason::FivefoldRepetition => write!(f, "fivefold repetition"),
+            DrawReason::FiftyMoveRule => write!(f, "the fifty-move rule"),
+            DrawReason::SeventyFiveMoveRule => write!(f, "the seventy-five-move rule"),
+            DrawReason::InsufficientMaterial => write!(f, "insufficient material"),
+        }
+    }
+}
+
+impl GameState {
+    /// Checks if the game has ended, or if a draw can be claimed.
+    /// The win conditions of the variant come first, then checkmate, which takes precedence over the draw rules.
+    /// Draws that end the game take precedence over draws that have to be claimed.
+    pub fn status(&self) -> GameStatus {
+        if let Some(winner) = self.variant_winner() {
+            return GameStatus::VariantWin { winner, variant: self.get_variant() };
+        }
+        if self.legal_moves().is_empty() {
+            if self.in_check() {
+                return GameStatus::Checkmate { winner: self.to_move.opposite() };
+            }
+            return GameStatus::Stalemate;
+        }
+
+        let repetitions = self.repetitions();
+        let reasons = [
+            (self.insufficient_material(), DrawReason::InsufficientMaterial),
+            (repetitions >= 5, DrawReason::FivefoldRepetition),
+            (self.get_halfmove_clock() >= 150, DrawReason::SeventyFiveMoveRule),
+            (repetitions >= 3, DrawReason::ThreefoldRepetition),
+            (self.get_halfmove_clock() >= 100, DrawReason::FiftyMoveRule),
+        ];
+        if let Some((_, reason)) = reasons.into_iter().find(|(applies, _)| *applies) {
+            return GameStatus::Draw(reason);
+        }
+        return GameStatus::Ongoing;
+    }
+
+    /// True for king against king, with at most a single knight, or only bishops that are all on the same colour.
+    /// In King of the Hill either king can still walk to the centre, and in Three-check any piece can still give check
+    pub fn insufficient_material(&self) -> bool {
+        match self.get_variant() {
+            Variant::Standard => {},
+            Variant::KingOfTheHill => return false,
+            Variant::ThreeCheck => return self.get_board().occupied().count_ones() == 2,
+        }
+        let mut knights = 0;
+        let mut bishop_colours = [false; 2];
+        for loc in Location::all() {
+            let Some(piece) = self.get(loc) else { continue; };
+            match piece.ty {
+                PieceType::King => {},
+                PieceType::Horsy => knights += 1,
+                PieceType::Bishop => bishop_colours[((loc.get_x() + loc.get_y()) % 2) as usize] = true,
+                PieceType::Pawn | PieceType::Rook | PieceType::Queen => return false,
+            }
+        }
+        let bishops_on_one_colour = !(bishop_colours[0] && bishop_colours[1]);
+        let has_bishops = bishop_colours[0] || bishop_colours[1];
+        return match knights {
+            0 => bishops_on_one_colour,
+            1 => !has_bishops,
+            _ => false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chess::{GameState, Move, Side, pgn::PgnResult};
+
+    use super::{GameStatus, DrawReason};
+
+    #[test]
+    fn ongoing() {
+        let state = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(state.status(), GameStatus::Ongoing);
+        assert!(!state.status().is_over());
+        // In check, but there's a way out
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/r3K3 w - - 0 1").status(), GameStatus::Ongoing);
+    }
+
+    #[test]
+    fn checkmate() {
+        // Fool's mate
+        let state = GameState::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert_eq!(state.status(), GameStatus::Checkmate { winner: Side::Black });
+        assert_eq!(state.status().pgn_result(), PgnResult::BlackWins);
+
+        let state = GameState::from_fen("6k1/5ppp/8/8/8/8/8/3R2K1 b - - 0 1");
+        assert_eq!(state.status(), GameStatus::Ongoing);
+        let state = GameState::from_fen("3R2k1/5ppp/8/8/8/8/8/6K1 b - - 0 1");
+        assert_eq!(state.status(), GameStatus::Checkmate { winner: Side::White });
+    }
+
+    #[test]
+    fn stalemate() {
+        let state = GameState::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert_eq!(state.status(), GameStatus::Stalemate);
+        assert_eq!(state.status().pgn_result(), PgnResult::Draw);
+    }
+
+    #[test]
+    fn fifty_moves() {
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 99 80").status(), GameStatus::Ongoing);
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 100 80").status(), GameStatus::Draw(DrawReason::FiftyMoveRule));
+        assert!(!GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 100 80").status().is_over());
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 150 80").status(), GameStatus::Draw(DrawReason::SeventyFiveMoveRule));
+        assert!(GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 150 80").status().is_over());
+        // Mate on the last move still counts
+        assert_eq!(GameState::from_fen("R3k3/8/4K3/8/8/8/8/8 b - - 100 80").status(), GameStatus::Checkmate { winner: Side::White });
+    }
+
+    fn shuffle(state: &mut GameState, times: usize) {
+        for _ in 0..times {
+            for m in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                state.try_play(Move::from_str(m)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn repetition() {
+        let mut state = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        shuffle(&mut state, 1);
+        assert_eq!(state.repetitions(), 2);
+        assert_eq!(state.status(), GameStatus::Ongoing);
+        shuffle(&mut state, 1);
+        assert_eq!(state.repetitions(), 3);
+        assert_eq!(state.status(), GameStatus::Draw(DrawReason::ThreefoldRepetition));
+        assert!(!state.status().is_over());
+        shuffle(&mut state, 2);
+        assert_eq!(state.status(), GameStatus::Draw(DrawReason::FivefoldRepetition));
+        assert!(state.status().is_over());
+
+        // A pawn move makes the earlier positions unreachable
+        state.try_play(Move::from_str("e2e4")).unwrap();
+        assert!(state.get_history().is_empty());
+        assert_eq!(state.repetitions(), 1);
+        assert_eq!(state.status(), GameStatus::Ongoing);
+    }
+
+    #[test]
+    fn repetition_after_double_push() {
+        // Nothing can take on e3, so the knights coming back repeat the position right after 1.e4
+        let mut state = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        state.try_play(Move::from_str("e2e4")).unwrap();
+        for times in 2..=3 {
+            for m in ["g8f6", "g1f3", "f6g8", "f3g1"] {
+                state.try_play(Move::from_str(m)).unwrap();
+            }
+            assert_eq!(state.repetitions(), times);
+        }
+        assert_eq!(state.status(), GameStatus::Draw(DrawReason::ThreefoldRepetition));
+
+        // Here the pawn on f4 could take, which it can't anymore once the kings have moved
+        let mut state = GameState::from_fen("4k3/8/8/8/5p2/8/4P3/4K3 w - - 0 1");
+        state.try_play(Move::from_str("e2e4")).unwrap();
+        for m in ["e8d8", "e1d1", "d8e8", "d1e1"] {
+            state.try_play(Move::from_str(m)).unwrap();
+        }
+        assert_eq!(state.repetitions(), 1);
+    }
+
+    #[test]
+    fn repetition_needs_same_side_to_move() {
+        // White triangulates, so the pieces end up where they started, but with black to move
+        let mut state = GameState::from_fen("4k3/8/8/8/8/8/8/K7 w - - 0 1");
+        for m in ["a1a2", "e8d8", "a2b1", "d8e8", "b1a1"] {
+            state.try_play(Move::from_str(m)).unwrap_or_else(|_| panic!("{m}"));
+        }
+        assert_eq!(state.repetitions(), 1);
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let draws = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/B1B1K3 w - - 0 1",
+        ];
+        for fen in draws {
+            assert_eq!(GameState::from_fen(fen).status(), GameStatus::Draw(DrawReason::InsufficientMaterial), "{fen}");
+        }
+        let not_draws = [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/3BK3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/1N2K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+        ];
+        for fen in not_draws {
+            assert_eq!(GameState::from_fen(fen).status(), GameStatus::Ongoing, "{fen}");
+        }
+    }
+}
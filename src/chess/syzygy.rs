@@ -0,0 +1,1672 @@
+use std::{collections::HashMap, fmt::Display, io, ops::Neg, path::PathBuf, sync::{Arc, Mutex, OnceLock}};
+
+use log::warn;
+
+use super::{GameState, Location, Move, Piece, PieceType, Side, Variant};
+
+/// The most pieces, kings included, that Syzygy tables go up to
+pub const MAX_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+/// Listed in the order that material is written in table names, like `KQRvKN`
+pub(super) const NAME_ORDER: [PieceType; 6] = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Horsy, PieceType::Pawn];
+/// Ranks root moves, see [`Tablebases::root_moves`]
+const MAX_DTZ: i32 = 1 << 18;
+/// Tables are read into memory whole, so the ones that weren't used for the longest are dropped past this many bytes.
+/// Larger tables, like many of the 7 piece ones, aren't used at all
+const CACHE_BYTES: usize = 1 << 30;
+
+// Flags of each part of a table
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// The result of a position with perfect play, for the side to move.
+/// Cursed wins and blessed losses would be wins and losses, if not for the 50 move rule
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    pub(crate) fn from_i32(value: i32) -> Self {
+        match value {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            2.. => Wdl::Win,
+        }
+    }
+
+    fn signum(self) -> i32 {
+        return (self as i32).signum();
+    }
+
+    /// The DTZ of a move that resets the 50 move counter into a position with this result
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Wdl::Loss => -1,
+            Wdl::BlessedLoss => -101,
+            Wdl::Draw => 0,
+            Wdl::CursedWin => 101,
+            Wdl::Win => 1,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Self::Output {
+        return Wdl::from_i32(-(self as i32));
+    }
+}
+
+#[derive(Debug)]
+enum TableError {
+    Io(io::Error),
+    /// The file doesn't start with the magic bytes of its kind
+    Magic,
+    /// Tables are always 16 bytes longer than a multiple of 64, this is the length the file has
+    Length(usize),
+    /// The header doesn't fit the material in the name, or points outside the file
+    Corrupt,
+    /// The file has more bytes than are kept in memory for all tables together
+    TooLarge(u64),
+}
+
+impl Display for TableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableError::Io(e) => write!(f, "{e}"),
+            TableError::Magic => write!(f, "not a syzygy table"),
+            TableError::Length(len) => write!(f, "a length of {len} bytes means the file is cut off"),
+            TableError::Corrupt => write!(f, "the table is corrupted"),
+            TableError::TooLarge(len) => write!(f, "{len} bytes is more than the {CACHE_BYTES} bytes that tables can take up"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum TableKind {
+    /// `.rtbw`, win/draw/loss
+    Wdl,
+    /// `.rtbz`, distance to the next capture or pawn move
+    Dtz,
+}
+
+impl TableKind {
+    fn extension(self) -> &'static str {
+        match self {
+            TableKind::Wdl => "rtbw",
+            TableKind::Dtz => "rtbz",
+        }
+    }
+}
+
+/// What a lookup in a table found
+enum Lookup {
+    Found(i32),
+    /// DTZ tables only store one side to move, and this is the other one
+    OtherSide,
+}
+
+/// A table that was read, `None` if the file is missing, broken or too large so it isn't tried again
+struct CachedTable {
+    table: Option<Arc<Table>>,
+    last_used: u64,
+}
+
+/// The tables that were read by name and kind
+#[derive(Default)]
+struct LoadedTables {
+    tables: HashMap<(String, TableKind), CachedTable>,
+    /// The size of every table that is kept
+    bytes: usize,
+    /// Counts up with every lookup, to find the table that was used the longest ago
+    uses: u64,
+}
+
+impl LoadedTables {
+    fn get(&mut self, name: &str, kind: TableKind) -> Option<Option<Arc<Table>>> {
+        self.uses += 1;
+        let cached = self.tables.get_mut(&(name.to_owned(), kind))?;
+        cached.last_used = self.uses;
+        return Some(cached.table.clone());
+    }
+
+    /// Keeps the table, dropping the least recently used ones until they fit in `limit` bytes again.
+    /// Searches that still use a dropped table keep it until they're done
+    fn insert(&mut self, name: &str, kind: TableKind, table: Option<Arc<Table>>, limit: usize) {
+        self.bytes += table.as_ref().map_or(0, |table| table.data.len());
+        self.tables.insert((name.to_owned(), kind), CachedTable { table, last_used: self.uses });
+        while self.bytes > limit {
+            let Some(oldest) = self.tables.iter().filter(|(_, cached)| cached.table.is_some())
+                .min_by_key(|(_, cached)| cached.last_used).map(|(key, _)| key.clone()) else { break; };
+            let cached = self.tables.remove(&oldest).unwrap();
+            self.bytes -= cached.table.map_or(0, |table| table.data.len());
+        }
+    }
+}
+
+/// A set of Syzygy tables on disk. Files are only read the first time a position needs them
+#[derive(Default)]
+pub struct Tablebases {
+    /// The directory of every table with a WDL file, by name like `KRvK`
+    paths: HashMap<String, PathBuf>,
+    /// The most pieces of any table that was found
+    max_pieces: usize,
+    loaded: Mutex<LoadedTables>,
+}
+
+impl Tablebases {
+    /// Looks for tables in a list of directories, separated like `PATH`
+    pub fn open(paths: &str) -> io::Result<Self> {
+        let mut tablebases = Self::default();
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        for dir in paths.split(separator).filter(|dir| !dir.is_empty()) {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == TableKind::Wdl.extension())
+                    && let Some(name) = path.file_stem().and_then(|s| s.to_str())
+                    && let Some(material) = Material::from_name(name) {
+                    tablebases.max_pieces = tablebases.max_pieces.max(material.piece_count());
+                    tablebases.paths.insert(name.to_owned(), PathBuf::from(dir));
+                }
+            }
+        }
+        return Ok(tablebases);
+    }
+
+    /// How many tables were found
+    pub fn len(&self) -> usize {
+        return self.paths.len();
+    }
+
+    /// The most pieces, kings included, of any table that was found
+    pub fn max_pieces(&self) -> usize {
+        return self.max_pieces;
+    }
+
+    /// The result of the position with perfect play, or `None` if the tables for it, or for any position a capture leads to, are missing.
+    /// Only standard chess without castling rights is in the tables
+    pub fn probe_wdl(&self, state: &GameState) -> Option<Wdl> {
+        if !self.covers(state) {
+            return None;
+        }
+        return self.search(&mut state.clone(), false).map(|(wdl, _)| wdl);
+    }
+
+    /// The distance in plies to the next capture or pawn move that keeps the result, positive when winning and negative when losing.
+    /// 0 means a draw, and it's off by 100 for cursed wins and blessed losses. Returns `None` if the tables are missing
+    pub fn probe_dtz(&self, state: &GameState) -> Option<i32> {
+        if !self.covers(state) {
+            return None;
+        }
+        return self.dtz(&mut state.clone());
+    }
+
+    /// The legal moves that keep the best result. Winning moves that reset the 50 move counter soonest are kept, so the win
+    /// is actually made. Returns `None` if the position isn't in the tables
+    pub fn root_moves(&self, state: &GameState) -> Option<Vec<Move>> {
+        if !self.covers(state) {
+            return None;
+        }
+        let halfmoves = state.get_halfmove_clock() as i32;
+        let repeated = state.repetitions() > 1;
+        let mut next = state.clone();
+        let mut ranked = Vec::new();
+        for m in state.legal_moves() {
+            let undo = next.play(m);
+            let dtz = if next.get_halfmove_clock() == 0 {
+                self.search(&mut next, false).map(|(wdl, _)| (-wdl).dtz_before_zeroing())
+            } else if next.repetitions() >= 3 || next.get_halfmove_clock() >= 100 {
+                Some(0)
+            } else {
+                // One ply further than the position after the move
+                self.dtz(&mut next).map(|dtz| -dtz - dtz.signum())
+            };
+            let mates = dtz == Some(2) && next.in_check() && next.legal_moves().is_empty();
+            next.unplay(m, undo);
+            let dtz = if mates { 1 } else { dtz? };
+
+            // Wins inside the 50 move rule are ranked above the rest, and the same goes for losses that can't be saved by it
+            let rank = if dtz > 0 {
+                if dtz + halfmoves <= 99 && !repeated { MAX_DTZ - dtz } else { MAX_DTZ - 100 - (dtz + halfmoves) }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmoves < 100 { -MAX_DTZ } else { -MAX_DTZ + (-dtz + halfmoves) }
+            } else {
+                0
+            };
+            ranked.push((m, rank));
+        }
+        let best = ranked.iter().map(|(_, rank)| *rank).max()?;
+        return Some(ranked.into_iter().filter(|(_, rank)| *rank == best).map(|(m, _)| m).collect());
+    }
+
+    /// Whether the position could be in the tables, without looking at the files
+    fn covers(&self, state: &GameState) -> bool {
+        let castles = state.get_castles();
+        return state.get_variant() == Variant::Standard
+            && [Side::White, Side::Black].into_iter().all(|side| !castles[side].kingside && !castles[side].queenside)
+            && state.get_board().occupied().count_ones() as usize <= self.max_pieces;
+    }
+
+    /// Probes the position, and the captures from it because tables may store anything for positions where a capture is best.
+    /// Also returns whether the best move resets the 50 move counter, in which case DTZ tables can't be trusted.
+    /// With `zeroing_pawn_moves` pawn moves are tried as well
+    fn search(&self, state: &mut GameState, zeroing_pawn_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = state.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for m in &moves {
+            if !is_capture(state, *m) && !(zeroing_pawn_moves && is_pawn_move(state, *m)) {
+                continue;
+            }
+            searched += 1;
+            let undo = state.play(*m);
+            let result = self.search(state, false);
+            state.unplay(*m, undo);
+            let value = -result?.0;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // Positions where every move was searched aren't probed, the table could be wrong about them
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves { best } else { self.probe_wdl_table(state)? };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || no_more_moves));
+        }
+        return Some((value, false));
+    }
+
+    fn dtz(&self, state: &mut GameState) -> Option<i32> {
+        let (wdl, zeroing) = self.search(state, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(wdl.dtz_before_zeroing());
+        }
+        if let Lookup::Found(dtz) = self.probe_dtz_table(state, wdl)? {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.signum());
+        }
+
+        // The table is for the other side to move, so look one ply further for the move that gets there fastest
+        let mut best = i32::MAX;
+        for m in state.legal_moves() {
+            let zeroing = is_capture(state, m) || is_pawn_move(state, m);
+            let undo = state.play(m);
+            // For moves that reset the counter, the DTZ is the one of the move itself
+            let result = if zeroing {
+                self.search(state, false).map(|(wdl, _)| -wdl.dtz_before_zeroing())
+            } else {
+                self.dtz(state).map(|dtz| -dtz)
+            };
+            let mates = result == Some(1) && state.in_check() && state.legal_moves().is_empty();
+            state.unplay(m, undo);
+            let mut dtz = result?;
+            if mates {
+                best = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < best && dtz.signum() == wdl.signum() {
+                best = dtz;
+            }
+        }
+        // Without legal moves it's mate
+        return Some(if best == i32::MAX { -1 } else { best });
+    }
+
+    fn probe_wdl_table(&self, state: &GameState) -> Option<Wdl> {
+        // There's no table for two kings
+        if state.get_board().occupied().count_ones() == 2 {
+            return Some(Wdl::Draw);
+        }
+        let (table, black_stronger) = self.table_for(state, TableKind::Wdl)?;
+        return match table.probe(state, black_stronger, Wdl::Draw) {
+            Lookup::Found(value) => Some(Wdl::from_i32(value)),
+            Lookup::OtherSide => None,
+        };
+    }
+
+    fn probe_dtz_table(&self, state: &GameState, wdl: Wdl) -> Option<Lookup> {
+        let (table, black_stronger) = self.table_for(state, TableKind::Dtz)?;
+        return Some(table.probe(state, black_stronger, wdl));
+    }
+
+    /// The table with the material of the position, and whether black has the pieces of the side written first in its name
+    fn table_for(&self, state: &GameState, kind: TableKind) -> Option<(Arc<Table>, bool)> {
+        let material = Material::from_state(state);
+        let name = material.name();
+        if let Some(table) = self.table(&name, kind) {
+            return Some((table, false));
+        }
+        return self.table(&material.flipped().name(), kind).map(|table| (table, true));
+    }
+
+    fn table(&self, name: &str, kind: TableKind) -> Option<Arc<Table>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if let Some(table) = loaded.get(name, kind) {
+            return table;
+        }
+        let dir = self.paths.get(name)?;
+        let path = dir.join(format!("{name}.{}", kind.extension()));
+        let table = std::fs::metadata(&path).map_err(TableError::Io).and_then(|metadata| {
+            if metadata.len() > CACHE_BYTES as u64 {
+                return Err(TableError::TooLarge(metadata.len()));
+            }
+            return std::fs::read(&path).map_err(TableError::Io).and_then(|data| Table::parse(name, kind, data));
+        });
+        let table = match table {
+            Ok(table) => Some(Arc::new(table)),
+            Err(e) => {
+                warn!("Can't use \"{}\": {e}", path.display());
+                None
+            },
+        };
+        loaded.insert(name, kind, table.clone(), CACHE_BYTES);
+        return table;
+    }
+
+    /// Makes a table available without a file behind it
+    #[cfg(test)]
+    pub(crate) fn insert(&mut self, name: &str, kind: TableKind, data: Vec<u8>) {
+        let table = Table::parse(name, kind, data).unwrap();
+        self.max_pieces = self.max_pieces.max(table.piece_count);
+        self.loaded.lock().unwrap().insert(name, kind, Some(Arc::new(table)), CACHE_BYTES);
+    }
+}
+
+fn is_capture(state: &GameState, m: Move) -> bool {
+    // A pawn moving sideways onto an empty square captures en passant
+    return state.get(m.1).is_some() || (is_pawn_move(state, m) && m.0.get_x() != m.1.get_x());
+}
+
+fn is_pawn_move(state: &GameState, m: Move) -> bool {
+    return state.get(m.0).is_some_and(|p| p.ty == PieceType::Pawn);
+}
+
+/// The amount of each piece type per side, in the order of [`NAME_ORDER`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct Material(pub(super) [[u8; 6]; 2]);
+
+impl Material {
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let mut counts = [[0; 6]; 2];
+        for (side, pieces) in [white, black].into_iter().enumerate() {
+            for c in pieces.chars() {
+                let ty = PieceType::try_from_char(c.to_ascii_lowercase())?;
+                counts[side][NAME_ORDER.iter().position(|t| *t == ty).unwrap()] += 1;
+            }
+        }
+        let material = Material(counts);
+        if material.0[0][0] != 1 || material.0[1][0] != 1 || material.piece_count() > MAX_PIECES {
+            return None;
+        }
+        return Some(material);
+    }
+
+    pub(super) fn from_state(state: &GameState) -> Self {
+        let mut counts = [[0; 6]; 2];
+        for loc in Location::all() {
+            if let Some(piece) = state.get(loc) {
+                counts[(piece.side == Side::Black) as usize][NAME_ORDER.iter().position(|t| *t == piece.ty).unwrap()] += 1;
+            }
+        }
+        return Material(counts);
+    }
+
+    pub(super) fn name(&self) -> String {
+        let side = |counts: &[u8; 6]| NAME_ORDER.iter().zip(counts)
+            .flat_map(|(ty, n)| (0..*n).map(|_| ty.to_char().to_ascii_uppercase()))
+            .collect::<String>();
+        return format!("{}v{}", side(&self.0[0]), side(&self.0[1]));
+    }
+
+    pub(super) fn flipped(&self) -> Self {
+        return Material([self.0[1], self.0[0]]);
+    }
+
+    pub(super) fn piece_count(&self) -> usize {
+        return self.0.iter().flatten().map(|n| *n as usize).sum();
+    }
+
+    pub(super) fn pawns(&self, side: usize) -> usize {
+        return self.0[side][5] as usize;
+    }
+}
+
+/// The piece as it's stored in tables: pawn to king are 1 to 6, and black has bit 3 set
+fn table_piece(piece: Piece) -> u8 {
+    let ty = match piece.ty {
+        PieceType::Pawn => 1,
+        PieceType::Horsy => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+    };
+    return ty | if piece.side == Side::Black { 8 } else { 0 };
+}
+
+/// Lookup tables for turning positions into indices, the same for every table
+struct Indices {
+    /// The squares a2 to h7 as 0..48, higher for pawns closer to the edge and the first rank
+    map_pawns: [usize; 64],
+    /// The squares below the a1-h8 diagonal as 0..28
+    map_b1h1h7: [usize; 64],
+    /// The a1-d1-d4 triangle as 0..10, with the diagonal last
+    map_a1d1d4: [usize; 64],
+    /// The 462 placements of two kings, the first in the a1-d1-d4 triangle
+    map_kk: [[usize; 64]; 10],
+    /// `binomial[k][n]` ways to pick `k` of `n`
+    binomial: [[u64; 64]; MAX_PIECES],
+    /// Where the indices start for each square of the leading pawn, by the amount of leading pawns
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    /// The amount of indices per file of the leading pawn, by the amount of leading pawns
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+}
+
+/// Squares are numbered like `rank * 8 + file` in here
+fn rank(sq: usize) -> usize {
+    return sq >> 3;
+}
+
+fn file(sq: usize) -> usize {
+    return sq & 7;
+}
+
+/// Negative below the a1-h8 diagonal, positive above it
+fn off_diagonal(sq: usize) -> i32 {
+    return rank(sq) as i32 - file(sq) as i32;
+}
+
+fn indices() -> &'static Indices {
+    static INDICES: OnceLock<Indices> = OnceLock::new();
+    return INDICES.get_or_init(|| {
+        let mut indices = Indices {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+        };
+
+        for (code, sq) in (0..64).filter(|sq| off_diagonal(*sq) < 0).enumerate() {
+            indices.map_b1h1h7[sq] = code;
+        }
+
+        let triangle = (0..28).filter(|sq| file(*sq) <= 3);
+        let below = triangle.clone().filter(|sq| off_diagonal(*sq) < 0);
+        let diagonal = triangle.filter(|sq| off_diagonal(*sq) == 0);
+        for (code, sq) in below.chain(diagonal).enumerate() {
+            indices.map_a1d1d4[sq] = code;
+        }
+
+        // Kings next to each other are left out, and if the first is on the diagonal the second isn't above it.
+        // Placements with both kings on the diagonal come last
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            // b1 is 0, a1 is the first square of the diagonal
+            for s1 in (0..28).filter(|sq| indices.map_a1d1d4[*sq] == idx && (idx != 0 || *sq == 1)) {
+                for s2 in 0..64 {
+                    if file(s1).abs_diff(file(s2)) <= 1 && rank(s1).abs_diff(rank(s2)) <= 1 {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        indices.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            indices.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        indices.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                indices.binomial[k][n] = if k > 0 { indices.binomial[k - 1][n - 1] } else { 0 } + if k < n { indices.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // The pawn closest to the a or h file, and then to the second rank, leads. Any other pawn can't be on a square
+        // with a higher number than the leading one, so the leading one on a2 leaves 47 squares for them
+        for lead_pawns in 1..MAX_PIECES {
+            for f in 0..4 {
+                let mut idx = 0;
+                for r in 1..7 {
+                    let sq = r * 8 + f;
+                    if lead_pawns == 1 {
+                        let available = 47 - 2 * (f * 6 + r - 1);
+                        indices.map_pawns[sq] = available;
+                        indices.map_pawns[sq ^ 7] = available - 1;
+                    }
+                    indices.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += indices.binomial[lead_pawns - 1][indices.map_pawns[sq]];
+                }
+                indices.lead_pawns_size[lead_pawns][f] = idx;
+            }
+        }
+        return indices;
+    });
+}
+
+/// Reads the table data, failing instead of panicking when a header points outside the file
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn u8(&self, pos: usize) -> Result<u8, TableError> {
+        return self.0.get(pos).copied().ok_or(TableError::Corrupt);
+    }
+
+    fn u16_le(&self, pos: usize) -> Result<u16, TableError> {
+        return Ok(u16::from_le_bytes([self.u8(pos)?, self.u8(pos + 1)?]));
+    }
+
+    fn u32_le(&self, pos: usize) -> Result<u32, TableError> {
+        return Ok(u32::from_le_bytes([self.u8(pos)?, self.u8(pos + 1)?, self.u8(pos + 2)?, self.u8(pos + 3)?]));
+    }
+
+    fn check(&self, end: usize) -> Result<(), TableError> {
+        return if end <= self.0.len() { Ok(()) } else { Err(TableError::Corrupt) };
+    }
+}
+
+/// One compressed part of a table, for one side to move and file of the leading pawn.
+/// Positions are turned into indices, and the values are compressed by replacing pairs of symbols with new symbols,
+/// which are then Huffman coded in blocks. The fields that hold a `usize` position are offsets into the file
+#[derive(Clone, Default, Debug)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    /// About every `span` values there's an entry in the sparse index
+    span: u64,
+    num_blocks: usize,
+    /// The length in bits of the shortest Huffman code. With [`FLAG_SINGLE_VALUE`] this is the value of every position instead
+    min_sym_len: u8,
+    /// The lowest symbol of each code length, as little-endian u16s
+    lowest_sym: usize,
+    /// The pair of symbols each symbol stands for, as three bytes with 12 bits each
+    btree: usize,
+    /// The amount of values in each block, minus one, as little-endian u16s
+    block_length: usize,
+    block_length_size: usize,
+    /// Entries of a 32 bit block and a 16 bit offset into the block, for indices in the middle of each span
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    /// The lowest code of each length, left aligned in 64 bits
+    base64: Vec<u64>,
+    /// The amount of values each symbol stands for, minus one
+    symlen: Vec<u8>,
+    /// The pieces in the order they're encoded in, which also groups them
+    pieces: [u8; MAX_PIECES],
+    /// What the index of each group is multiplied by, the one after the last group is the size of the table
+    group_idx: [u64; MAX_PIECES + 1],
+    /// The amount of pieces in each group, ending with 0
+    group_len: [usize; MAX_PIECES + 1],
+    /// Where the DTZ values of losses, wins, cursed wins and blessed losses start in the map
+    map_idx: [usize; 4],
+}
+
+struct Table {
+    kind: TableKind,
+    data: Vec<u8>,
+    /// Both sides have the same pieces, in which case only white to move is stored
+    symmetric: bool,
+    has_pawns: bool,
+    /// Whether there's a piece, other than a king, that's the only one of its kind and color
+    has_unique_pieces: bool,
+    piece_count: usize,
+    /// The pawns of the side whose pawns lead, then of the other side
+    pawn_count: [usize; 2],
+    /// By side to move and the file of the leading pawn. DTZ and symmetric tables have one side, and tables without pawns one file
+    items: [[PairsData; 4]; 2],
+    /// Where the map of stored DTZ values to real ones starts
+    map: usize,
+}
+
+impl PairsData {
+    /// The amount of indices
+    fn size(&self) -> u64 {
+        return self.group_idx[self.group_len.iter().position(|len| *len == 0).unwrap()];
+    }
+
+    fn left(&self, data: &[u8], sym: usize) -> usize {
+        let lr = &data[self.btree + 3 * sym..];
+        return ((lr[1] as usize & 0xF) << 8) | lr[0] as usize;
+    }
+
+    fn right(&self, data: &[u8], sym: usize) -> usize {
+        let lr = &data[self.btree + 3 * sym..];
+        return ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+    }
+
+    fn lowest_sym(&self, data: &[u8], len: usize) -> u16 {
+        let pos = self.lowest_sym + 2 * len;
+        return u16::from_le_bytes([data[pos], data[pos + 1]]);
+    }
+
+    fn block_length(&self, data: &[u8], block: usize) -> i64 {
+        let pos = self.block_length + 2 * block;
+        return u16::from_le_bytes([data[pos], data[pos + 1]]) as i64;
+    }
+
+    /// Reads the sizes and Huffman codes, returning where they end
+    fn set_sizes(&mut self, reader: &Reader, mut pos: usize) -> Result<usize, TableError> {
+        self.flags = reader.u8(pos)?;
+        pos += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            self.min_sym_len = reader.u8(pos)?;
+            return Ok(pos + 1);
+        }
+
+        let table_size = self.size();
+        self.block_size = 1 << reader.u8(pos)?;
+        self.span = 1 << reader.u8(pos + 1)?;
+        self.sparse_index_size = table_size.div_ceil(self.span) as usize;
+        let padding = reader.u8(pos + 2)? as usize;
+        self.num_blocks = reader.u32_le(pos + 3)? as usize;
+        // Padded so the sparse index never points outside of it
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = reader.u8(pos + 7)? as usize;
+        self.min_sym_len = reader.u8(pos + 8)?;
+        pos += 9;
+        let min_sym_len = self.min_sym_len as usize;
+        if min_sym_len == 0 || max_sym_len < min_sym_len || max_sym_len > 64 {
+            return Err(TableError::Corrupt);
+        }
+
+        // The canonical code gives longer codes lower values, so each length's lowest code follows from the next length's
+        self.lowest_sym = pos;
+        let lengths = max_sym_len - min_sym_len + 1;
+        reader.check(pos + 2 * lengths)?;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let next = self.base64[i + 1].wrapping_add(self.lowest_sym(reader.0, i) as u64).wrapping_sub(self.lowest_sym(reader.0, i + 1) as u64);
+            self.base64[i] = next / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base <<= 64 - i - min_sym_len;
+        }
+        pos += 2 * lengths;
+
+        let symbols = reader.u16_le(pos)? as usize;
+        pos += 2;
+        self.btree = pos;
+        reader.check(pos + 3 * symbols)?;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(reader.0, sym, &mut visited)?;
+            }
+        }
+        return Ok(pos + 3 * symbols + (symbols & 1));
+    }
+
+    fn set_symlen(&mut self, data: &[u8], sym: usize, visited: &mut [bool]) -> Result<u8, TableError> {
+        visited[sym] = true;
+        let right = self.right(data, sym);
+        // A symbol that doesn't stand for a pair stands for its value
+        if right == 0xFFF {
+            return Ok(0);
+        }
+        let left = self.left(data, sym);
+        if left >= visited.len() || right >= visited.len() {
+            return Err(TableError::Corrupt);
+        }
+        for child in [left, right] {
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(data, child, visited)?;
+            }
+        }
+        return Ok(self.symlen[left].wrapping_add(self.symlen[right]).wrapping_add(1));
+    }
+
+    /// The value stored at `idx`
+    fn decompress(&self, data: &[u8], idx: u64) -> i32 {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return self.min_sym_len as i32;
+        }
+
+        // Start at the sparse index entry for the middle of the span, and walk through the blocks from there
+        let entry = self.sparse_index + 6 * (idx / self.span) as usize;
+        let mut block = u32::from_le_bytes(data[entry..entry + 4].try_into().unwrap()) as usize;
+        let mut offset = u16::from_le_bytes([data[entry + 4], data[entry + 5]]) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += self.block_length(data, block) + 1;
+        }
+        while offset > self.block_length(data, block) {
+            offset -= self.block_length(data, block) + 1;
+            block += 1;
+        }
+
+        // Skip symbols until the one that covers the offset
+        let min_sym_len = self.min_sym_len as usize;
+        let mut pos = self.data + block * self.block_size;
+        let mut buf = u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap());
+        pos += 8;
+        let mut buf_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf < self.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - self.base64[len]) >> (64 - len - min_sym_len)) as usize;
+            sym = (sym as u16).wrapping_add(self.lowest_sym(data, len)) as usize;
+            if offset < self.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= self.symlen[sym] as i64 + 1;
+            len += min_sym_len;
+            buf <<= len;
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64) << (64 - buf_size);
+                pos += 4;
+            }
+        }
+
+        // Then go down the pairs the symbol stands for
+        while self.symlen[sym] != 0 {
+            let left = self.left(data, sym);
+            if offset < self.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen[left] as i64 + 1;
+                sym = self.right(data, sym);
+            }
+        }
+        return self.left(data, sym) as i32;
+    }
+}
+
+impl Table {
+    fn parse(name: &str, kind: TableKind, mut data: Vec<u8>) -> Result<Self, TableError> {
+        let material = Material::from_name(name).ok_or(TableError::Corrupt)?;
+        let magic = match kind {
+            TableKind::Wdl => WDL_MAGIC,
+            TableKind::Dtz => DTZ_MAGIC,
+        };
+        if data.len() % 64 != 16 {
+            return Err(TableError::Length(data.len()));
+        }
+        if data[..4] != magic {
+            return Err(TableError::Magic);
+        }
+
+        let symmetric = material.0[0] == material.0[1];
+        let has_pawns = material.pawns(0) + material.pawns(1) > 0;
+        // The side with fewer pawns leads, because that compresses better
+        let white_leads = material.pawns(1) == 0 || (material.pawns(0) > 0 && material.pawns(1) >= material.pawns(0));
+        let pawn_count = if white_leads { [material.pawns(0), material.pawns(1)] } else { [material.pawns(1), material.pawns(0)] };
+        let mut table = Table {
+            kind,
+            data: Vec::new(),
+            symmetric,
+            has_pawns,
+            has_unique_pieces: material.0.iter().any(|side| side[1..].contains(&1)),
+            piece_count: material.piece_count(),
+            pawn_count,
+            items: Default::default(),
+            map: 0,
+        };
+
+        let reader = Reader(&data);
+        let flags = reader.u8(4)?;
+        if (flags & 2 != 0) != has_pawns || (flags & 1 != 0) == symmetric {
+            return Err(TableError::Corrupt);
+        }
+        let mut pos = 5;
+        let sides = if kind == TableKind::Wdl && !symmetric { 2 } else { 1 };
+        let files = if has_pawns { 4 } else { 1 };
+        let both_have_pawns = has_pawns && pawn_count[1] > 0;
+
+        for f in 0..files {
+            let first = reader.u8(pos)?;
+            let second = if both_have_pawns { reader.u8(pos + 1)? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            pos += 1 + both_have_pawns as usize;
+            for k in 0..table.piece_count {
+                let pieces = reader.u8(pos)?;
+                for i in 0..sides {
+                    table.items[i][f].pieces[k] = if i == 0 { pieces & 0xF } else { pieces >> 4 };
+                }
+                pos += 1;
+            }
+            for (i, order) in order.into_iter().enumerate().take(sides) {
+                table.set_groups(i, f, order);
+            }
+        }
+        pos += pos & 1;
+
+        for f in 0..files {
+            for i in 0..sides {
+                pos = table.items[i][f].set_sizes(&reader, pos)?;
+            }
+        }
+        if kind == TableKind::Dtz {
+            pos = table.set_dtz_map(&reader, pos, files)?;
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut table.items[i][f];
+                d.sparse_index = pos;
+                pos += 6 * d.sparse_index_size;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut table.items[i][f];
+                d.block_length = pos;
+                pos += 2 * d.block_length_size;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                let d = &mut table.items[i][f];
+                pos = pos.next_multiple_of(64);
+                d.data = pos;
+                pos += d.num_blocks * d.block_size;
+            }
+        }
+        reader.check(pos)?;
+
+        // Reading whole words at the end of the last block may go past the end
+        data.extend([0; 8]);
+        table.data = data;
+        return Ok(table);
+    }
+
+    /// Splits the pieces into groups that are encoded together, and works out what each group's index is multiplied by.
+    /// `order` says where the leading group and the pawns of the other side go among the rest
+    fn set_groups(&mut self, side: usize, f: usize, order: [u8; 2]) {
+        let indices = indices();
+        let d = &mut self.items[side][f];
+        let mut n = 0;
+        // The unique pieces or kings lead without pawns, and they're grouped even when they're different
+        let mut first_len: i32 = if self.has_pawns { 0 } else if self.has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let both_have_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_have_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if both_have_pawns { d.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    indices.lead_pawns_size[d.group_len[0]][f]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= indices.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= indices.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    /// DTZ tables can store an index into a map instead of the DTZ itself
+    fn set_dtz_map(&mut self, reader: &Reader, mut pos: usize, files: usize) -> Result<usize, TableError> {
+        self.map = pos;
+        for f in 0..files {
+            let d = &mut self.items[0][f];
+            if d.flags & FLAG_MAPPED == 0 {
+                continue;
+            }
+            if d.flags & FLAG_WIDE != 0 {
+                // Wide maps are u16s, counted from the start of the map
+                pos += pos & 1;
+                for i in 0..4 {
+                    d.map_idx[i] = (pos - self.map) / 2 + 1;
+                    pos += 2 * reader.u16_le(pos)? as usize + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    d.map_idx[i] = pos - self.map + 1;
+                    pos += reader.u8(pos)? as usize + 1;
+                }
+            }
+        }
+        return Ok(pos + (pos & 1));
+    }
+
+    /// Looks up the position, which has to have the material of this table.
+    /// WDL tables give the [`Wdl`] as a number, DTZ tables the DTZ in plies for the result `wdl`
+    fn probe(&self, state: &GameState, black_stronger: bool, wdl: Wdl) -> Lookup {
+        let Some((side, tb_file, idx)) = self.index(state, black_stronger) else {
+            return Lookup::OtherSide;
+        };
+        let value = self.items[side][tb_file].decompress(&self.data, idx);
+        return Lookup::Found(match self.kind {
+            TableKind::Wdl => value - 2,
+            TableKind::Dtz => self.map_dtz(tb_file, value, wdl),
+        });
+    }
+
+    /// Where the position is stored: the side to move and the file of the leading pawn that pick the part, and the index in it.
+    /// `None` if it's a DTZ table for the other side to move
+    fn index(&self, state: &GameState, black_stronger: bool) -> Option<(usize, usize, u64)> {
+        let indices = indices();
+        // Tables have the pieces of the first side of their name as white, and symmetric ones only have white to move.
+        // Anything else is looked up with the colors swapped and the board mirrored
+        let flip = black_stronger || (self.symmetric && state.to_move == Side::Black);
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ (state.to_move == Side::Black) as usize;
+
+        let occupied: Vec<(usize, u8)> = (0..64)
+            .filter_map(|sq| state.get(Location::new(file(sq) as u8, rank(sq) as u8)).map(|p| (sq, table_piece(p))))
+            .collect();
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut tb_file = 0;
+
+        // The leading pawns come first, and the one closest to the edge decides which file's part of the table is used
+        if self.has_pawns {
+            let pawn = self.items[0][0].pieces[0] ^ flip_color;
+            for (sq, piece) in &occupied {
+                if *piece == pawn {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_pawns = size;
+            let leading = (0..lead_pawns).max_by_key(|i| indices.map_pawns[squares[*i]]).unwrap();
+            squares.swap(0, leading);
+            tb_file = file(squares[0]).min(7 - file(squares[0]));
+        }
+
+        if self.kind == TableKind::Dtz {
+            let flags = self.items[0][tb_file].flags;
+            if (flags & FLAG_STM) as usize != stm && !(self.symmetric && !self.has_pawns) {
+                return None;
+            }
+        }
+
+        let lead_pawn = if self.has_pawns { self.items[0][0].pieces[0] ^ flip_color } else { 0 };
+        for (sq, piece) in &occupied {
+            if self.has_pawns && *piece == lead_pawn {
+                continue;
+            }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = piece ^ flip_color;
+            size += 1;
+        }
+
+        let side = if self.kind == TableKind::Wdl { stm } else { 0 };
+        let d = &self.items[side][tb_file];
+
+        // Put the pieces in the order of the table
+        for i in lead_pawns..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The leading piece goes on the a to d files
+        if file(squares[0]) > 3 {
+            for sq in &mut squares[..size] {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = indices.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|sq| indices.map_pawns[*sq]);
+            for (i, sq) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += indices.binomial[i][indices.map_pawns[*sq]];
+            }
+        } else {
+            // Without pawns it also goes on the first four ranks, and below the a1-h8 diagonal
+            if rank(squares[0]) > 3 {
+                for sq in &mut squares[..size] {
+                    *sq ^= 56;
+                }
+            }
+            // The first piece of the leading group that's off the diagonal decides
+            let off = (0..d.group_len[0]).find(|i| off_diagonal(squares[*i]) != 0);
+            if let Some(i) = off && off_diagonal(squares[i]) > 0 {
+                for sq in &mut squares[i..size] {
+                    *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                }
+            }
+
+            if self.has_unique_pieces {
+                // Three pieces are encoded together. Squares after earlier pieces of the group are moved down, since they can't be taken twice
+                let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                idx = if off_diagonal(s0) != 0 {
+                    (indices.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + rank(s0) * 28 + indices.map_b1h1h7[s1]) * 62 + s2 - adjust2
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(s0) * 7 * 28 + (rank(s1) - adjust1) * 28 + indices.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) * 7 * 6 + (rank(s1) - adjust1) * 6 + (rank(s2) - adjust2)
+                } as u64;
+            } else {
+                idx = indices.map_kk[indices.map_a1d1d4[squares[0]]][squares[1]] as u64;
+            }
+        }
+
+        // The remaining groups, each as a combination of the squares that are still free
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[group_start..group_start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|s| sq > **s).count();
+                // Pawns can't be on the first or last rank
+                n += indices.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start += len;
+            next += 1;
+        }
+        return Some((side, tb_file, idx));
+    }
+
+    /// Turns a stored DTZ value into plies
+    fn map_dtz(&self, tb_file: usize, mut value: i32, wdl: Wdl) -> i32 {
+        let d = &self.items[0][tb_file];
+        if d.flags & FLAG_MAPPED != 0 {
+            // Losses, wins, cursed wins and blessed losses each have their own map
+            let map = match wdl {
+                Wdl::Win | Wdl::Draw => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+            };
+            let idx = d.map_idx[map] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                let pos = self.map + 2 * idx;
+                u16::from_le_bytes([self.data[pos], self.data[pos + 1]]) as i32
+            } else {
+                self.data[self.map + idx] as i32
+            };
+        }
+        // Some tables count moves instead of plies
+        let in_moves = match wdl {
+            Wdl::Win => d.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => d.flags & FLAG_LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        if in_moves {
+            value *= 2;
+        }
+        return value + 1;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, sync::Arc};
+
+    use crate::{chess::{Board, GameState, Move, Variant, Location, Piece, PieceType, Side, test::GPU_ADAPTER}, gpu::{init_gpu_evaluator, GpuAllocations}, gpu_tree::GpuTree};
+
+    use super::{indices, LoadedTables, Material, Table, TableKind, Tablebases, Wdl};
+
+    /// The start of a table: the magic, the flags, and for each file the order of the groups and a byte per piece for both sides
+    fn table_header(kind: TableKind, flags: u8, files: &[&[u8]]) -> Vec<u8> {
+        let mut data = match kind {
+            TableKind::Wdl => super::WDL_MAGIC.to_vec(),
+            TableKind::Dtz => super::DTZ_MAGIC.to_vec(),
+        };
+        data.push(flags);
+        for file in files {
+            data.extend(*file);
+        }
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+        return data;
+    }
+
+    /// Files are 16 bytes longer than a multiple of 64
+    fn pad_table(data: &mut Vec<u8>) {
+        while data.len() < 64 || data.len() % 64 != 16 {
+            data.push(0);
+        }
+    }
+
+    /// A table where every position has the same value. That skips the compression, but not finding the table or the captures
+    pub(crate) fn single_value_table(kind: TableKind, pieces: &[u8], values: &[u8]) -> Vec<u8> {
+        // The sides have different pieces, and there aren't any pawns. The leading group goes first
+        let mut data = table_header(kind, 1, &[&[&[0], pieces].concat()]);
+        for value in values {
+            data.extend([super::FLAG_SINGLE_VALUE, *value]);
+        }
+        pad_table(&mut data);
+        return data;
+    }
+
+    /// One part of a table after [`compress`], in the pieces the file keeps apart
+    #[derive(Default)]
+    struct Part {
+        sizes: Vec<u8>,
+        sparse_index: Vec<u8>,
+        block_lengths: Vec<u8>,
+        data: Vec<u8>,
+    }
+
+    /// Compresses the values of a part like the generator does, if less cleverly: the most common pair of neighbouring symbols
+    /// is replaced by a new symbol a few times over, and then every symbol gets a canonical Huffman code and they're packed in blocks
+    fn compress(flags: u8, values: &[u8]) -> Part {
+        if values.iter().all(|value| *value == values[0]) {
+            return Part { sizes: vec![flags | super::FLAG_SINGLE_VALUE, values[0]], ..Default::default() };
+        }
+
+        // A symbol is a value with 0xFFF on the right, or a pair of other symbols
+        let mut symbols: Vec<[usize; 2]> = Vec::new();
+        let mut lens: Vec<usize> = Vec::new();
+        let mut leaves = HashMap::new();
+        let mut seq: Vec<usize> = values.iter().map(|value| *leaves.entry(*value).or_insert_with(|| {
+            symbols.push([*value as usize, 0xFFF]);
+            lens.push(1);
+            symbols.len() - 1
+        })).collect();
+        for _ in 0..6 {
+            let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+            for pair in seq.windows(2) {
+                *counts.entry((pair[0], pair[1])).or_default() += 1;
+            }
+            let Some((&(left, right), &count)) = counts.iter().max_by_key(|(pair, count)| (**count, Reverse(**pair))) else { break; };
+            if count < 2 {
+                break;
+            }
+            symbols.push([left, right]);
+            lens.push(lens[left] + lens[right]);
+            let mut paired = Vec::new();
+            let mut i = 0;
+            while i < seq.len() {
+                if i + 1 < seq.len() && seq[i] == left && seq[i + 1] == right {
+                    paired.push(symbols.len() - 1);
+                    i += 2;
+                } else {
+                    paired.push(seq[i]);
+                    i += 1;
+                }
+            }
+            seq = paired;
+        }
+
+        // Huffman code lengths of the symbols that are left in the sequence, the others only appear in pairs
+        let mut freq = vec![0; symbols.len()];
+        for sym in &seq {
+            freq[*sym] += 1;
+        }
+        let coded: Vec<usize> = (0..symbols.len()).filter(|sym| freq[*sym] > 0).collect();
+        let mut parent = vec![0; coded.len()];
+        let mut heap: BinaryHeap<_> = coded.iter().enumerate().map(|(node, sym)| Reverse((freq[*sym], node))).collect();
+        while heap.len() > 1 {
+            let Reverse((freq1, node1)) = heap.pop().unwrap();
+            let Reverse((freq2, node2)) = heap.pop().unwrap();
+            parent.push(0);
+            parent[node1] = parent.len() - 1;
+            parent[node2] = parent.len() - 1;
+            heap.push(Reverse((freq1 + freq2, parent.len() - 1)));
+        }
+        let root = parent.len() - 1;
+        let mut code_len = vec![0; symbols.len()];
+        for (mut node, sym) in coded.iter().enumerate() {
+            while node != root {
+                node = parent[node];
+                code_len[*sym] += 1;
+            }
+            code_len[*sym] = code_len[*sym].max(1);
+        }
+
+        // Symbols are numbered from the longest codes to the shortest, and the ones without a code come last
+        let mut order: Vec<usize> = (0..symbols.len()).collect();
+        order.sort_by_key(|sym| (freq[*sym] == 0, Reverse(code_len[*sym]), *sym));
+        let mut number = vec![0; symbols.len()];
+        for (i, sym) in order.iter().enumerate() {
+            number[*sym] = i;
+        }
+        let min_len = coded.iter().map(|sym| code_len[*sym]).min().unwrap();
+        let max_len = coded.iter().map(|sym| code_len[*sym]).max().unwrap();
+        let lowest: Vec<usize> = (min_len..=max_len).map(|len| coded.iter().filter(|sym| code_len[**sym] > len).count()).collect();
+        let mut base = vec![0; lowest.len()];
+        for i in (0..lowest.len() - 1).rev() {
+            base[i] = (base[i + 1] + lowest[i] - lowest[i + 1]) / 2;
+        }
+
+        // Blocks hold whole symbols, and the sparse index points at the middle of every span
+        let block_bits = 32 * 8;
+        let span: usize = 64;
+        let mut blocks: Vec<(Vec<u8>, usize, usize)> = vec![(vec![0; block_bits / 8], 0, 0)];
+        for sym in &seq {
+            let len = code_len[*sym];
+            let code = base[len - min_len] + number[*sym] - lowest[len - min_len];
+            let (_, bits, count) = blocks.last().unwrap();
+            if bits + len > block_bits || count + lens[*sym] > 1 << 16 {
+                blocks.push((vec![0; block_bits / 8], 0, 0));
+            }
+            let (block, bits, count) = blocks.last_mut().unwrap();
+            for bit in 0..len {
+                if (code >> (len - 1 - bit)) & 1 != 0 {
+                    block[(*bits + bit) / 8] |= 0x80 >> ((*bits + bit) % 8);
+                }
+            }
+            *bits += len;
+            *count += lens[*sym];
+        }
+
+        let mut part = Part::default();
+        part.sizes.extend([flags, block_bits.trailing_zeros() as u8 - 3, span.trailing_zeros() as u8, 0]);
+        part.sizes.extend((blocks.len() as u32).to_le_bytes());
+        part.sizes.extend([max_len as u8, min_len as u8]);
+        for sym in &lowest {
+            part.sizes.extend((*sym as u16).to_le_bytes());
+        }
+        part.sizes.extend((symbols.len() as u16).to_le_bytes());
+        for sym in &order {
+            let [left, right] = symbols[*sym];
+            let (left, right) = if right == 0xFFF { (left, right) } else { (number[left], number[right]) };
+            part.sizes.extend([left as u8, ((left >> 8) | ((right & 0xF) << 4)) as u8, (right >> 4) as u8]);
+        }
+        if symbols.len() % 2 == 1 {
+            part.sizes.push(0);
+        }
+
+        let mut start = 0;
+        let starts: Vec<usize> = blocks.iter().map(|(_, _, count)| { start += count; start - count }).collect();
+        for mid in (0..values.len().div_ceil(span)).map(|i| i * span + span / 2) {
+            let block = starts.iter().rposition(|start| *start <= mid).unwrap();
+            part.sparse_index.extend((block as u32).to_le_bytes());
+            part.sparse_index.extend(u16::try_from(mid - starts[block]).unwrap().to_le_bytes());
+        }
+        for (block, _, count) in blocks {
+            part.block_lengths.extend((count as u16 - 1).to_le_bytes());
+            part.data.extend(block);
+        }
+        return part;
+    }
+
+    /// Puts a table together from its header, the parts by file and then by side to move, and the DTZ map
+    fn compressed_table(kind: TableKind, header: &[u8], parts: &[Part], dtz_map: &[u8]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        for part in parts {
+            data.extend(&part.sizes);
+        }
+        if kind == TableKind::Dtz {
+            data.extend(dtz_map);
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+        for part in parts {
+            data.extend(&part.sparse_index);
+        }
+        for part in parts {
+            data.extend(&part.block_lengths);
+        }
+        for part in parts {
+            data.resize(data.len().next_multiple_of(64), 0);
+            data.extend(&part.data);
+        }
+        pad_table(&mut data);
+        return data;
+    }
+
+    /// Every legal position with the material of `name`, for both sides to move
+    fn positions(name: &str) -> Vec<GameState> {
+        let material = Material::from_name(name).unwrap();
+        let pieces: Vec<Piece> = [Side::White, Side::Black].into_iter().enumerate().flat_map(|(i, side)| {
+            return super::NAME_ORDER.iter().zip(material.0[i]).flat_map(move |(ty, n)| (0..n).map(move |_| Piece::new(side, *ty)));
+        }).collect();
+        let mut states = Vec::new();
+        'placements: for placement in 0..64usize.pow(pieces.len() as u32) {
+            let mut state = GameState::default();
+            for (i, piece) in pieces.iter().enumerate() {
+                let square = placement / 64usize.pow(i as u32) % 64;
+                let loc = Location::new(square as u8 & 7, square as u8 >> 3);
+                if state.get(loc).is_some() || (piece.ty == PieceType::Pawn && (square >> 3 == 0 || square >> 3 == 7)) {
+                    continue 'placements;
+                }
+                state.set(loc, Some(*piece));
+            }
+            for to_move in [Side::White, Side::Black] {
+                state.to_move = to_move;
+                if state.get_board().is_valid(to_move.opposite()) {
+                    states.push(state.clone());
+                }
+            }
+        }
+        return states;
+    }
+
+    /// Writes a compressed table of `name`, where every position of the material has the value `value` gives it.
+    /// Indices that no position has get the value before them, like the generator fills in what doesn't matter.
+    /// Checks that every position reads back its own value, and returns the file
+    fn write_table(name: &str, kind: TableKind, header: &[u8], flags: &[u8], dtz_map: &[u8], value: impl Fn(&GameState) -> u8) -> Vec<u8> {
+        // The same header with every part a single value lays out the indices already
+        let shape: Vec<_> = flags.iter().map(|flags| Part { sizes: vec![flags | super::FLAG_SINGLE_VALUE, 0], ..Default::default() }).collect();
+        let shape = Table::parse(name, kind, compressed_table(kind, header, &shape, dtz_map)).unwrap();
+        let sides = flags.len() / if shape.has_pawns { 4 } else { 1 };
+        let mut values: Vec<Vec<Option<u8>>> = flags.iter().enumerate().map(|(i, _)| vec![None; shape.items[i % sides][i / sides].size() as usize]).collect();
+
+        let positions: Vec<_> = positions(name).into_iter().filter_map(|state| {
+            let (side, file, idx) = shape.index(&state, false)?;
+            let value = value(&state);
+            let stored = values[file * sides + side][idx as usize].get_or_insert(value);
+            assert_eq!(*stored, value, "{} shares its index with a position of another value", state.to_fen());
+            return Some((state, side, file, idx, value));
+        }).collect();
+
+        let parts: Vec<_> = flags.iter().zip(values).map(|(flags, values)| {
+            let values: Vec<_> = values.iter().scan(0, |last, value| { *last = value.unwrap_or(*last); Some(*last) }).collect();
+            return compress(*flags, &values);
+        }).collect();
+        let data = compressed_table(kind, header, &parts, dtz_map);
+        let table = Table::parse(name, kind, data.clone()).unwrap();
+        for (state, side, file, idx, value) in positions {
+            assert_eq!(table.items[side][file].decompress(&table.data, idx), value as i32, "{}", state.to_fen());
+        }
+        return data;
+    }
+
+    #[test]
+    fn index_tables() {
+        let indices = indices();
+        // Every placement of two kings is there once
+        let mut kk: Vec<_> = indices.map_kk.iter().flatten().copied().filter(|code| *code != 0).collect();
+        kk.sort();
+        kk.dedup();
+        assert_eq!(kk.len(), 461);
+        assert_eq!(*kk.last().unwrap(), 461);
+
+        assert_eq!(indices.binomial[2][5], 10);
+        assert_eq!(indices.binomial[5][63], 7028847);
+        // a2 leads over every other pawn
+        assert_eq!(indices.map_pawns[8], 47);
+        assert_eq!(indices.map_pawns[15], 46);
+        assert_eq!(indices.lead_pawns_size[1][0], 6);
+        // With two leading pawns, the one on a2 leaves 47 squares for the other
+        assert_eq!(indices.lead_pawn_idx[2][16], 47);
+    }
+
+    #[test]
+    fn names() {
+        let state = GameState::from_fen("8/8/4k3/8/2n5/8/1QR5/4K3 w - - 0 1");
+        let material = Material::from_state(&state);
+        assert_eq!(material.name(), "KQRvKN");
+        assert_eq!(material.flipped().name(), "KNvKQR");
+        assert_eq!(Material::from_name("KQRvKN"), Some(material));
+        assert_eq!(Material::from_name("KQRvKN").unwrap().piece_count(), 5);
+        assert_eq!(Material::from_name("KQvQ"), None);
+        assert_eq!(Material::from_name("KQQQQvK"), Some(Material([[1, 4, 0, 0, 0, 0], [1, 0, 0, 0, 0, 0]])));
+        assert_eq!(Material::from_name("KRRRRvKRR"), None);
+    }
+
+    #[test]
+    fn probe() {
+        let mut tablebases = Tablebases::default();
+        // White king and queen against the black king, a win for white and a loss for black whoever moves
+        tablebases.insert("KQvK", TableKind::Wdl, single_value_table(TableKind::Wdl, &[0x66, 0x55, 0xEE], &[4, 0]));
+        tablebases.insert("KQvK", TableKind::Dtz, single_value_table(TableKind::Dtz, &[0x66, 0x55, 0xEE], &[5]));
+
+        let wins = GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 0 1");
+        assert_eq!(tablebases.probe_wdl(&wins), Some(Wdl::Win));
+        // The colors are swapped to find the table
+        let loses = GameState::from_fen("q3k3/8/8/8/4K3/8/8/8 w - - 0 1");
+        assert_eq!(tablebases.probe_wdl(&loses), Some(Wdl::Loss));
+        // Taking the queen leaves two kings, which is a draw
+        let takes = GameState::from_fen("8/8/8/8/8/8/3kQ3/7K b - - 0 1");
+        assert_eq!(tablebases.probe_wdl(&takes), Some(Wdl::Draw));
+
+        assert_eq!(tablebases.probe_dtz(&wins), Some(11));
+        assert_eq!(tablebases.probe_dtz(&takes), Some(0));
+
+        // Missing tables and too many pieces
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1")), None);
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/QQ2K3 w - - 0 1")), None);
+    }
+
+    #[test]
+    fn cache_limit() {
+        let table = |values: &[u8]| Some(Arc::new(Table::parse("KQvK", TableKind::Wdl, single_value_table(TableKind::Wdl, &[0x66, 0x55, 0xEE], values)).unwrap()));
+        let mut loaded = LoadedTables::default();
+        let size = table(&[4, 0]).unwrap().data.len();
+        loaded.insert("KQvK", TableKind::Wdl, table(&[4, 0]), 2 * size);
+        loaded.insert("KRvK", TableKind::Wdl, table(&[4, 0]), 2 * size);
+        // Missing tables don't take up space
+        loaded.insert("KBvK", TableKind::Wdl, None, 2 * size);
+        assert!(loaded.get("KQvK", TableKind::Wdl).is_some());
+
+        // KRvK was used longest ago, so it makes room
+        loaded.insert("KNvK", TableKind::Wdl, table(&[2, 2]), 2 * size);
+        assert_eq!(loaded.bytes, 2 * size);
+        assert!(loaded.get("KRvK", TableKind::Wdl).is_none());
+        assert!(loaded.get("KQvK", TableKind::Wdl).is_some_and(|table| table.is_some()));
+        assert!(loaded.get("KNvK", TableKind::Wdl).is_some_and(|table| table.is_some()));
+        assert!(loaded.get("KBvK", TableKind::Wdl).is_some_and(|table| table.is_none()));
+    }
+
+    #[test]
+    fn root_moves() {
+        let mut tablebases = Tablebases::default();
+        tablebases.insert("KQvK", TableKind::Wdl, single_value_table(TableKind::Wdl, &[0x66, 0x55, 0xEE], &[4, 0]));
+        tablebases.insert("KQvK", TableKind::Dtz, single_value_table(TableKind::Dtz, &[0x66, 0x55, 0xEE], &[5]));
+
+        // Every move wins, except for putting the queen next to the black king on d4 or e5
+        let state = GameState::from_fen("8/8/8/3k4/8/8/8/Q6K w - - 0 1");
+        let moves = tablebases.root_moves(&state).unwrap();
+        assert_eq!(moves.len(), state.legal_moves().len() - 2);
+        assert!(!moves.contains(&Move::from_str("a1d4")) && !moves.contains(&Move::from_str("a1e5")));
+
+        // Taking the queen is the only way for black to draw
+        let state = GameState::from_fen("8/8/8/8/8/8/3kQ3/7K b - - 0 1");
+        assert_eq!(tablebases.root_moves(&state), Some(vec![Move::from_str("d2e2")]));
+    }
+
+    /// The result of a KQvK or KRvK position: white always wins, unless black is stalemated or can take the piece
+    fn kqk_wdl(state: &GameState) -> Wdl {
+        if state.to_move == Side::White {
+            return Wdl::Win;
+        }
+        let moves = state.legal_moves();
+        if moves.is_empty() {
+            return if state.in_check() { Wdl::Loss } else { Wdl::Draw };
+        }
+        return if moves.iter().any(|m| state.get(m.1).is_some()) { Wdl::Draw } else { Wdl::Loss };
+    }
+
+    /// How many squares apart the kings are, which stays the same when the board is turned or mirrored
+    fn king_distance(state: &GameState) -> u8 {
+        let kings: Vec<_> = Location::all().filter(|loc| state.get(*loc).is_some_and(|p| p.ty == PieceType::King)).collect();
+        return kings[0].get_x().abs_diff(kings[1].get_x()).max(kings[0].get_y().abs_diff(kings[1].get_y()));
+    }
+
+    /// The win map of [`compressed_dtz`], in moves
+    const KQK_DTZ_MAP: [u8; 8] = [0, 1, 2, 3, 5, 8, 13, 21];
+
+    /// Mates in one are stored as 0, which is mapped to a DTZ of one ply. Other positions get a made up entry in the map
+    fn kqk_dtz(state: &GameState) -> u8 {
+        let mut next = state.clone();
+        for m in state.legal_moves() {
+            let undo = next.play(m);
+            let mates = next.in_check() && next.legal_moves().is_empty();
+            next.unplay(m, undo);
+            if mates {
+                return 0;
+            }
+        }
+        return 1 + king_distance(state) % 7;
+    }
+
+    #[test]
+    fn compressed_wdl() {
+        let header = table_header(TableKind::Wdl, 1, &[&[0, 0x66, 0x55, 0xEE]]);
+        let data = write_table("KQvK", TableKind::Wdl, &header, &[0, 0], &[], |state| (kqk_wdl(state) as i32 + 2) as u8);
+        let mut tablebases = Tablebases::default();
+        tablebases.insert("KQvK", TableKind::Wdl, data);
+
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 0 1")), Some(Wdl::Win));
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 b - - 0 1")), Some(Wdl::Loss));
+        // Stalemate, mate, and a queen that can be taken
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1")), Some(Wdl::Draw));
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1")), Some(Wdl::Loss));
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("8/8/8/8/8/8/3kQ3/7K b - - 0 1")), Some(Wdl::Draw));
+        // The colors are swapped to find the table
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("8/8/8/8/8/6q1/8/6k1 w - - 0 1")), Some(Wdl::Draw));
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("1q2k3/8/8/8/4K3/8/8/8 w - - 0 1")), Some(Wdl::Loss));
+    }
+
+    #[test]
+    fn compressed_dtz() {
+        // The map has a section for wins, losses, cursed wins and blessed losses, which starts with its length
+        let mut dtz_map = vec![KQK_DTZ_MAP.len() as u8];
+        dtz_map.extend(KQK_DTZ_MAP);
+        dtz_map.extend([0, 0, 0]);
+        let header = table_header(TableKind::Dtz, 1, &[&[0, 0x66, 0x55, 0xEE]]);
+        let data = write_table("KQvK", TableKind::Dtz, &header, &[super::FLAG_MAPPED], &dtz_map, kqk_dtz);
+        let mut tablebases = Tablebases::default();
+        tablebases.insert("KQvK", TableKind::Wdl, single_value_table(TableKind::Wdl, &[0x66, 0x55, 0xEE], &[4, 0]));
+        tablebases.insert("KQvK", TableKind::Dtz, data);
+
+        // Mate in one, and the kings 4 squares apart which is the 5th entry of the map
+        assert_eq!(tablebases.probe_dtz(&GameState::from_fen("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1")), Some(1));
+        assert_eq!(tablebases.probe_dtz(&GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 0 1")), Some(KQK_DTZ_MAP[5] as i32 * 2 + 1));
+        // Black to move isn't stored, so it's found one ply further on. The only move is Kb8, and then white mates in one
+        assert_eq!(tablebases.probe_dtz(&GameState::from_fen("k7/8/1K6/8/8/8/8/6Q1 b - - 0 1")), Some(-2));
+    }
+
+    #[test]
+    fn compressed_pawns() {
+        // Every file of the leading pawn has its own parts, and the values are made up but stay the same when the board is mirrored
+        let file: &[u8] = &[0, 0x11, 0x66, 0xEE];
+        let header = table_header(TableKind::Wdl, 3, &[file, file, file, file]);
+        write_table("KPvK", TableKind::Wdl, &header, &[0; 8], &[], |state| {
+            let pawn = Location::all().find(|loc| state.get(*loc).is_some_and(|p| p.ty == PieceType::Pawn)).unwrap();
+            return (pawn.get_y() + king_distance(state) + state.to_move as u8) % 5;
+        });
+    }
+
+    #[tokio::test]
+    async fn variant_leaves() {
+        let mut tablebases = Tablebases::default();
+        tablebases.insert("KQvK", TableKind::Wdl, single_value_table(TableKind::Wdl, &[0x66, 0x55, 0xEE], &[4, 0]));
+
+        // The leaves have tablebase material, but in the variants the king on the hill or the checks decide instead
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let mut allocator = GpuAllocations::init(engine.device.clone());
+        for (variant, fen) in [(Variant::Standard, "k7/8/8/8/8/8/8/1Q5K w - - 0 1"), (Variant::KingOfTheHill, "k7/8/8/8/8/8/8/1Q5K w - - 0 1"), (Variant::ThreeCheck, "k7/8/8/8/8/8/8/1Q5K w - - 2+0 0 1")] {
+            let mut state = GameState::from_fen(fen);
+            state.set_variant(variant);
+            let mut tree = GpuTree::new(&engine, &mut allocator);
+            tree.init_layer_from_state(&state);
+            tree.expand_last_layer().await;
+            let hits = tree.probe_last_layer(3, |state| tablebases.probe_wdl(state)).await;
+            let expected = if variant == Variant::Standard { state.legal_moves().len() as u64 } else { 0 };
+            assert_eq!(hits, expected, "{variant}");
+        }
+    }
+
+    /// Reads the real 3 piece tables, which have to be put in `tests/syzygy` first. They're the KQvK, KRvK and KPvK `.rtbw` and
+    /// `.rtbz` files from https://tablebase.lichess.ovh/tables/standard/3-4-5/, a few KB each
+    #[test]
+    #[ignore = "needs the 3 piece Syzygy files in tests/syzygy"]
+    fn real_tables() {
+        let tablebases = Tablebases::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy")).unwrap();
+        assert_eq!(tablebases.len(), 3);
+        assert_eq!(tablebases.max_pieces(), 3);
+
+        // Every position is read like the cpu works it out, and the longest wins take as long as the longest mates
+        for (name, longest) in [("KQvK", 19), ("KRvK", 31)] {
+            let mut dtz = Vec::new();
+            for state in positions(name) {
+                assert_eq!(tablebases.probe_wdl(&state), Some(kqk_wdl(&state)), "{}", state.to_fen());
+                if state.to_move == Side::White {
+                    dtz.push(tablebases.probe_dtz(&state).unwrap());
+                }
+            }
+            assert_eq!(dtz.iter().max(), Some(&longest), "{name}");
+            assert!(dtz.iter().all(|dtz| *dtz > 0), "{name}");
+        }
+
+        // The king in front of the pawn wins with the opposition, and a rook pawn doesn't get past the king in the corner
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1")), Some(Wdl::Draw));
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1")), Some(Wdl::Loss));
+        assert_eq!(tablebases.probe_wdl(&GameState::from_fen("k7/8/8/8/8/8/P7/K7 w - - 0 1")), Some(Wdl::Draw));
+        assert_eq!(tablebases.probe_dtz(&GameState::from_fen("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1")), Some(0));
+        // The pawn runs away from the king, and pushing it is the zeroing move
+        let state = GameState::from_fen("8/8/8/8/8/8/4P3/k3K3 w - - 0 1");
+        assert_eq!(tablebases.probe_wdl(&state), Some(Wdl::Win));
+        assert_eq!(tablebases.probe_dtz(&state), Some(1));
+        assert!(tablebases.root_moves(&state).unwrap().contains(&Move::from_str("e2e4")));
+    }
+}
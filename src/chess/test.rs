@@ -3,7 +3,7 @@ use std::fmt;
 use wgpu::Adapter;
 use pollster::FutureExt as _;
 
-use crate::{gpu::{GpuGlobalData, init_gpu_evaluator, init_adapter, GpuAllocations}, chess::{StandardBoard, GameState, Side, EvalScore, MAX_MOVES}, gpu_tree::GpuTree};
+use crate::{gpu::{GpuGlobalData, init_gpu_evaluator, init_adapter, GpuAllocations}, chess::{StandardBoard, GameState, Side, EvalScore, MAX_MOVES, Location, Variant, PieceType, syzygy::Wdl}, gpu_tree::GpuTree};
 
 use super::{Board, board::convert, GpuBoard};
 
@@ -36,9 +36,33 @@ impl TestEngine for GpuTester {
     }
 }
 
+struct GpuLegalTester;
+
+impl TestEngine for GpuLegalTester {
+    type Out = GpuBoard;
+
+    async fn get_moves(board_in: GameState) -> Vec<Self::Out> {
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let mut allocator = GpuAllocations::init(engine.device.clone());
+        let mut tree = GpuTree::new(&engine, &mut allocator);
+        tree.set_legal_only(true);
+        tree.init_layer_from_state(&board_in);
+        tree.expand_last_layer().await;
+        return tree.view_boards_last().await.cast_t().into_iter().map(|b| b.clone()).collect();
+    }
+}
+
 async fn assert_moves(start: &'static str, expected_moves: &[&'static str]) {
+    assert_moves_with::<GpuTester>(start, expected_moves).await;
+}
+
+async fn assert_legal_moves(start: &'static str, expected_moves: &[&'static str]) {
+    assert_moves_with::<GpuLegalTester>(start, expected_moves).await;
+}
+
+async fn assert_moves_with<T: TestEngine<Out = GpuBoard>>(start: &'static str, expected_moves: &[&'static str]) {
     let start_board = GameState::from_fen(start);
-    let res = GpuTester::get_moves(start_board).await;
+    let res = T::get_moves(start_board).await;
     let expected_boards: Vec<_> = expected_moves.iter().map(|i| convert(&GameState::from_fen(i).get_board())).collect();
     let mut err_str = String::new();
     for i in &expected_boards {
@@ -58,12 +82,26 @@ async fn assert_moves(start: &'static str, expected_moves: &[&'static str]) {
     }
 }
 
+async fn assert_move_absent(start: &'static str, unexpected_move: &'static str) {
+    let res = GpuTester::get_moves(GameState::from_fen(start)).await;
+    let unexpected: GpuBoard = convert(&GameState::from_fen(unexpected_move).get_board());
+    if res.contains(&unexpected) {
+        panic!("The following board shouldn't be a valid move: \n{unexpected}");
+    }
+}
+
+async fn find_child(start: &'static str, child: &'static str) -> GpuBoard {
+    let res = GpuTester::get_moves(GameState::from_fen(start)).await;
+    let child: GpuBoard = convert(&GameState::from_fen(child).get_board());
+    return *res.iter().find(|b| **b == child).expect("Child board should be present");
+}
+
 #[tokio::test]
 async fn pawn_basic() {
     assert_moves(
-        "8/8/8/8/8/P/8/8 w KQkq - 0 1",
+        "8/8/8/8/8/P7/8/8 w KQkq - 0 1",
         &[
-            "8/8/8/8/P/8/8/8 b KQkq - 0 1"
+            "8/8/8/8/P7/8/8/8 b KQkq - 0 1"
         ]
     ).await;
 }
@@ -71,10 +109,10 @@ async fn pawn_basic() {
 #[tokio::test]
 async fn pawn_double() {
     assert_moves(
-        "8/8/8/8/8/8/P/8 w KQkq - 0 1",
+        "8/8/8/8/8/8/P7/8 w KQkq - 0 1",
         &[
-            "8/8/8/8/8/P/8/8 b KQkq - 0 1",
-            "8/8/8/8/P/8/8/8 b KQkq - 0 1"
+            "8/8/8/8/8/P7/8/8 b KQkq - 0 1",
+            "8/8/8/8/P7/8/8/8 b KQkq - 0 1"
         ]
     ).await;
 }
@@ -82,7 +120,7 @@ async fn pawn_double() {
 #[tokio::test]
 async fn pawn_block() {
     assert_moves(
-        "8/8/8/8/8/n/P/8 w KQkq - 0 1",
+        "8/8/8/8/8/n7/P7/8 w KQkq - 0 1",
         &[
         ]
     ).await;
@@ -91,7 +129,7 @@ async fn pawn_block() {
 #[tokio::test]
 async fn pawn_capture() {
     assert_moves(
-        "8/8/8/8/1n6/P/8/8 w KQkq - 0 1",
+        "8/8/8/8/1n6/P7/8/8 w KQkq - 0 1",
         &[
             "8/8/8/8/1P6/8/8/8 b KQkq - 0 1",
             "8/8/8/8/Pn6/8/8/8 b KQkq - 0 1",
@@ -104,10 +142,10 @@ async fn pawn_promote() {
     assert_moves(
         "8/P7/8/8/8/8/8/8 w KQkq - 0 1",
         &[
-            "Q/8/8/8/8/8/8/8 b KQkq - 0 1",
-            "R/8/8/8/8/8/8/8 b KQkq - 0 1",
-            "B/8/8/8/8/8/8/8 b KQkq - 0 1",
-            "N/8/8/8/8/8/8/8 b KQkq - 0 1",
+            "Q7/8/8/8/8/8/8/8 b KQkq - 0 1",
+            "R7/8/8/8/8/8/8/8 b KQkq - 0 1",
+            "B7/8/8/8/8/8/8/8 b KQkq - 0 1",
+            "N7/8/8/8/8/8/8/8 b KQkq - 0 1",
         ]
     ).await;
 }
@@ -115,7 +153,7 @@ async fn pawn_promote() {
 #[tokio::test]
 async fn pawn_capture_promote() {
     assert_moves(
-        "nr6/P/8/8/8/8/8/8 w KQkq - 0 1",
+        "nr6/P7/8/8/8/8/8/8 w KQkq - 0 1",
         &[
             "nQ6/8/8/8/8/8/8/8 b KQkq - 0 1",
             "nR6/8/8/8/8/8/8/8 b KQkq - 0 1",
@@ -249,12 +287,12 @@ async fn king() {
 #[tokio::test]
 async fn king_blocked() {
     assert_moves(
-        "8/8/8/8/8/2p6/1KP5/8 w KQkq - 0 1",
+        "8/8/8/8/8/2p5/1KP5/8 w KQkq - 0 1",
         &[
-            "8/8/8/8/8/2p6/K1P5/8 b KQkq - 0 1",
-            "8/8/8/8/8/2p6/2P5/K7 b KQkq - 0 1",
-            "8/8/8/8/8/2p6/2P5/1K6 b KQkq - 0 1",
-            "8/8/8/8/8/2p6/2P5/2K5 b KQkq - 0 1",
+            "8/8/8/8/8/2p5/K1P5/8 b KQkq - 0 1",
+            "8/8/8/8/8/2p5/2P5/K7 b KQkq - 0 1",
+            "8/8/8/8/8/2p5/2P5/1K6 b KQkq - 0 1",
+            "8/8/8/8/8/2p5/2P5/2K5 b KQkq - 0 1",
             "8/8/8/8/8/K1p5/2P5/8 b KQkq - 0 1",
             "8/8/8/8/8/1Kp5/2P5/8 b KQkq - 0 1",
             "8/8/8/8/8/2K5/2P5/8 b KQkq - 0 1",
@@ -262,21 +300,410 @@ async fn king_blocked() {
     ).await;
 }
 
+#[tokio::test]
+async fn castle_kingside() {
+    assert_moves(
+        "8/8/8/8/8/8/8/4K2R w K - 0 1",
+        &[
+            "8/8/8/8/8/8/8/3K3R b - - 0 1",
+            "8/8/8/8/8/8/3K4/7R b - - 0 1",
+            "8/8/8/8/8/8/4K3/7R b - - 0 1",
+            "8/8/8/8/8/8/5K2/7R b - - 0 1",
+            "8/8/8/8/8/8/8/5K1R b - - 0 1",
+            "8/8/8/8/8/8/8/4K1R1 b - - 0 1",
+            "8/8/8/8/8/8/8/4KR2 b - - 0 1",
+            "8/8/8/8/8/8/7R/4K3 b - - 0 1",
+            "8/8/8/8/8/7R/8/4K3 b - - 0 1",
+            "8/8/8/8/7R/8/8/4K3 b - - 0 1",
+            "8/8/8/7R/8/8/8/4K3 b - - 0 1",
+            "8/8/7R/8/8/8/8/4K3 b - - 0 1",
+            "8/7R/8/8/8/8/8/4K3 b - - 0 1",
+            "7R/8/8/8/8/8/8/4K3 b - - 0 1",
+            "8/8/8/8/8/8/8/5RK1 b - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn castle_queenside() {
+    assert_moves(
+        "r3k3/8/8/8/8/8/8/8 b q - 0 1",
+        &[
+            "r2k4/8/8/8/8/8/8/8 w - - 0 1",
+            "r7/3k4/8/8/8/8/8/8 w - - 0 1",
+            "r7/4k3/8/8/8/8/8/8 w - - 0 1",
+            "r7/5k2/8/8/8/8/8/8 w - - 0 1",
+            "r4k2/8/8/8/8/8/8/8 w - - 0 1",
+            "1r2k3/8/8/8/8/8/8/8 w - - 0 1",
+            "2r1k3/8/8/8/8/8/8/8 w - - 0 1",
+            "3rk3/8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/r7/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/r7/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/r7/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/r7/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/r7/8/8 w - - 0 1",
+            "4k3/8/8/8/8/8/r7/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/r7 w - - 0 1",
+            "2kr4/8/8/8/8/8/8/8 w - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn castle_no_rights() {
+    assert_move_absent("8/8/8/8/8/8/8/4K2R w - - 0 1", "8/8/8/8/8/8/8/5RK1 b - - 0 1").await;
+    assert_move_absent("8/8/8/8/8/8/8/R3K2R w K - 0 1", "8/8/8/8/8/8/8/2KR3R b - - 0 1").await;
+}
+
+#[tokio::test]
+async fn castle_blocked() {
+    assert_move_absent("8/8/8/8/8/8/8/RN2K3 w Q - 0 1", "8/8/8/8/8/8/8/1NKR4 b - - 0 1").await;
+    assert_move_absent("8/8/8/8/8/8/8/4K1NR w K - 0 1", "8/8/8/8/8/8/8/5RK1 b - - 0 1").await;
+}
+
+#[tokio::test]
+async fn castle_attacked() {
+    // In check
+    assert_move_absent("4r3/8/8/8/8/8/8/4K2R w K - 0 1", "4r3/8/8/8/8/8/8/5RK1 b - - 0 1").await;
+    // Through check
+    assert_move_absent("5r2/8/8/8/8/8/8/4K2R w K - 0 1", "5r2/8/8/8/8/8/8/5RK1 b - - 0 1").await;
+    // Into check
+    assert_move_absent("6r1/8/8/8/8/8/8/4K2R w K - 0 1", "6r1/8/8/8/8/8/8/5RK1 b - - 0 1").await;
+    assert_move_absent("8/8/8/8/8/8/1p6/R3K3 w Q - 0 1", "8/8/8/8/8/8/1p6/2KR4 b - - 0 1").await;
+    // The rook may pass through an attacked square when castling queenside
+    find_child("1r6/8/8/8/8/8/8/R3K3 w Q - 0 1", "1r6/8/8/8/8/8/8/2KR4 b - - 0 1").await;
+}
+
+#[tokio::test]
+async fn castle_rights_update() {
+    let start = "r3k3/8/8/8/8/8/8/R3K2R w KQq - 0 1";
+
+    let rook_moved = find_child(start, "r3k3/8/8/8/8/8/7R/R3K3 b - - 0 1").await.get_castles();
+    assert!(!rook_moved[Side::White].kingside);
+    assert!(rook_moved[Side::White].queenside);
+    assert!(rook_moved[Side::Black].queenside);
+
+    let king_moved = find_child(start, "r3k3/8/8/8/8/8/4K3/R6R b - - 0 1").await.get_castles();
+    assert!(!king_moved[Side::White].kingside);
+    assert!(!king_moved[Side::White].queenside);
+    assert!(king_moved[Side::Black].queenside);
+
+    let rook_captured = find_child(start, "R3k3/8/8/8/8/8/8/4K2R b - - 0 1").await.get_castles();
+    assert!(rook_captured[Side::White].kingside);
+    assert!(!rook_captured[Side::White].queenside);
+    assert!(!rook_captured[Side::Black].queenside);
+}
+
+#[tokio::test]
+async fn en_passant_black() {
+    assert_moves(
+        "8/8/8/8/1Pp5/8/8/8 b - b3 0 1",
+        &[
+            "8/8/8/8/1P6/2p5/8/8 w - - 0 1",
+            "8/8/8/8/8/1p6/8/8 w - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn en_passant_white() {
+    assert_moves(
+        "8/8/8/2pP4/8/8/8/8 w - c6 0 1",
+        &[
+            "8/8/3P4/2p5/8/8/8/8 b - - 0 1",
+            "8/8/2P5/8/8/8/8/8 b - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn en_passant_expired() {
+    assert_moves(
+        "8/8/8/8/1Pp5/8/8/8 b - - 0 1",
+        &[
+            "8/8/8/8/1P6/2p5/8/8 w - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn en_passant_after_double_push() {
+    let start = "8/8/8/8/2p5/8/1P6/8 w - - 0 1";
+    let double = find_child(start, "8/8/8/8/1Pp5/8/8/8 b - b3 0 1").await;
+    assert_eq!(double.get_en_passant(), Some(Location::new(1, 2)));
+    let single = find_child(start, "8/8/8/8/2p5/1P6/8/8 b - - 0 1").await;
+    assert_eq!(single.get_en_passant(), None);
+
+    let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+    let mut allocator = GpuAllocations::init(engine.device.clone());
+    let mut tree = GpuTree::new(&engine, &mut allocator);
+    tree.init_layer_from_state(&GameState::from_fen(start));
+    tree.expand_last_layer().await;
+    tree.expand_last_layer().await;
+    let captured: GpuBoard = convert(&GameState::from_fen("8/8/8/8/8/1p6/8/8 w - - 0 2").get_board());
+    let boards: Vec<_> = tree.view_boards_last().await.cast_t().into_iter().map(|b| b.clone()).collect();
+    assert!(boards.contains(&captured));
+    assert!(boards.iter().all(|b| b.get_en_passant().is_none()));
+}
+
 #[tokio::test]
 async fn king_corner() {
     assert_moves(
         "8/8/8/8/8/8/8/K7 w KQkq - 0 1",
         &[
             "8/8/8/8/8/8/8/1K6 b KQkq - 0 1",
-            "8/8/8/8/8/8/K/8 b KQkq - 0 1",
+            "8/8/8/8/8/8/K7/8 b KQkq - 0 1",
             "8/8/8/8/8/8/1K6/8 b KQkq - 0 1",
         ]
     ).await;
 }
 
+#[tokio::test]
+async fn legal_pinned() {
+    assert_legal_moves(
+        "k3r3/8/8/8/8/8/4B3/4K3 w - - 0 1",
+        &[
+            "k3r3/8/8/8/8/8/4B3/3K4 b - - 0 1",
+            "k3r3/8/8/8/8/8/3KB3/8 b - - 0 1",
+            "k3r3/8/8/8/8/8/4BK2/8 b - - 0 1",
+            "k3r3/8/8/8/8/8/4B3/5K2 b - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn legal_evasion() {
+    assert_legal_moves(
+        "k7/8/8/8/8/8/8/r3K3 w - - 0 1",
+        &[
+            "k7/8/8/8/8/8/3K4/r7 b - - 0 1",
+            "k7/8/8/8/8/8/4K3/r7 b - - 0 1",
+            "k7/8/8/8/8/8/5K2/r7 b - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn legal_kings() {
+    // Both sides need exactly one king
+    assert_legal_moves(
+        "8/8/8/8/8/8/8/4K3 w - - 0 1",
+        &[]
+    ).await;
+    // Kings can't be next to each other
+    assert_legal_moves(
+        "8/8/8/8/8/2k5/8/K7 w - - 0 1",
+        &[
+            "8/8/8/8/8/2k5/8/1K6 b - - 0 1",
+            "8/8/8/8/8/2k5/K7/8 b - - 0 1",
+        ]
+    ).await;
+}
+
+#[tokio::test]
+async fn legal_multiple_expansions() {
+    let board = GameState::from_fen("k7/8/8/8/8/8/8/4K2R w K - 0 1");
+    let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+    let mut allocator = GpuAllocations::init(engine.device.clone());
+    let mut tree = GpuTree::new(&engine, &mut allocator);
+    tree.set_legal_only(true);
+    tree.init_layer_from_state(&board);
+    tree.expand_last_layer().await;
+    let first = tree.last_layer().size();
+    tree.expand_last_layer().await;
+
+    // Every child should still point to an existing parent after compaction
+    let boards = tree.view_boards_last().await;
+    assert!(boards.cast_t().iter().all(|b| b.get_prev() < first as usize));
+    assert_eq!(first, 15);
+    // Black has three king moves, except after Rh8+ (two) and Rh7 (one)
+    assert_eq!(boards.cast_t().len(), 13 * 3 + 2 + 1);
+}
+
+#[tokio::test]
+async fn cpu_matches_gpu() {
+    let positions = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/8/8/K1pP3q/8/8/8/7k w - c6 0 1",
+        // Chess960, with the king already on its castled square and a rook that ends up on its own square
+        "1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1",
+        "rk3r2/8/8/8/8/8/8/RK3R2 b FAfa - 0 1",
+    ];
+    for fen in positions {
+        let state = GameState::from_fen(fen);
+        let mut gpu = GpuLegalTester::get_moves(state.clone()).await;
+        let mut cpu: Vec<GpuBoard> = state.legal_moves().into_iter().map(|m| {
+            let mut next = state.clone();
+            next.play(m);
+            convert(&next.get_board())
+        }).collect();
+        let key = |b: &GpuBoard| format!("{b}");
+        gpu.sort_by_key(key);
+        cpu.sort_by_key(key);
+        assert!(gpu == cpu, "Move generators disagree on {fen}");
+    }
+}
+
+#[tokio::test]
+async fn gpu_hash_matches_cpu() {
+    let positions = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "8/8/8/K1pP3q/8/8/8/7k w - c6 0 1",
+        "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+    ];
+    for fen in positions {
+        let state = GameState::from_fen(fen);
+        let gpu = GpuLegalTester::get_moves(state.clone()).await;
+        assert_eq!(gpu.len(), state.legal_moves().len());
+        for m in state.legal_moves() {
+            let mut next = state.clone();
+            next.play(m);
+            let child: GpuBoard = convert(&next.get_board());
+            let found = gpu.iter().find(|b| **b == child).expect("Child board should be present");
+            assert_eq!(found.get_hash(), next.hash(), "Hash of {m} in {fen}");
+        }
+    }
+
+    // The hashes of the children are used as the starting point for the next layer
+    let state = GameState::from_fen(positions[0]);
+    let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+    let mut allocator = GpuAllocations::init(engine.device.clone());
+    let mut tree = GpuTree::new(&engine, &mut allocator);
+    tree.set_legal_only(true);
+    tree.init_layer_from_state(&state);
+    tree.expand_last_layer().await;
+    tree.expand_last_layer().await;
+    let boards = tree.view_boards_last().await;
+    assert_eq!(boards.cast_t().len(), 2039);
+    for board in boards.cast_t() {
+        assert_eq!(board.get_hash(), board.hash(state.to_move), "Hash of\n{board}");
+    }
+}
+
+#[tokio::test]
+async fn three_check_counts() {
+    let positions = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 2+3 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 3+1 1 8",
+        "4k3/8/8/8/8/8/8/R3K2R w K - 1+1 0 1",
+    ];
+    for fen in positions {
+        let state = GameState::from_fen(fen);
+        let gpu = GpuLegalTester::get_moves(state.clone()).await;
+        assert_eq!(gpu.len(), state.legal_moves().len());
+        for m in state.legal_moves() {
+            let mut next = state.clone();
+            next.play(m);
+            let child: GpuBoard = convert(&next.get_board());
+            let found = gpu.iter().find(|b| **b == child).expect("Child board should be present");
+            assert_eq!(found.get_checks(), next.get_checks(), "Checks after {m} in {fen}");
+            assert_eq!(found.get_hash(), next.hash(), "Hash of {m} in {fen}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn move_records() {
+    let positions = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "8/8/8/2pP4/8/8/8/k6K w - c6 0 1",
+        "1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1",
+    ];
+    for fen in positions {
+        let state = GameState::from_fen(fen);
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let mut allocator = GpuAllocations::init(engine.device.clone());
+        let mut tree = GpuTree::new(&engine, &mut allocator);
+        tree.set_legal_only(true);
+        tree.init_layer_from_state(&state);
+        tree.expand_last_layer().await;
+        assert_eq!(tree.view_moves(0).await, vec![None]);
+
+        let boards: Vec<GpuBoard> = tree.view_boards_last().await.cast_t().into_iter().map(|b| b.clone()).collect();
+        let records = tree.view_moves(1).await;
+        assert_eq!(records.len(), state.legal_moves().len());
+        let mut moves = Vec::new();
+        for (board, record) in boards.iter().zip(records) {
+            let record = record.expect("Every child should have a move");
+            let m = record.to_move(state.castles_onto_rook(state.to_move));
+            let mut next = state.clone();
+            next.try_play(m).unwrap_or_else(|_| panic!("{m} in {fen}"));
+            assert_eq!(*board, convert(&next.get_board()), "{m} in {fen}");
+
+            let en_passant = state.get_en_passant() == Some(m.1) && state.get(m.0).is_some_and(|p| p.ty == PieceType::Pawn);
+            assert_eq!(record.castle, state.castle_wing(m).is_some(), "{m} in {fen}");
+            assert_eq!(record.en_passant, en_passant, "{m} in {fen}");
+            assert_eq!(record.capture, en_passant || (!record.castle && state.get(m.1).is_some()), "{m} in {fen}");
+            assert_eq!(record.double_push, next.get_en_passant().is_some(), "{m} in {fen}");
+            moves.push(m);
+        }
+        moves.sort_by_key(|m| m.to_string());
+        let mut expected = state.legal_moves();
+        expected.sort_by_key(|m| m.to_string());
+        assert_eq!(moves, expected, "{fen}");
+    }
+}
+
+#[tokio::test]
+async fn variant_win_eval() {
+    // Only stepping onto the hill changes the material count
+    let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+    let mut allocator = GpuAllocations::init(engine.device.clone());
+    for (variant, expected) in [(Variant::Standard, 0), (Variant::KingOfTheHill, 1000000)] {
+        let mut state = GameState::from_fen("k7/8/8/8/8/4K3/8/8 w - - 0 1");
+        state.set_variant(variant);
+        let mut tree = GpuTree::new(&engine, &mut allocator);
+        tree.init_layer_from_state(&state);
+        tree.expand_last_layer().await;
+        tree.contract_eval(1).await;
+        assert_eq!(tree.view_evals(0).await.cast_t()[0], EvalScore::from(expected), "{variant}");
+    }
+
+    // The third check ends the game, even though white could take the rook afterwards
+    let state = GameState::from_fen("k1r5/8/8/8/8/8/8/1K6 b - - 3+1 0 1");
+    let mut tree = GpuTree::new(&engine, &mut allocator);
+    tree.init_layer_from_state(&state);
+    tree.expand_last_layer().await;
+    tree.expand_last_layer().await;
+    tree.contract_eval(2).await;
+    tree.contract(1).await;
+    assert_eq!(tree.view_evals(0).await.cast_t()[0], EvalScore::from(-1000000));
+}
+
+#[tokio::test]
+async fn tablebase_eval() {
+    // Marked boards are scored by their result for black, who is to move on them, instead of white's extra queen
+    let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+    let mut allocator = GpuAllocations::init(engine.device.clone());
+    for (wdl, expected) in [(Wdl::Loss, 50000), (Wdl::Draw, 0), (Wdl::CursedWin, -1), (Wdl::Win, -50000)] {
+        let state = GameState::from_fen("k7/8/8/8/8/8/8/KQ6 w - - 0 1");
+        let mut tree = GpuTree::new(&engine, &mut allocator);
+        tree.init_layer_from_state(&state);
+        tree.expand_last_layer().await;
+        let marked: Vec<_> = tree.view_boards_last().await.cast_t().iter().enumerate().map(|(i, board)| {
+            let mut board = *board;
+            board.set_tablebase(Some(wdl));
+            (i, board)
+        }).collect();
+        tree.write_boards(1, &marked);
+        tree.contract_eval(1).await;
+        assert_eq!(tree.view_evals(0).await.cast_t()[0], EvalScore::from(expected), "{wdl:?}");
+
+        // The mark isn't passed on to the children
+        tree.expand_last_layer().await;
+        assert!(tree.view_boards_last().await.cast_t().iter().all(|board| board.get_tablebase().is_none()));
+    }
+}
+
 #[tokio::test]
 async fn multiple_expansions() {
-    let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+    let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
     let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
     let mut allocator = GpuAllocations::init(engine.device.clone());
     let mut tree = GpuTree::new(&engine, &mut allocator);
@@ -292,7 +719,7 @@ async fn multiple_expansions() {
 #[tokio::test]
 async fn test_eval() {
     // It should be obviously better to move the pawn two spots than just one
-    let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+    let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
     let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
     let mut allocator = GpuAllocations::init(engine.device.clone());
     let mut tree = GpuTree::new(&engine, &mut allocator);
@@ -317,7 +744,7 @@ async fn test_eval() {
 #[bench]
 fn bench_init(b: &mut Bencher) {
     pollster::block_on(async {
-        let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+        let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
         let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
         let mut allocator = GpuAllocations::init(engine.device.clone());
         
@@ -334,7 +761,7 @@ fn bench_init(b: &mut Bencher) {
 #[bench]
 fn bench_expand_single(b: &mut Bencher) {
     pollster::block_on(async {
-        let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+        let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
         let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
         let mut allocator = GpuAllocations::init(engine.device.clone());
         
@@ -352,7 +779,7 @@ fn bench_expand_single(b: &mut Bencher) {
 #[bench]
 fn bench_triple_expand(b: &mut Bencher) {
     pollster::block_on(async {
-        let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+        let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
         let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
         let mut allocator = GpuAllocations::init(engine.device.clone());
         assert!(allocator.fits(20 * MAX_MOVES * MAX_MOVES));
@@ -373,7 +800,7 @@ fn bench_triple_expand(b: &mut Bencher) {
 #[bench]
 fn bench_triple_expand_contract(b: &mut Bencher) {
     pollster::block_on(async {
-        let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+        let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
         let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
         let mut allocator = GpuAllocations::init(engine.device.clone());
         assert!(allocator.fits(20 * MAX_MOVES * MAX_MOVES));
@@ -395,7 +822,7 @@ fn bench_triple_expand_contract(b: &mut Bencher) {
 #[bench]
 fn bench_expand_read(b: &mut Bencher) {
     pollster::block_on(async {
-        let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+        let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
         let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
         let mut allocator = GpuAllocations::init(engine.device.clone());
         
@@ -414,7 +841,7 @@ fn bench_expand_read(b: &mut Bencher) {
 #[bench]
 fn bench_read(b: &mut Bencher) {
     pollster::block_on(async {
-        let board = GameState::from_fen("8/p7/8/8/8/8/4P2/8 w KQkq - 0 1");
+        let board = GameState::from_fen("8/p7/8/8/8/8/4P3/8 w KQkq - 0 1");
         let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
         let mut allocator = GpuAllocations::init(engine.device.clone());
         
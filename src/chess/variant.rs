@@ -0,0 +1,174 @@
+use std::fmt::Display;
+
+use super::{Board, GameState, Location, Side};
+
+/// The rules a game is played by. Every variant uses the normal board and moves, they only add ways to win
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    /// Getting the king to one of the four centre squares wins
+    KingOfTheHill,
+    /// Giving check for the third time wins, [`GameState::get_checks`] keeps count
+    ThreeCheck,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 3] = [Variant::Standard, Variant::KingOfTheHill, Variant::ThreeCheck];
+
+    /// The name used for the `UCI_Variant` option, these are the same as in other engines
+    pub fn uci_name(&self) -> &'static str {
+        match self {
+            Variant::Standard => "chess",
+            Variant::KingOfTheHill => "kingofthehill",
+            Variant::ThreeCheck => "3check",
+        }
+    }
+
+    pub fn from_uci_name(name: &str) -> Option<Variant> {
+        return Self::ALL.into_iter().find(|v| v.uci_name() == name);
+    }
+
+    /// The fen of the position every game of the variant starts from
+    pub fn start_fen(&self) -> &'static str {
+        match self {
+            Variant::Standard | Variant::KingOfTheHill => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            Variant::ThreeCheck => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1",
+        }
+    }
+
+    /// How the shaders know which variant is played, has to match the constants in lib.wgsl
+    pub fn gpu_representation(&self) -> u32 {
+        return match self {
+            Variant::Standard => 0,
+            Variant::KingOfTheHill => 1,
+            Variant::ThreeCheck => 2,
+        };
+    }
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Variant::Standard => write!(f, "standard chess"),
+            Variant::KingOfTheHill => write!(f, "king of the hill"),
+            Variant::ThreeCheck => write!(f, "three-check"),
+        }
+    }
+}
+
+/// Whether a square is one of d4, e4, d5 and e5
+fn is_centre(loc: Location) -> bool {
+    return (3..=4).contains(&loc.get_x()) && (3..=4).contains(&loc.get_y());
+}
+
+impl GameState {
+    /// The starting position of a variant
+    pub fn start_position(variant: Variant) -> GameState {
+        let mut state = GameState::from_fen(variant.start_fen());
+        state.set_variant(variant);
+        return state;
+    }
+
+    /// The side that has won by a rule of the variant, checkmate isn't included
+    pub fn variant_winner(&self) -> Option<Side> {
+        let board = self.get_board();
+        let sides = [Side::White, Side::Black];
+        return match self.get_variant() {
+            Variant::Standard => None,
+            Variant::KingOfTheHill => sides.into_iter().find(|side| board.king(*side).is_some_and(is_centre)),
+            Variant::ThreeCheck => sides.into_iter().find(|side| self.get_checks()[*side] >= 3),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use enum_map::enum_map;
+
+    use crate::chess::{DrawReason, GameState, GameStatus, Move, Side};
+
+    use super::Variant;
+
+    #[test]
+    fn uci_names() {
+        for variant in Variant::ALL {
+            assert_eq!(Variant::from_uci_name(variant.uci_name()), Some(variant));
+        }
+        assert_eq!(Variant::from_uci_name("crazyhouse"), None);
+    }
+
+    #[test]
+    fn start_positions() {
+        for variant in Variant::ALL {
+            let state = GameState::start_position(variant);
+            assert_eq!(state.get_variant(), variant);
+            assert_eq!(state.legal_moves().len(), 20);
+            assert_eq!(state.status(), GameStatus::Ongoing);
+        }
+        assert_eq!(GameState::start_position(Variant::ThreeCheck).to_fen(), Variant::ThreeCheck.start_fen());
+    }
+
+    #[test]
+    fn king_of_the_hill() {
+        let mut state = GameState::from_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1");
+        state.set_variant(Variant::KingOfTheHill);
+        assert_eq!(state.variant_winner(), None);
+        state.play(Move::from_str("e3d4"));
+        assert_eq!(state.variant_winner(), Some(Side::White));
+        assert_eq!(state.status(), GameStatus::VariantWin { winner: Side::White, variant: Variant::KingOfTheHill });
+        assert!(state.status().is_over());
+
+        // The same position is just a game in standard chess
+        state.set_variant(Variant::Standard);
+        assert_eq!(state.variant_winner(), None);
+    }
+
+    #[test]
+    fn three_check() {
+        let mut state = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 1+3 0 1");
+        assert_eq!(state.get_variant(), Variant::ThreeCheck);
+        assert_eq!(state.get_checks(), enum_map! { Side::White => 2, Side::Black => 0 });
+        let before = state.clone();
+
+        // Not a check
+        let undo_quiet = state.play(Move::from_str("a1a2"));
+        assert_eq!(state.get_checks()[Side::White], 2);
+        state.unplay(Move::from_str("a1a2"), undo_quiet);
+
+        let undo = state.play(Move::from_str("a1a8"));
+        assert_eq!(state.get_checks()[Side::White], 3);
+        assert_eq!(state.to_fen(), "R3k3/8/8/8/8/8/8/4K3 b - - 0+3 1 1");
+        assert_eq!(state.status(), GameStatus::VariantWin { winner: Side::White, variant: Variant::ThreeCheck });
+        state.unplay(Move::from_str("a1a8"), undo);
+        assert_eq!(state, before);
+        assert_eq!(state.hash(), before.hash());
+    }
+
+    #[test]
+    fn material_draws() {
+        // Bare kings can still walk to the centre
+        let mut state = GameState::from_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1");
+        assert_eq!(state.status(), GameStatus::Draw(DrawReason::InsufficientMaterial));
+        state.set_variant(Variant::KingOfTheHill);
+        assert_eq!(state.status(), GameStatus::Ongoing);
+        assert!(!state.status().is_over());
+
+        // A lone bishop can still give check, but bare kings can't
+        let mut state = GameState::from_fen("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1");
+        state.set_variant(Variant::ThreeCheck);
+        assert_eq!(state.status(), GameStatus::Ongoing);
+        let mut state = GameState::from_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1");
+        state.set_variant(Variant::ThreeCheck);
+        assert_eq!(state.status(), GameStatus::Draw(DrawReason::InsufficientMaterial));
+    }
+
+    #[test]
+    fn checks_are_hashed() {
+        let a = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 3+3 0 1");
+        let b = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 2+3 0 1");
+        assert_ne!(a.hash(), b.hash());
+        assert_ne!(a, b);
+        assert_eq!(a.hash(), GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").hash());
+    }
+}
@@ -0,0 +1,182 @@
+use enum_map::EnumMap;
+
+use super::{Board, GpuBoard, Location, Piece, PieceType, Side, state::Castles};
+
+/// 12 pieces times 64 squares, the side to move, 4 castling rights, 8 en passant files and 3 check counts per side
+pub const KEY_COUNT: usize = 787;
+const SIDE_KEY: usize = 768;
+const CASTLE_KEYS: usize = 769;
+const EN_PASSANT_KEYS: usize = 773;
+const CHECK_KEYS: usize = 781;
+
+/// The random keys that are xored together to form a hash.
+/// The expansion shader gets a copy of this table, so the indexing below has to match `hashPiece` and `stateKey` in `expand.wgsl`:
+/// - `(side * 6 + type) * 64 + y * 8 + x` for pieces, with white = 0 and the type as [`super::PieceType::to_byte`] - 1
+/// - 768 when black is to move
+/// - 769 to 772 for the castling rights, in the same order as the bits in [`GpuBoard`]
+/// - 773 to 780 for the file of the en passant square, only when a pawn of the side to move is next to the pawn that moved
+/// - `781 + side * 3 + checks - 1` for the checks each side has given in Three-check, nothing when it's 0
+pub static KEYS: [u64; KEY_COUNT] = generate_keys();
+
+/// Fills the table using splitmix64, so the keys are the same on every build
+const fn generate_keys() -> [u64; KEY_COUNT] {
+    let mut keys = [0; KEY_COUNT];
+    let mut seed: u64 = 0x6170_6F70_6874_6567;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        keys[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    return keys;
+}
+
+pub fn piece_key(piece: Piece, loc: Location) -> u64 {
+    let side = match piece.side {
+        Side::White => 0,
+        Side::Black => 1,
+    };
+    let ty = piece.ty.to_byte() as usize - 1;
+    return KEYS[(side * 6 + ty) * 64 + loc.get_y() as usize * 8 + loc.get_x() as usize];
+}
+
+/// The part of the hash that isn't about the pieces. The board is only looked at for the en passant square,
+/// which is left out when no pawn could take, so the position after a double push repeats later on
+pub fn state_key(board: &impl Board, to_move: Side, castles: EnumMap<Side, Castles>, en_passant: Option<Location>, checks: EnumMap<Side, u8>) -> u64 {
+    let mut key = 0;
+    if to_move == Side::Black {
+        key ^= KEYS[SIDE_KEY];
+    }
+    let rights = [castles[Side::White].kingside, castles[Side::White].queenside, castles[Side::Black].kingside, castles[Side::Black].queenside];
+    for (i, right) in rights.into_iter().enumerate() {
+        if right {
+            key ^= KEYS[CASTLE_KEYS + i];
+        }
+    }
+    if let Some(square) = en_passant && can_take_en_passant(board, to_move, square) {
+        key ^= KEYS[EN_PASSANT_KEYS + square.get_x() as usize];
+    }
+    for (side, count) in [(0, checks[Side::White]), (1, checks[Side::Black])] {
+        if count > 0 {
+            key ^= KEYS[CHECK_KEYS + side * 3 + count as usize - 1];
+        }
+    }
+    return key;
+}
+
+/// Whether a pawn of `to_move` is next to the pawn that moved past `square`, like Polyglot checks it. The capture might still be illegal
+fn can_take_en_passant(board: &impl Board, to_move: Side, square: Location) -> bool {
+    let pawn_rank = if to_move == Side::White { 4 } else { 3 };
+    let own_pawn = |dx: i16| square.with_y(pawn_rank).try_add(dx, 0)
+        .is_some_and(|loc| board.get(loc).is_some_and(|p| p.side == to_move && p.ty == PieceType::Pawn));
+    return own_pawn(-1) || own_pawn(1);
+}
+
+/// Hashes only the pieces on the board
+pub fn hash_pieces(board: &impl Board) -> u64 {
+    return Location::all()
+        .filter_map(|loc| board.get(loc).map(|piece| piece_key(piece, loc)))
+        .fold(0, |a, b| a ^ b);
+}
+
+/// The full hash of a position, as returned by [`super::GameState::hash`]
+pub fn hash(board: &impl Board, to_move: Side, castles: EnumMap<Side, Castles>, en_passant: Option<Location>, checks: EnumMap<Side, u8>) -> u64 {
+    return hash_pieces(board) ^ state_key(board, to_move, castles, en_passant, checks);
+}
+
+impl GpuBoard {
+    /// Hashes the board with the castling rights, en passant square and check counts it carries.
+    /// The side to move isn't stored in the board, so it has to be passed in, it's the same for the whole layer.
+    pub fn hash(&self, to_move: Side) -> u64 {
+        return hash(self, to_move, self.get_castles(), self.get_en_passant(), self.get_checks());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::chess::{GameState, Move};
+
+    use super::{hash, KEYS};
+
+    fn full_hash(state: &GameState) -> u64 {
+        return hash(&state.get_board(), state.to_move, state.get_castles(), state.get_en_passant(), state.get_checks());
+    }
+
+    #[test]
+    fn keys_are_unique() {
+        let unique: HashSet<u64> = KEYS.iter().copied().collect();
+        assert_eq!(unique.len(), KEYS.len());
+    }
+
+    #[test]
+    fn incremental_matches_full() {
+        // Everything two plies deep, these positions cover castling, en passant and promotions
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/8/8/2pP4/8/8/8/k6K w - c6 0 1",
+        ];
+        for fen in fens {
+            let start = GameState::from_fen(fen);
+            assert_eq!(start.hash(), full_hash(&start));
+            for m in start.legal_moves() {
+                let mut state = start.clone();
+                state.play(m);
+                assert_eq!(state.hash(), full_hash(&state), "{m} in {fen}");
+                for reply in state.legal_moves() {
+                    let mut next = state.clone();
+                    next.play(reply);
+                    assert_eq!(next.hash(), full_hash(&next), "{m} {reply} in {fen}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transpositions() {
+        let mut a = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let start = a.hash();
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            a.play(Move::from_str(m));
+        }
+        assert_eq!(a.hash(), start);
+
+        let mut b = a.clone();
+        a.play(Move::from_str("e2e3"));
+        a.play(Move::from_str("e7e6"));
+        a.play(Move::from_str("d2d3"));
+        b.play(Move::from_str("d2d3"));
+        b.play(Move::from_str("e7e6"));
+        b.play(Move::from_str("e2e3"));
+        assert_eq!(a.hash(), b.hash());
+    }
+
+    #[test]
+    fn state_is_hashed() {
+        let hashes: HashSet<u64> = [
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1",
+            "4k3/8/8/2pP4/8/8/8/4K3 w - c6 0 1",
+            "4k3/8/8/2pP4/8/8/8/4K3 w - - 0 1",
+        ].into_iter().map(|fen| GameState::from_fen(fen).hash()).collect();
+        assert_eq!(hashes.len(), 7);
+
+        // The clocks aren't part of the position
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").hash(), GameState::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 12 40").hash());
+    }
+
+    #[test]
+    fn gpu_board() {
+        let mut state = GameState::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        state.play(Move::from_str("a2a4"));
+        assert_eq!(state.get_gpu_board().hash(state.to_move), state.hash());
+    }
+}
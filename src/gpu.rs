@@ -2,7 +2,7 @@ use core::slice::SlicePattern;
 use std::collections::HashMap;
 use std::iter::{Map, Take};
 use std::marker::PhantomData;
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use std::num::NonZeroU64;
 use std::rc::Rc;
 use std::slice::Iter;
@@ -12,7 +12,7 @@ use tokio::join;
 use wgpu::{RequestAdapterOptions, DeviceDescriptor, BufferDescriptor, BufferUsages, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindGroupDescriptor, BindGroupLayout, BindGroupEntry, PipelineLayoutDescriptor, ShaderModule, ShaderModuleDescriptor, include_wgsl, CommandEncoderDescriptor, ComputePassDescriptor, Backends, Buffer, BindGroup, ComputePipeline, BufferSlice, MapMode, Device, Queue, SubmissionIndex, BufferView, Adapter};
 
 use crate::buffers::BufferManager;
-use crate::chess::{GpuBoard, Side, EvalScore};
+use crate::chess::{GpuBoard, Side, EvalScore, Variant, zobrist};
 use crate::shaders::{Shader, self, BuffOffsets, WORKGROUP_SIZE};
 use crate::misc::SliceExtension;
 
@@ -23,21 +23,26 @@ pub struct GpuGlobalData {
     pub just_zero: Buffer,
     pub out_index: Buffer,
     pub out_index_staging: Buffer,
+    /// The zobrist keys, so the expansion pass can hash the boards it creates
+    pub zobrist_keys: Buffer,
     pub expand_shader: Shader,
     pub eval_contract_shader: Shader,
     pub contract_shader: Shader,
     pub fill_max_shader: Shader,
     pub filter_shader: Shader,
+    pub legal_shader: Shader,
+    pub checks_shader: Shader,
 }
 
 impl GpuGlobalData {
-    pub fn set_all_global_data(&self, input_size: u32, to_move: Side, move_num: u32, offsets: BuffOffsets) {
+    pub fn set_all_global_data(&self, input_size: u32, to_move: Side, move_num: u32, offsets: BuffOffsets, variant: Variant) {
         assert!(move_num == 0);
-        let mut data = [0; 28];
+        let mut data = [0; 32];
         data[0..4].copy_from_slice(&(input_size as u32).to_le_bytes());
         data[4..8].copy_from_slice(bytemuck::bytes_of(&to_move.gpu_representation()));
         data[8..12].copy_from_slice(bytemuck::bytes_of(&move_num));
         data[12..28].copy_from_slice(bytemuck::bytes_of(&offsets));
+        data[28..32].copy_from_slice(bytemuck::bytes_of(&variant.gpu_representation()));
         self.queue.write_buffer(&self.global_data, 0, &data);
     }
 }
@@ -50,7 +55,7 @@ pub async fn init_gpu_evaluator(adapter: &Adapter) -> GpuGlobalData {
     let global_data = device.create_buffer(
         &BufferDescriptor { 
             label: Some("Global Data Uniform"),
-            size: 7 * size_of::<u32>() as u64, 
+            size: 8 * size_of::<u32>() as u64, 
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST, 
             mapped_at_creation: false
         }
@@ -83,11 +88,25 @@ pub async fn init_gpu_evaluator(adapter: &Adapter) -> GpuGlobalData {
         }
     );
 
+    let zobrist_keys = device.create_buffer(
+        &BufferDescriptor {
+            label: Some("Zobrist Keys"),
+            size: size_of_val(&zobrist::KEYS) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        }
+    );
+    // Split into (low, high) words, which is how the shader reads them
+    let key_words: Vec<u32> = zobrist::KEYS.iter().flat_map(|k| [*k as u32, (*k >> 32) as u32]).collect();
+    queue.write_buffer(&zobrist_keys, 0, bytemuck::cast_slice(&key_words));
+
     let expand_shader = shaders::expand(&device);
     let eval_contract_shader = shaders::eval_contract(&device);
     let contract_shader = shaders::contract(&device);
     let fill_max_shader = shaders::fill_max(&device);
     let filter_shader = shaders::filter(&device);
+    let legal_shader = shaders::legal(&device);
+    let checks_shader = shaders::checks(&device);
 
     let device_rc = Rc::new(device);
 
@@ -98,11 +117,14 @@ pub async fn init_gpu_evaluator(adapter: &Adapter) -> GpuGlobalData {
         just_zero,
         out_index,
         out_index_staging,
+        zobrist_keys,
         expand_shader,
         eval_contract_shader,
         contract_shader,
         fill_max_shader,
-        filter_shader
+        filter_shader,
+        legal_shader,
+        checks_shader,
     };
 }
 
@@ -3,13 +3,17 @@ use std::{mem::{size_of, self}, num::NonZeroU64};
 
 use wgpu::{CommandEncoderDescriptor, ComputePassDescriptor};
 
-use crate::{gpu::{GpuGlobalData, GpuAllocations}, chess::{board::{GpuBoard, self}, MAX_MOVES, Side, GameState, EvalScore}, buffers::{AllocToken, BufView}, misc::{ceil_div, SliceExtension}, shaders::{WORKGROUP_SIZE, ExpansionBindGroupMngr, ExpansionBuffers, FillMaxBindGroupMngr, FillMaxBuffers, EvalContractBindGroupMngr, EvalContractBuffers, ContractBindGroupMngr, ContractBuffers, FilterBindGroupMngr, FilterBuffers}};
+use crate::{gpu::{GpuGlobalData, GpuAllocations}, chess::{board::{GpuBoard, self, Board}, MAX_MOVES, Side, GameState, EvalScore, Variant, MoveRecord, syzygy::Wdl}, buffers::{AllocToken, BufView}, misc::{ceil_div, SliceExtension}, shaders::{WORKGROUP_SIZE, ExpansionBindGroupMngr, ExpansionBuffers, FillMaxBindGroupMngr, FillMaxBuffers, EvalContractBindGroupMngr, EvalContractBuffers, ContractBindGroupMngr, ContractBuffers, FilterBindGroupMngr, FilterBuffers, LegalBindGroupMngr, LegalBuffers, ChecksBindGroupMngr, ChecksBuffers}};
 
 /// A tree of chess positions that lives mainly on the gpu
 pub struct GpuTree<'dev> {
     layers: Vec<GpuTreeLayer>,
     engine: &'dev GpuGlobalData,
     gpu_allocator: &'dev GpuAllocations,
+    /// Whether illegal boards should be removed after each expansion
+    legal_only: bool,
+    /// Decides when the shaders count checks and which boards have already been won
+    variant: Variant,
 }
 
 impl<'dev> GpuTree<'dev> {
@@ -18,11 +22,24 @@ impl<'dev> GpuTree<'dev> {
             layers: Vec::new(),
             engine,
             gpu_allocator: allocator,
+            legal_only: false,
+            variant: Variant::Standard,
         }
     }
 
+    /// When enabled, [`Self::expand_last_layer`] only keeps the children which are legal positions
+    pub fn set_legal_only(&mut self, legal_only: bool) {
+        self.legal_only = legal_only;
+    }
+
+    /// Plays by the rules of `variant`, [`Self::init_layer_from_state`] sets it as well
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
     pub fn init_layer_from_state(&mut self, state: &GameState) {
-        self.init_layer(&[board::convert(&state.get_board())], state.to_move);
+        self.variant = state.get_variant();
+        self.init_layer(&[state.get_gpu_board()], state.to_move);
     }
 
     pub fn init_layer(&mut self, boards: &[GpuBoard], to_move: Side) {
@@ -37,6 +54,35 @@ impl<'dev> GpuTree<'dev> {
         });
     }
 
+    /// Probes the boards of the last layer that have at most `probe_limit` pieces, and marks the ones that are found so
+    /// [`Self::contract_eval`] scores them by their result instead of their material. Returns how many were found
+    pub async fn probe_last_layer(&mut self, probe_limit: usize, probe: impl Fn(&GameState) -> Option<Wdl>) -> u64 {
+        let last = self.layers.len() - 1;
+        let to_move = self.layers[last].to_move;
+        let hits: Vec<_> = self.view_boards(last).await.cast_t().iter().enumerate().filter_map(|(i, board)| {
+            // Boards where the king can be taken are left to the search
+            if board.piece_count() as usize > probe_limit || !board.is_valid(to_move.opposite()) {
+                return None;
+            }
+            let wdl = probe(&GameState::from_gpu_board(board, to_move, self.variant))?;
+            let mut board = *board;
+            board.set_tablebase(Some(wdl));
+            return Some((i, board));
+        }).collect();
+        self.write_boards(last, &hits);
+        return hits.len() as u64;
+    }
+
+    /// Overwrites boards of a layer, by their index in the layer
+    pub fn write_boards(&mut self, layer: usize, boards: &[(usize, GpuBoard)]) {
+        let layer = &self.layers[layer];
+        let buffer = layer.board_buf.buffer(&self.gpu_allocator.boards);
+        for (i, board) in boards {
+            assert!(*i < layer.num_boards as usize);
+            self.engine.queue.write_buffer(&buffer, layer.board_buf.start() + (*i * size_of::<GpuBoard>()) as u64, bytemuck::bytes_of(board));
+        }
+    }
+
     pub async fn filter_last_layer(&mut self, eval: EvalScore) {
         let last = self.layers.len()-1;
         self.filter(last, eval).await;
@@ -59,6 +105,12 @@ impl<'dev> GpuTree<'dev> {
             eval_buf: None,
         };
         self.expand(last, &mut new_layer).await;
+        if self.variant == Variant::ThreeCheck {
+            self.count_checks(&new_layer);
+        }
+        if self.legal_only {
+            self.remove_illegal(&mut new_layer).await;
+        }
         self.layers.push(new_layer);
     }
 
@@ -71,7 +123,7 @@ impl<'dev> GpuTree<'dev> {
             output: &to.board_buf,
         });
         
-        self.engine.set_all_global_data(from.num_boards, from.to_move, 0, bind.1);
+        self.engine.set_all_global_data(from.num_boards, from.to_move, 0, bind.1, self.variant);
         let mut command_encoder = self.engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
         let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
         pass_encoder.set_pipeline(&self.engine.expand_shader.1);
@@ -102,6 +154,61 @@ impl<'dev> GpuTree<'dev> {
         self.engine.out_index_staging.unmap();
     }
 
+    /// Adds the checks given by the moves that led to the boards in the layer to their counts
+    fn count_checks(&self, layer: &GpuTreeLayer) {
+        let bind = ChecksBindGroupMngr::create(self.engine, &self.gpu_allocator, ChecksBuffers {
+            boards: &layer.board_buf,
+        });
+
+        // The shader wants to know which side made the last move
+        self.engine.set_all_global_data(layer.num_boards, layer.to_move.opposite(), 0, bind.1, self.variant);
+        let mut command_encoder = self.engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
+        let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass_encoder.set_pipeline(&self.engine.checks_shader.1);
+        pass_encoder.set_bind_group(0, &bind.0, &[]);
+        pass_encoder.dispatch_workgroups(ceil_div(layer.num_boards, WORKGROUP_SIZE), 1, 1);
+        drop(pass_encoder);
+        self.engine.queue.submit([command_encoder.finish()]);
+    }
+
+    /// Compacts the layer so that it only contains boards where the side that just moved isn't in check
+    async fn remove_illegal(&self, layer: &mut GpuTreeLayer) {
+        let out_buf = self.gpu_allocator.boards.allocate(layer.num_boards);
+
+        let bind = LegalBindGroupMngr::create(self.engine, &self.gpu_allocator, LegalBuffers {
+            input: &layer.board_buf,
+            output: &out_buf,
+        });
+
+        // The shader wants to know which side made the last move
+        self.engine.set_all_global_data(layer.num_boards, layer.to_move.opposite(), 0, bind.1, self.variant);
+        let mut command_encoder = self.engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
+        let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass_encoder.set_pipeline(&self.engine.legal_shader.1);
+        pass_encoder.set_bind_group(0, &bind.0, &[]);
+        pass_encoder.dispatch_workgroups(ceil_div(layer.num_boards, WORKGROUP_SIZE), 1, 1);
+        drop(pass_encoder);
+        command_encoder.copy_buffer_to_buffer(
+            &self.engine.out_index,
+            0, // Source offset
+            &self.engine.out_index_staging,
+            0, // Destination offset
+            1 * size_of::<u32>() as u64,
+        );
+        command_encoder.clear_buffer(&self.engine.out_index, 0, None);
+        self.engine.queue.submit([command_encoder.finish()]);
+
+        self.engine.out_index_staging.slice(..).map_buffer(&self.engine.device, wgpu::MapMode::Read).await.unwrap();
+        let out_index_view = self.engine.out_index_staging.slice(..).get_mapped_range();
+        let output_size: u32 = u32::from_le(*bytemuck::from_bytes(&out_index_view.as_slice()));
+        layer.num_boards = output_size;
+        drop(out_index_view);
+        self.engine.out_index_staging.unmap();
+
+        let old_buf = mem::replace(&mut layer.board_buf, out_buf);
+        self.gpu_allocator.boards.dealloc(old_buf);
+    }
+
     pub async fn filter(&mut self, layer: usize, eval: EvalScore) {
         let layer = &mut self.layers[layer];
         let out_buf = self.gpu_allocator.boards.allocate(layer.num_boards);
@@ -113,7 +220,7 @@ impl<'dev> GpuTree<'dev> {
             evals: &layer.eval_buf.as_ref().unwrap()
         });
         
-        self.engine.set_all_global_data(layer.num_boards, layer.to_move, 0, bind.1);
+        self.engine.set_all_global_data(layer.num_boards, layer.to_move, 0, bind.1, self.variant);
         let mut command_encoder = self.engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
         let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
         pass_encoder.set_pipeline(&self.engine.filter_shader.1);
@@ -185,7 +292,7 @@ impl<'dev> GpuTree<'dev> {
             // We should be able to optimize this and combine the data for these passes, so we don't need two command encoders
             let mut command_encoder2 = self.engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
             let mut pass_encoder = command_encoder2.begin_compute_pass(&ComputePassDescriptor::default());
-            self.engine.set_all_global_data(parent_num_boards, to_move, 0, fill_max_bind.1);
+            self.engine.set_all_global_data(parent_num_boards, to_move, 0, fill_max_bind.1, self.variant);
             pass_encoder.set_pipeline(&self.engine.fill_max_shader.1);
             pass_encoder.set_bind_group(0, &fill_max_bind.0, &[]);
             pass_encoder.dispatch_workgroups(ceil_div(parent_num_boards, WORKGROUP_SIZE), 1, 1);
@@ -202,7 +309,7 @@ impl<'dev> GpuTree<'dev> {
             }
         }
         let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
-        self.engine.set_all_global_data(child_layer.num_boards, to_move, 0, generic_contract_bind.1);
+        self.engine.set_all_global_data(child_layer.num_boards, to_move, 0, generic_contract_bind.1, self.variant);
         if do_eval {
             pass_encoder.set_pipeline(&self.engine.eval_contract_shader.1);
         } else {
@@ -225,6 +332,12 @@ impl<'dev> GpuTree<'dev> {
         return view;
     }
 
+    /// The moves that produced the boards of a layer, in the same order as the boards.
+    /// They're `None` for boards that were put in with [`Self::init_layer`] instead of coming out of an expansion
+    pub async fn view_moves(&self, layer: usize) -> Vec<Option<MoveRecord>> {
+        return self.view_boards(layer).await.cast_t().iter().map(|b| b.get_move()).collect();
+    }
+
     pub async fn view_evals(&self, layer: usize) -> BufView<'_, EvalScore> {
         let layer = &self.layers[layer];
         let view = self.gpu_allocator.evals.view(&self.engine.queue, &layer.eval_buf.as_ref().unwrap(), 0..layer.num_boards).await.unwrap();
@@ -17,6 +17,9 @@ mod buffers;
 pub(crate) mod misc;
 mod shaders;
 mod uci;
+mod perft;
+mod book;
+mod retrograde;
 
 use core::slice::SlicePattern;
 use std::{mem::size_of, thread, time::Duration, rc::Rc, sync::Arc, cell::RefCell};
@@ -39,38 +42,79 @@ const BUFFER_SIZE: u64 = size_of::<GpuBoard>() as u64 * BOARDS_IN_BUF;
 #[tokio::main]
 async fn main() {
     env_logger::init();
+    // Without arguments this is a uci engine, `book build` makes an opening book and `tablebase generate` makes DTM tables instead
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("book") => Some(book::run(&args[1..])),
+        Some("tablebase") => Some(retrograde::run(&args[1..]).await),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
     let engine_coms = start();
     uci::start_loop(engine_coms);
 }
 
 fn start() -> impl EngineComs {
     let thread = LocalPoolHandle::new(1);
-    let (sender, mut receiver) = tokio::sync::mpsc::channel::<(Arc<UciEvalSession>, GameState)>(1);
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<EngineRequest>(1);
     thread.spawn_pinned(|| {async move {
         let adapter = init_adapter().await;
         let engine = init_gpu_evaluator(&adapter).await;
         let mut allocations = GpuAllocations::init(engine.device.clone());
 
         loop {
-            let Some((coms, state)) = receiver.recv().await else {break;};
+            let Some(request) = receiver.recv().await else {break;};
+            let (coms, state) = match request {
+                EngineRequest::Search(coms, state) => (coms, state),
+                EngineRequest::Perft(state, depth) => {
+                    let mut total = 1;
+                    if depth > 0 {
+                        let divided = perft::gpu_divide(&engine, &allocations, &state, depth).await;
+                        for (m, nodes) in &divided {
+                            println!("{m}: {nodes}");
+                        }
+                        println!();
+                        total = divided.iter().map(|(_, nodes)| nodes).sum();
+                    }
+                    println!("Nodes searched: {total}");
+                    continue;
+                }
+            };
             engine.device.start_capture();
             let mut tree = GpuTree::new(&engine, &mut allocations);
 
             tree.init_layer_from_state(&state);
             tree.expand_last_layer().await;
             
-            let first_moves: Vec<_> = tree.view_boards_last()
+            let moves = tree.view_moves(1).await;
+            let mut first_moves: Vec<_> = tree.view_boards_last()
                 .await
                 .cast_t()
                 .iter()
-                .filter(|m| m.is_valid(state.to_move))
-                .map(|b| b.clone())
+                .zip(moves)
+                .filter(|(b, _)| b.is_valid(state.to_move))
+                .map(|(b, m)| (b.clone(), m.unwrap().to_move(state.castles_onto_rook(state.to_move))))
                 .collect();
             drop(tree);
+
+            // In the tablebases, only the moves that keep the best result are searched
+            let root_pieces = state.get_board().occupied().count_ones() as usize;
+            if root_pieces <= coms.probe_limit() && let Some(keep) = coms.root_moves(&state) {
+                // Counted once for the position, like other engines do, even though every move was looked up
+                coms.report_tbhits(1);
+                first_moves.retain(|(_, m)| keep.contains(m));
+            }
             
-            let mut trees: Vec<_> = first_moves.iter().map(|m| {
+            let mut trees: Vec<_> = first_moves.iter().map(|(board, _)| {
                 let mut tree = GpuTree::new(&engine, &allocations);
-                tree.init_layer(&[*m], state.to_move.opposite());
+                tree.set_variant(state.get_variant());
+                tree.init_layer(&[*board], state.to_move.opposite());
                 tree
             }).collect();
 
@@ -90,12 +134,18 @@ fn start() -> impl EngineComs {
                     }
                 }
 
+                // Every ply takes at most one piece, so the leaves may reach the tablebases even if the root can't.
+                // The first layer of these trees is already one ply in
+                let limit = coms.probe_limit();
+                if limit > 0 && root_pieces.saturating_sub(tree.last_layer().depth() + 1) <= limit {
+                    coms.report_tbhits(tree.probe_last_layer(limit, |state| coms.probe_wdl(state)).await);
+                }
+
                 tree.contract_all().await;
 
                 let result = tree.view_evals(0).await.cast_t()[0];
                 if EvalScore::better(&result, &best_score, state.to_move).is_ge() {
-                    let board = first_moves[i];
-                    coms.set_best(board::find_move(&state.get_board(), &board).unwrap(), result);
+                    coms.set_best(first_moves[i].1, result);
                     best_score = result;
                 }
                 if EvalScore::better(&result, &best_per_move[i], state.to_move).is_gt() {
@@ -119,8 +169,7 @@ fn start() -> impl EngineComs {
 
             //     let result = tree.view_evals(0).await.cast_t()[0];
             //     if EvalScore::better(&result, &best_score, state.to_move).is_ge() {
-            //         let board = first_moves[i];
-            //         coms.set_best(board::find_move(&state.get_board(), &board).unwrap(), result);
+            //         coms.set_best(first_moves[i].1, result);
             //         best_score = result;
             //     }
             //     if EvalScore::better(&result, &best_per_move[i], state.to_move).is_gt() {
@@ -137,13 +186,22 @@ fn start() -> impl EngineComs {
     }
 }
 
+enum EngineRequest {
+    Search(Arc<UciEvalSession>, GameState),
+    Perft(GameState, u32),
+}
+
 struct Coms {
-    sender: Sender<(Arc<UciEvalSession>, GameState)>
+    sender: Sender<EngineRequest>
 }
 
 impl EngineComs for Coms {
     async fn start_session(&mut self, coms: Arc<UciEvalSession>, state: GameState) {
-        self.sender.send((coms, state)).await.unwrap();
+        self.sender.send(EngineRequest::Search(coms, state)).await.unwrap();
+    }
+
+    async fn start_perft(&mut self, state: GameState, depth: u32) {
+        self.sender.send(EngineRequest::Perft(state, depth)).await.unwrap();
     }
 }
 
@@ -0,0 +1,159 @@
+use std::{future::Future, pin::Pin};
+
+use crate::{chess::{GameState, Move, MAX_MOVES}, gpu::{GpuGlobalData, GpuAllocations}, gpu_tree::GpuTree};
+
+/// Counts the leaf nodes of the legal move tree, using the cpu move generator
+pub fn perft(state: &GameState, depth: u32) -> u64 {
+    return perft_in_place(&mut state.clone(), depth);
+}
+
+/// Walks the tree by playing and taking back moves on a single state
+fn perft_in_place(state: &mut GameState, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = state.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    return moves.into_iter().map(|m| {
+        let undo = state.play(m);
+        let count = perft_in_place(state, depth - 1);
+        state.unplay(m, undo);
+        count
+    }).sum();
+}
+
+/// Like [`perft`], but broken down per root move
+pub fn divide(state: &GameState, depth: u32) -> Vec<(Move, u64)> {
+    assert!(depth >= 1);
+    return state.legal_moves().into_iter().map(|m| {
+        let mut next = state.clone();
+        next.play(m);
+        (m, perft(&next, depth - 1))
+    }).collect();
+}
+
+/// Counts the leaf nodes of the legal move tree, using the gpu to expand the layers.
+/// If the tree doesn't fit in memory it's split up per move, and the counts of the subtrees are summed.
+pub fn gpu_perft<'a>(engine: &'a GpuGlobalData, allocations: &'a GpuAllocations, state: &'a GameState, depth: u32) -> Pin<Box<dyn Future<Output = u64> + 'a>> {
+    Box::pin(async move {
+        if depth == 0 {
+            return 1;
+        }
+
+        let mut tree = GpuTree::new(engine, allocations);
+        tree.set_legal_only(true);
+        tree.init_layer_from_state(state);
+        for _ in 0..depth {
+            if !allocations.fits(tree.last_layer().size() * MAX_MOVES) {
+                drop(tree);
+                let mut sum = 0;
+                for m in state.legal_moves() {
+                    let mut next = state.clone();
+                    next.play(m);
+                    sum += gpu_perft(engine, allocations, &next, depth - 1).await;
+                }
+                return sum;
+            }
+            tree.expand_last_layer().await;
+        }
+        return tree.last_layer().size() as u64;
+    })
+}
+
+/// Like [`gpu_perft`], but broken down per root move
+pub async fn gpu_divide(engine: &GpuGlobalData, allocations: &GpuAllocations, state: &GameState, depth: u32) -> Vec<(Move, u64)> {
+    assert!(depth >= 1);
+    let mut result = Vec::new();
+    for m in state.legal_moves() {
+        let mut next = state.clone();
+        next.play(m);
+        result.push((m, gpu_perft(engine, allocations, &next, depth - 1).await));
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{chess::{GameState, test::GPU_ADAPTER}, gpu::{init_gpu_evaluator, GpuAllocations}};
+
+    use super::{perft, divide, gpu_perft, gpu_divide};
+
+    /// Positions from https://www.chessprogramming.org/Perft_Results, with their node counts per depth (starting at 1)
+    const POSITIONS: [(&str, &[u64]); 6] = [
+        ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902]),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]),
+        ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379]),
+        ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079, 89890]),
+    ];
+
+    #[test]
+    fn cpu_perft() {
+        for (fen, counts) in POSITIONS {
+            let state = GameState::from_fen(fen);
+            for (depth, expected) in counts.iter().enumerate() {
+                assert_eq!(perft(&state, depth as u32 + 1), *expected, "perft {} of {fen}", depth + 1);
+            }
+        }
+    }
+
+    /// Chess960 positions from the same page
+    const CHESS960_POSITIONS: [(&str, &[u64]); 3] = [
+        ("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", &[21, 528, 12189]),
+        ("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", &[21, 807, 18002]),
+        ("b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", &[20, 479, 10471]),
+    ];
+
+    #[test]
+    fn cpu_perft_960() {
+        for (fen, counts) in CHESS960_POSITIONS {
+            let state = GameState::from_fen(fen);
+            for (depth, expected) in counts.iter().enumerate() {
+                assert_eq!(perft(&state, depth as u32 + 1), *expected, "perft {} of {fen}", depth + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_divide() {
+        let state = GameState::from_fen(POSITIONS[0].0);
+        let result = divide(&state, 2);
+        assert_eq!(result.len(), 20);
+        assert!(result.iter().all(|(_, n)| *n == 20));
+    }
+
+    #[tokio::test]
+    async fn gpu_perft_table() {
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let allocations = GpuAllocations::init(engine.device.clone());
+        for (fen, counts) in POSITIONS {
+            let state = GameState::from_fen(fen);
+            for (depth, expected) in counts.iter().enumerate() {
+                assert_eq!(gpu_perft(&engine, &allocations, &state, depth as u32 + 1).await, *expected, "perft {} of {fen}", depth + 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gpu_perft_960() {
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let allocations = GpuAllocations::init(engine.device.clone());
+        for (fen, counts) in CHESS960_POSITIONS {
+            let state = GameState::from_fen(fen);
+            for (depth, expected) in counts.iter().enumerate() {
+                assert_eq!(gpu_perft(&engine, &allocations, &state, depth as u32 + 1).await, *expected, "perft {} of {fen}", depth + 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gpu_divide_matches_cpu() {
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let allocations = GpuAllocations::init(engine.device.clone());
+        let state = GameState::from_fen(POSITIONS[1].0);
+        assert_eq!(gpu_divide(&engine, &allocations, &state, 2).await, divide(&state, 2));
+    }
+}
@@ -0,0 +1,283 @@
+use std::{mem::size_of, path::Path};
+
+use bytemuck::{Pod, Zeroable};
+use log::info;
+use wgpu::{Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, ComputePassDescriptor};
+
+use crate::{chess::dtm::{Dtm, DtmTable, DtmTables, Layout, MAX_PIECES}, gpu::{GpuGlobalData, init_adapter, init_gpu_evaluator}, misc::SliceExtension, shaders::{self, RetrogradeBindGroupMngr, RetrogradeBuffers, RetrogradePass, WORKGROUP_SIZE}};
+
+const USAGE: &str = "usage: apophthegm tablebase generate [--output <dir>] <material>...";
+
+// The kinds of results, have to match `retrograde.wgsl`
+const UNKNOWN: u32 = 0;
+const WIN: u32 = 1;
+const LOSS: u32 = 2;
+const DRAW: u32 = 3;
+
+/// The most passes before giving up, longer mates can't be stored anyway
+const MAX_PASSES: u32 = 256;
+
+/// Has to match `TableParams` in `retrograde.wgsl`, padded to the size of a uniform buffer
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct TableParams {
+    size: u32,
+    row_size: u32,
+    ply: u32,
+    num_pieces: u32,
+    pieces: u32,
+    padding: [u32; 3],
+}
+
+fn encode(dtm: Dtm) -> u32 {
+    return match dtm {
+        Dtm::Win(plies) => plies << 3 | WIN,
+        Dtm::Loss(plies) => plies << 3 | LOSS,
+        Dtm::Draw => DRAW,
+    };
+}
+
+fn decode(result: u32) -> Dtm {
+    return match result & 0x7 {
+        WIN => Dtm::Win(result >> 3),
+        LOSS => Dtm::Loss(result >> 3),
+        // Positions that can't come up are stored as draws
+        _ => Dtm::Draw,
+    };
+}
+
+/// The best result of the moves that capture or promote, for every position of the layout. These leave the table,
+/// so they're looked up in the tables with less material on the cpu
+fn exits(layout: &Layout, tables: &DtmTables) -> Result<Vec<u32>, String> {
+    let mut exits = vec![UNKNOWN; layout.size()];
+    for (index, exit) in exits.iter_mut().enumerate() {
+        let Some(state) = layout.state(index) else { continue; };
+        let mut best: Option<Dtm> = None;
+        let mut next = state.clone();
+        for m in state.legal_moves() {
+            if m.2.is_none() && state.get(m.1).is_none() {
+                continue;
+            }
+            let undo = next.play(m);
+            let dtm = tables.probe(&next).ok_or_else(|| format!("{} needs the table for {}", layout.name(), next.to_fen()))?;
+            next.unplay(m, undo);
+            let dtm = dtm.before_move();
+            if !best.is_some_and(|best| best.rank() >= dtm.rank()) {
+                best = Some(dtm);
+            }
+        }
+        *exit = best.map_or(UNKNOWN, encode);
+    }
+    return Ok(exits);
+}
+
+fn storage_buffer(engine: &GpuGlobalData, label: &str, size: u64, usage: BufferUsages) -> Buffer {
+    return engine.device.create_buffer(&BufferDescriptor { label: Some(label), size, usage, mapped_at_creation: false });
+}
+
+/// Generates the table of a layout on the gpu. The tables that captures and promotions lead to have to be in `tables`.
+///
+/// Every pass gives results to the positions that are as many plies from mate as the pass, starting with the mates,
+/// and then marks the positions before them by taking back moves. Positions that are left when nothing changes anymore are draws
+pub async fn generate(engine: &GpuGlobalData, layout: &Layout, tables: &DtmTables) -> Result<DtmTable, String> {
+    let size = layout.size() as u64;
+    let results_size = size * size_of::<u32>() as u64;
+    let limits = engine.device.limits();
+    if results_size > limits.max_storage_buffer_binding_size as u64 || results_size > limits.max_buffer_size {
+        return Err(format!("{} doesn't fit in a buffer on this gpu", layout.name()));
+    }
+
+    let exits = exits(layout, tables)?;
+    // Exits can give a result in any pass, so the passes go on until the furthest one has had its turn
+    let furthest_exit = exits.iter().map(|exit| exit >> 3).max().unwrap_or(0);
+
+    let resolve = shaders::retrograde(&engine.device, RetrogradePass::Resolve);
+    let unmove = shaders::retrograde(&engine.device, RetrogradePass::Unmove);
+    let params = storage_buffer(engine, "Table Params", size_of::<TableParams>() as u64, BufferUsages::UNIFORM | BufferUsages::COPY_DST);
+    let results = storage_buffer(engine, "Table Results", results_size, BufferUsages::STORAGE | BufferUsages::COPY_SRC);
+    let exits_buf = storage_buffer(engine, "Table Exits", results_size, BufferUsages::STORAGE | BufferUsages::COPY_DST);
+    // A bit per position, rounded up to whole words
+    let candidates = storage_buffer(engine, "Table Candidates", size.div_ceil(32) * size_of::<u32>() as u64, BufferUsages::STORAGE | BufferUsages::COPY_DST);
+    let changed = storage_buffer(engine, "Table Changed", size_of::<u32>() as u64, BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST);
+    let changed_staging = storage_buffer(engine, "Table Changed Staging", size_of::<u32>() as u64, BufferUsages::COPY_DST | BufferUsages::MAP_READ);
+    let results_staging = storage_buffer(engine, "Table Results Staging", results_size, BufferUsages::COPY_DST | BufferUsages::MAP_READ);
+    engine.queue.write_buffer(&exits_buf, 0, bytemuck::cast_slice(&exits));
+
+    let buffers = || RetrogradeBuffers { params: &params, results: &results, exits: &exits_buf, candidates: &candidates, changed: &changed };
+    let resolve_bind = RetrogradeBindGroupMngr::create(&engine.device, &resolve, buffers());
+    let unmove_bind = RetrogradeBindGroupMngr::create(&engine.device, &unmove, buffers());
+
+    // Big tables need more workgroups than fit in one dimension
+    let workgroups = size.div_ceil(WORKGROUP_SIZE);
+    let columns = workgroups.min(limits.max_compute_workgroups_per_dimension as u64);
+    let rows = workgroups.div_ceil(columns);
+    let mut table_params = TableParams {
+        size: size as u32,
+        row_size: (columns * WORKGROUP_SIZE) as u32,
+        ply: 0,
+        num_pieces: layout.pieces().len() as u32,
+        pieces: layout.gpu_pieces(),
+        padding: [0; 3],
+    };
+
+    for ply in 0..MAX_PASSES {
+        table_params.ply = ply;
+        engine.queue.write_buffer(&params, 0, bytemuck::bytes_of(&table_params));
+        let mut command_encoder = engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
+        let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass_encoder.set_pipeline(&resolve.1);
+        pass_encoder.set_bind_group(0, &resolve_bind, &[]);
+        pass_encoder.dispatch_workgroups(columns as u32, rows as u32, 1);
+        drop(pass_encoder);
+        command_encoder.copy_buffer_to_buffer(&changed, 0, &changed_staging, 0, size_of::<u32>() as u64);
+        command_encoder.clear_buffer(&changed, 0, None);
+        command_encoder.clear_buffer(&candidates, 0, None);
+        let mut pass_encoder = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass_encoder.set_pipeline(&unmove.1);
+        pass_encoder.set_bind_group(0, &unmove_bind, &[]);
+        pass_encoder.dispatch_workgroups(columns as u32, rows as u32, 1);
+        drop(pass_encoder);
+        engine.queue.submit([command_encoder.finish()]);
+
+        changed_staging.slice(..).map_buffer(&engine.device, wgpu::MapMode::Read).await.unwrap();
+        let changed_view = changed_staging.slice(..).get_mapped_range();
+        let changed_count = u32::from_le(*bytemuck::from_bytes(&changed_view[..]));
+        drop(changed_view);
+        changed_staging.unmap();
+        info!("{}: pass {ply} gave {changed_count} positions their result", layout.name());
+
+        if changed_count == 0 && ply >= furthest_exit {
+            let mut command_encoder = engine.device.create_command_encoder(&CommandEncoderDescriptor::default());
+            command_encoder.copy_buffer_to_buffer(&results, 0, &results_staging, 0, results_size);
+            engine.queue.submit([command_encoder.finish()]);
+            results_staging.slice(..).map_buffer(&engine.device, wgpu::MapMode::Read).await.unwrap();
+            let view = results_staging.slice(..).get_mapped_range();
+            let table = DtmTable::new(layout.clone(), bytemuck::cast_slice::<_, u32>(&view[..]).iter().map(|result| decode(*result)));
+            return table.map_err(|e| format!("can't store {}: {e}", layout.name()));
+        }
+    }
+    return Err(format!("{} has mates longer than {MAX_PASSES} plies", layout.name()));
+}
+
+/// Generates the tables for the layouts, and first the ones they lead to that aren't in `tables` yet. Every table is
+/// added to `tables`, and their names are returned in the order they were made
+pub async fn generate_all(engine: &GpuGlobalData, layouts: &[Layout], tables: &mut DtmTables) -> Result<Vec<String>, String> {
+    fn dependencies_first(layout: &Layout, tables: &DtmTables, order: &mut Vec<Layout>) {
+        if order.contains(layout) {
+            return;
+        }
+        for next in layout.successors() {
+            if !tables.contains(&next) {
+                dependencies_first(&next, tables, order);
+            }
+        }
+        order.push(layout.clone());
+    }
+    let mut order = Vec::new();
+    for layout in layouts {
+        dependencies_first(layout, tables, &mut order);
+    }
+
+    let mut names = Vec::new();
+    for layout in order {
+        let table = generate(engine, &layout, tables).await?;
+        names.push(layout.name());
+        tables.insert(table);
+    }
+    return Ok(names);
+}
+
+/// Runs `apophthegm tablebase <args>`, the only subcommand is `generate`
+pub async fn run(args: &[String]) -> Result<(), String> {
+    let mut args = args.iter();
+    if args.next().map(String::as_str) != Some("generate") {
+        return Err(USAGE.to_owned());
+    }
+
+    let mut output = ".".to_owned();
+    let mut layouts = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" => output = args.next().ok_or(format!("{arg} needs a path\n{USAGE}"))?.clone(),
+            _ => layouts.push(Layout::from_name(arg).ok_or(format!("\"{arg}\" isn't a material with 3 to {MAX_PIECES} pieces and pawns on one side at most, like KQvK\n{USAGE}"))?),
+        }
+    }
+    if layouts.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+
+    // Tables that were made before don't have to be made again to make the ones that need them
+    std::fs::create_dir_all(&output).map_err(|e| format!("can't create \"{output}\": {e}"))?;
+    let mut tables = DtmTables::open(&output).map_err(|e| format!("can't read the tables in \"{output}\": {e}"))?;
+    let adapter = init_adapter().await;
+    let engine = init_gpu_evaluator(&adapter).await;
+
+    for name in generate_all(&engine, &layouts, &mut tables).await? {
+        let layout = Layout::from_name(&name).unwrap();
+        let table = tables.get(&layout).unwrap();
+        let path = Path::new(&output).join(table.file_name());
+        table.write(&path).map_err(|e| format!("can't write \"{}\": {e}", path.display()))?;
+        let longest = (0..layout.size()).filter_map(|i| match table.get(i) { Dtm::Win(plies) => Some(plies), _ => None }).max();
+        match longest {
+            Some(plies) => println!("{name}: mate in at most {} moves, wrote {}", plies.div_ceil(2), path.display()),
+            None => println!("{name}: no wins, wrote {}", path.display()),
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{chess::{GameState, Side, dtm::{Dtm, DtmTables, Layout}, test::GPU_ADAPTER}, gpu::init_gpu_evaluator};
+
+    use super::generate_all;
+
+    /// The result of the position from the results after each of its moves, which the tables have to agree with
+    fn minimax(tables: &DtmTables, state: &GameState) -> Dtm {
+        let moves = state.legal_moves();
+        if moves.is_empty() {
+            return if state.in_check() { Dtm::Loss(0) } else { Dtm::Draw };
+        }
+        let mut next = state.clone();
+        return moves.into_iter().map(|m| {
+            let undo = next.play(m);
+            let dtm = tables.probe(&next).unwrap().before_move();
+            next.unplay(m, undo);
+            return dtm;
+        }).max_by_key(|dtm| dtm.rank()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn generate_tables() {
+        let engine = init_gpu_evaluator(&GPU_ADAPTER).await;
+        let layouts = ["KQvK", "KRvK"].map(|name| Layout::from_name(name).unwrap());
+        let mut tables = DtmTables::default();
+        assert_eq!(generate_all(&engine, &layouts, &mut tables).await.unwrap(), ["KQvK", "KRvK"]);
+
+        for (layout, longest) in layouts.iter().zip([19, 31]) {
+            // The longest mates are in 10 and 16 moves
+            let table = tables.get(layout).unwrap();
+            let wins = (0..layout.size()).filter_map(|i| match table.get(i) { Dtm::Win(plies) => Some(plies), _ => None });
+            assert_eq!(wins.max(), Some(longest), "{}", layout.name());
+
+            // Which only holds if the gpu reads the indices like the cpu, and the passes go through the distances in order
+            for i in (0..layout.size()).step_by(101) {
+                if let Some(state) = layout.state(i) {
+                    assert_eq!(table.get(i), minimax(&tables, &state), "{}", state.to_fen());
+                }
+            }
+        }
+
+        // Stalemates, a queen that can be taken, and positions that can't come up
+        for fen in ["k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", "k7/8/K7/8/8/8/8/1R6 b - - 0 1", "8/8/8/8/8/8/3kQ3/7K b - - 0 1"] {
+            assert_eq!(tables.probe(&GameState::from_fen(fen)), Some(Dtm::Draw), "{fen}");
+        }
+        let table = tables.get(&layouts[0]).unwrap();
+        // Black is in check with white to move, and two pieces share a square
+        for (squares, to_move) in [([41, 7, 56], Side::White), ([1, 1, 56], Side::Black)] {
+            let index = layouts[0].index(&squares, to_move);
+            assert!(layouts[0].state(index).is_none());
+            assert_eq!(table.get(index), Dtm::Draw);
+        }
+    }
+}
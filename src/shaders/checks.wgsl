@@ -0,0 +1,58 @@
+@group(0) @binding(0)
+var<uniform> globals: GlobalData;
+@group(0) @binding(1)
+var<storage, read_write> boards: array<Board>;
+@group(0) @binding(2)
+var<storage, read> zobrist_keys: array<vec2<u32>, 787>;
+
+// Counts the checks for Three-check. This is a pass of its own, doing it for every child in the expansion pass makes that too big to compile
+@compute @workgroup_size(64)
+fn checks_pass(
+  @builtin(global_invocation_id)
+  global_id : vec3u,
+
+  @builtin(local_invocation_id)
+  local_id : vec3u,
+) {
+  // Avoid accessing the buffer out of bounds
+  if (global_id.x >= globals.input_size) {
+    return;
+  }
+  var board = boards[global_id.x + globals.buf_offset_1];
+  // The side that made the move which resulted in this board
+  let last_moved = globals.to_move;
+
+  var found = false;
+  var king_x = 0u;
+  var king_y = 0u;
+  for (var x = 0u; x < 8u; x++) {
+    for (var y = 0u; y < 8u; y++) {
+      if (getPiece(&board, x, y) == (King | (last_moved ^ 0x8u))) {
+        found = true;
+        king_x = x;
+        king_y = y;
+      }
+    }
+  }
+  if (!found || !isAttacked(&board, king_x, king_y, last_moved)) {
+    return;
+  }
+
+  let checks = getChecks(&board, last_moved);
+  if (checks == 3u) {
+    return;
+  }
+  var shift = ChecksShift + 2u;
+  var side = 1u; // White is 0, like in the zobrist keys
+  if (last_moved == 0x8u) {
+    shift = ChecksShift;
+    side = 0u;
+  }
+  board.pieces[9] = (board.pieces[9] & ~(0x3u << shift)) | ((checks + 1u) << shift);
+  // Swap the key for the old count with the one for the new count
+  if (checks != 0u) {
+    board.hash ^= zobrist_keys[781u + side * 3u + checks - 1u];
+  }
+  board.hash ^= zobrist_keys[781u + side * 3u + checks];
+  boards[global_id.x + globals.buf_offset_1] = board;
+}
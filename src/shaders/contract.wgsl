@@ -21,7 +21,12 @@ fn contract_pass(
   }
   var board = child_boards[global_id.x + globals.buf_offset_1];
   let prev_index = getPrev(&board, globals.move_index);
-  let child_eval = child_evals[global_id.x + globals.buf_offset_2];
+  var child_eval = child_evals[global_id.x + globals.buf_offset_2];
+  // The game is over when a variant's win condition is met, so whatever comes after it doesn't count
+  let win = variantWinScore(&board, globals.variant);
+  if (win != 0) {
+    child_eval = u32(win) ^ (1u<<31u);
+  }
 
   if (child_eval != 0x00000000u && child_eval != 0xFFFFFFFFu) {
     switch globals.to_move {
@@ -18,7 +18,17 @@ fn eval_contract_pass(
     return;
   }
   var board = child_boards[global_id.x + globals.buf_offset_1];
-  let score = u32(evalPosition(&board)) ^ (1u<<31u);
+  // A game that was won by a rule of the variant doesn't need to be evaluated
+  var eval_score = variantWinScore(&board, globals.variant);
+  if (eval_score == 0) {
+    if (board.pieces[TablebaseWord] != 0u) {
+      // The side to move on the child is the one that doesn't move on the parent
+      eval_score = tablebaseScore(&board, globals.to_move ^ 0x8u);
+    } else {
+      eval_score = evalPosition(&board);
+    }
+  }
+  let score = u32(eval_score) ^ (1u<<31u);
   let prev_index = getPrev(&board, globals.move_index);
 
   switch globals.to_move {
@@ -6,6 +6,8 @@ var<storage, read_write> output: array<Board>;
 var<storage, read_write> out_index: atomic<u32>;
 @group(0) @binding(2)
 var<uniform> globals: GlobalData;
+@group(0) @binding(4)
+var<storage, read> zobrist_keys: array<vec2<u32>, 787>;
 
 @compute @workgroup_size(64)
 fn expansion_pass(
@@ -21,6 +23,10 @@ fn expansion_pass(
   }
   var board = input[global_id.x + globals.buf_offset_0];
   let to_move = globals.to_move;
+  // Only the board itself was found in the tablebases, not its children
+  board.pieces[TablebaseWord] = 0u;
+  // Only keep the pieces in the hash while generating children, the rest is added back by `pushBoard`
+  board.hash ^= stateKey(&board, to_move);
 
   var pawn_start_rank = 6u; // 0-indexed!
   if (to_move == 0x8u) {
@@ -49,8 +55,10 @@ fn expansion_pass(
 
             if (y == pawn_start_rank && getPiece(&board, x, y+(offset*2u)) == 0u) {
               var new_board2 = movePiece(&board, piece, x, y, x, y+(offset*2u), global_id.x);
-              let out = atomicAdd(&out_index, 1u);
-              output[out + globals.buf_offset_1] = new_board2;
+              // The square that was skipped over can be captured en passant
+              new_board2.pieces[9] |= (x << 4u) | ((y+offset) << 7u);
+              new_board2.pieces[10] |= MoveDoublePush;
+              pushBoard(&new_board2);
             }
           }
           // Capture
@@ -61,6 +69,7 @@ fn expansion_pass(
           if (x - 1u < 8u && isOpponent(&board, to_move, x - 1u, y+offset)) {
             pawn_move(&board, x, y, x - 1u, y+offset, pawn_promote_rank, to_move, global_id.x);
           }
+          try_en_passant(&board, piece, x, y, y+offset, global_id.x);
         } else if (piece_type == King) {
           try_move(&board, piece, x, y, (x - 1u), (y - 1u), to_move, global_id.x);
           try_move(&board, piece, x, y, (x + 0u), (y - 1u), to_move, global_id.x);
@@ -70,6 +79,7 @@ fn expansion_pass(
           try_move(&board, piece, x, y, (x - 1u), (y + 1u), to_move, global_id.x);
           try_move(&board, piece, x, y, (x + 0u), (y + 1u), to_move, global_id.x);
           try_move(&board, piece, x, y, (x + 1u), (y + 1u), to_move, global_id.x);
+          try_castle(&board, piece, x, y, to_move, global_id.x);
         } else if (piece_type == Horsy) {
           try_move(&board, piece, x, y, (x + 2u), (y - 1u), to_move, global_id.x);
           try_move(&board, piece, x, y, (x + 2u), (y + 1u), to_move, global_id.x);
@@ -104,18 +114,19 @@ fn move_in_dir(board: ptr<function, Board>, piece: u32, x: u32, y: u32, dx: i32,
   if (yNew >= 8u) { return; }
   if (isColour(board, to_move, xNew, yNew)) { return; }
   var new_board = *board;
-  new_board.pieces[y] &= ~(0xFu << (x*4u)); // Remove the original piece
+  setSquare(&new_board, x, y, 0u); // Remove the original piece
+  let from_meta = (*board).pieces[9] & ~(castleMask(board, x, y) | EnPassantMask);
   loop {
     let target_square = getPiece(board, xNew, yNew);
     if (target_square != 0u && (target_square & 0x8u) == to_move) {
       // Trying to move to a square with an own piece
       return;
     }
-    new_board.pieces[yNew] &= ~(0xFu << (xNew*4u));
-    new_board.pieces[yNew] |= (piece << (xNew*4u));
+    setSquare(&new_board, xNew, yNew, piece);
+    new_board.pieces[9] = from_meta & ~castleMask(board, xNew, yNew);
     setPrev(&new_board, prev);
-    let out = atomicAdd(&out_index, 1u);
-    output[out + globals.buf_offset_1] = new_board;
+    setMove(&new_board, moveRecord(board, x, y, xNew, yNew));
+    pushBoard(&new_board);
 
     if (target_square != 0u && (target_square & 0x8u) != to_move) {
       // This was a capture, no more moves
@@ -126,17 +137,78 @@ fn move_in_dir(board: ptr<function, Board>, piece: u32, x: u32, y: u32, dx: i32,
     yNew = yNew + u32(dy);
     if (xNew >= 8u) { return; }
     if (yNew >= 8u) { return; }
-    new_board.pieces[yNew - u32(dy)] &= ~(0xFu << ((xNew - u32(dx))*4u));
+    setSquare(&new_board, xNew - u32(dx), yNew - u32(dy), 0u);
   }
 }
 
+fn try_en_passant(board: ptr<function, Board>, piece: u32, x: u32, y: u32, yNew: u32, prev: u32) {
+  if (!hasEnPassant(board)) { return; }
+  let ep = getEnPassant(board);
+  if (ep.y != yNew || (ep.x + 1u != x && x + 1u != ep.x)) { return; }
+
+  var new_board = movePiece(board, piece, x, y, ep.x, yNew, prev);
+  setSquare(&new_board, ep.x, y, 0u); // Remove the captured pawn
+  new_board.pieces[10] |= MoveCapture | MoveEnPassant;
+  pushBoard(&new_board);
+}
+
+fn try_castle(board: ptr<function, Board>, piece: u32, x: u32, y: u32, to_move: u32, prev: u32) {
+  var home_rank = 7u;
+  var kingside = BlackKingside;
+  var queenside = BlackQueenside;
+  if (to_move == 0x8u) {
+    home_rank = 0u;
+    kingside = WhiteKingside;
+    queenside = WhiteQueenside;
+  }
+  let files = getCastleFiles(board, to_move);
+  if (x != files.x || y != home_rank) { return; }
+
+  let opponent = to_move ^ 0x8u;
+  let castles = getCastles(board);
+  // The king may not castle out of, through, or into check
+  if ((castles & (kingside | queenside)) == 0u || isAttacked(board, x, y, opponent)) { return; }
+
+  if ((castles & kingside) != 0u) {
+    try_castle_wing(board, piece, x, y, files.y, 6u, 5u, opponent, prev);
+  }
+  if ((castles & queenside) != 0u) {
+    try_castle_wing(board, piece, x, y, files.z, 2u, 3u, opponent, prev);
+  }
+}
+
+// The king and rook end up on the same squares as in normal chess, wherever they started
+fn try_castle_wing(board: ptr<function, Board>, king: u32, x: u32, y: u32, rook_x: u32, king_to: u32, rook_to: u32, opponent: u32, prev: u32) {
+  let rook = Rook | (king & 0x8u);
+  if (getPiece(board, rook_x, y) != rook) { return; }
+  // Everything between where the king and rook start and end has to be empty, apart from the king and rook themselves
+  let left = min(min(x, rook_x), min(king_to, rook_to));
+  let right = max(max(x, rook_x), max(king_to, rook_to));
+  for (var i = left; i <= right; i++) {
+    if (i != x && i != rook_x && getPiece(board, i, y) != 0u) { return; }
+  }
+  for (var i = min(x, king_to); i <= max(x, king_to); i++) {
+    if (isAttacked(board, i, y, opponent)) { return; }
+  }
+
+  var new_board = *board;
+  setSquare(&new_board, x, y, 0u);
+  setSquare(&new_board, rook_x, y, 0u);
+  setSquare(&new_board, king_to, y, king);
+  setSquare(&new_board, rook_to, y, rook);
+  updateMeta(&new_board, x, y, king_to, y);
+  setPrev(&new_board, prev);
+  // Not through `moveRecord`, the king landing on its own rook isn't a capture
+  setMove(&new_board, (y * 8u + x) | ((y * 8u + rook_x) << MoveToShift) | MoveCastle);
+  pushBoard(&new_board);
+}
+
 fn try_move(board: ptr<function, Board>, piece: u32, x: u32, y: u32, xNew: u32, yNew: u32, to_move: u32, prev: u32) {
   if (xNew >= 8u) { return; }
   if (yNew >= 8u) { return; }
   if (!isColour(board, to_move, xNew, yNew)) {
     var new_board = movePiece(board, piece, x, y, xNew, yNew, prev);
-    let out = atomicAdd(&out_index, 1u);
-    output[out + globals.buf_offset_1] = new_board;
+    pushBoard(&new_board);
   }
 }
 
@@ -144,26 +216,22 @@ fn pawn_move(board: ptr<function, Board>, x: u32, y: u32, xNew: u32, yNew: u32,
   if (yNew == pawn_promote_rank) {
     // Promote
     var new_board = *board;
-    new_board.pieces[y] &= ~(0xFu << (x*4u)); // Remove the original pawn
+    setSquare(&new_board, x, y, 0u); // Remove the original pawn
+    updateMeta(&new_board, x, y, xNew, yNew);
     setPrev(&new_board, prev);
-    let clear_mask = ~(0xFu << (xNew*4u));
+    let record = moveRecord(board, x, y, xNew, yNew);
+    var promotions = array<u32, 4>(Queen, Bishop, Horsy, Rook);
     let out = atomicAdd(&out_index, 4u);
-    new_board.pieces[yNew] &= clear_mask;
-    new_board.pieces[yNew] |= ((Queen | to_move) << (xNew*4u));
-    output[out + globals.buf_offset_1] = new_board;
-    new_board.pieces[yNew] &= clear_mask;
-    new_board.pieces[yNew] |= ((Bishop | to_move) << (xNew*4u));
-    output[out+1u + globals.buf_offset_1] = new_board;
-    new_board.pieces[yNew] &= clear_mask;
-    new_board.pieces[yNew] |= ((Horsy | to_move) << (xNew*4u));
-    output[out+2u + globals.buf_offset_1] = new_board;
-    new_board.pieces[yNew] &= clear_mask;
-    new_board.pieces[yNew] |= ((Rook | to_move) << (xNew*4u));
-    output[out+3u + globals.buf_offset_1] = new_board;
+    for (var i = 0u; i < 4u; i++) {
+      var promoted = new_board;
+      setSquare(&promoted, xNew, yNew, promotions[i] | to_move);
+      setMove(&promoted, record | (promotions[i] << MovePromotionShift));
+      finishHash(&promoted);
+      output[out+i + globals.buf_offset_1] = promoted;
+    }
   } else {
     var new_board = movePiece(board, (Pawn | to_move), x, y, xNew, yNew, prev);
-    let out = atomicAdd(&out_index, 1u);
-    output[out + globals.buf_offset_1] = new_board;
+    pushBoard(&new_board);
   }
 }
 
@@ -179,13 +247,116 @@ fn isOpponent(board: ptr<function, Board>, to_move: u32, x: u32, y: u32) -> bool
 
 fn movePiece(board: ptr<function, Board>, piece: u32, x: u32, y: u32, xNew: u32, yNew: u32, prev: u32) -> Board {
   var new_board = *board;
-  new_board.pieces[y] &= ~(0xFu << (x*4u));
-  new_board.pieces[yNew] &= ~(0xFu << (xNew*4u));
-  new_board.pieces[yNew] |= (piece << (xNew*4u));
+  setSquare(&new_board, x, y, 0u);
+  setSquare(&new_board, xNew, yNew, piece);
+  updateMeta(&new_board, x, y, xNew, yNew);
   setPrev(&new_board, prev);
+  setMove(&new_board, moveRecord(board, x, y, xNew, yNew));
   return new_board;
 }
 
+// The castling rights that are lost when a piece moves from, or gets captured on, this square
+fn castleMask(board: ptr<function, Board>, x: u32, y: u32) -> u32 {
+  var side = 0u;
+  var kingside = BlackKingside;
+  var queenside = BlackQueenside;
+  if (y == 0u) {
+    side = 0x8u;
+    kingside = WhiteKingside;
+    queenside = WhiteQueenside;
+  } else if (y != 7u) {
+    return 0u;
+  }
+  let files = getCastleFiles(board, side);
+  var mask = 0u;
+  if (x == files.x) { mask |= kingside | queenside; }
+  if (x == files.y) { mask |= kingside; }
+  if (x == files.z) { mask |= queenside; }
+  return mask;
+}
+
+// Updates the castling rights, and removes the en passant square of the parent
+fn updateMeta(board: ptr<function, Board>, x: u32, y: u32, xNew: u32, yNew: u32) {
+  (*board).pieces[9] &= ~(castleMask(board, x, y) | castleMask(board, xNew, yNew) | EnPassantMask);
+}
+
 fn setPrev(board: ptr<function, Board>, id: u32) {
   (*board).pieces[8] = id;
-}
\ No newline at end of file
+}
+
+fn setMove(board: ptr<function, Board>, record: u32) {
+  (*board).pieces[10] = record;
+}
+
+// The squares of a move, and whether it captures on `board`, which is the parent. The other flags are added by the caller
+fn moveRecord(board: ptr<function, Board>, x: u32, y: u32, xNew: u32, yNew: u32) -> u32 {
+  var record = (y * 8u + x) | ((yNew * 8u + xNew) << MoveToShift);
+  if (getPiece(board, xNew, yNew) != 0u) {
+    record |= MoveCapture;
+  }
+  return record;
+}
+
+// Replaces whatever is on the square, keeping the hash up to date
+fn setSquare(board: ptr<function, Board>, x: u32, y: u32, piece: u32) {
+  hashPiece(board, getPiece(board, x, y), x, y);
+  hashPiece(board, piece, x, y);
+  (*board).pieces[y] &= ~(0xFu << (x*4u));
+  (*board).pieces[y] |= (piece << (x*4u));
+}
+
+// Toggles a piece in the hash, the key layout matches `chess::zobrist::KEYS`
+fn hashPiece(board: ptr<function, Board>, piece: u32, x: u32, y: u32) {
+  if (piece == 0u) { return; }
+  let side = 1u - (piece >> 3u); // White is 0
+  let index = (side * 6u + (piece & 0x7u) - 1u) * 64u + y * 8u + x;
+  (*board).hash ^= zobrist_keys[index];
+}
+
+// The part of the hash for the side to move, the castling rights, the en passant square and the checks
+fn stateKey(board: ptr<function, Board>, to_move: u32) -> vec2<u32> {
+  let metadata = (*board).pieces[9];
+  var key = vec2(0u, 0u);
+  if (to_move == 0u) {
+    key ^= zobrist_keys[768u];
+  }
+  for (var i = 0u; i < 4u; i++) {
+    if ((metadata & (1u << i)) != 0u) {
+      key ^= zobrist_keys[769u + i];
+    }
+  }
+  if (canTakeEnPassant(board, to_move)) {
+    key ^= zobrist_keys[773u + ((metadata >> 4u) & 0x7u)];
+  }
+  for (var side = 0u; side < 2u; side++) {
+    let checks = (metadata >> (ChecksShift + side * 2u)) & 0x3u;
+    if (checks != 0u) {
+      key ^= zobrist_keys[781u + side * 3u + checks - 1u];
+    }
+  }
+  return key;
+}
+
+// The en passant file is only hashed when a pawn of the side to move is next to the pawn that moved, like `zobrist::state_key`
+fn canTakeEnPassant(board: ptr<function, Board>, to_move: u32) -> bool {
+  if (!hasEnPassant(board)) { return false; }
+  let ep = getEnPassant(board);
+  var pawn_rank = 3u;
+  if (to_move == 0x8u) {
+    pawn_rank = 4u;
+  }
+  let pawn = Pawn | to_move;
+  return (ep.x > 0u && getPiece(board, ep.x - 1u, pawn_rank) == pawn) || (ep.x < 7u && getPiece(board, ep.x + 1u, pawn_rank) == pawn);
+}
+
+// Adds the state of a child back into its hash, which only covered the pieces until now
+fn finishHash(board: ptr<function, Board>) {
+  (*board).hash ^= stateKey(board, globals.to_move ^ 0x8u);
+}
+
+fn pushBoard(board: ptr<function, Board>) {
+  var out_board = *board;
+  finishHash(&out_board);
+  let out = atomicAdd(&out_index, 1u);
+  output[out + globals.buf_offset_1] = out_board;
+}
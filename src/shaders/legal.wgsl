@@ -0,0 +1,52 @@
+@group(0) @binding(0)
+var<uniform> globals: GlobalData;
+@group(0) @binding(1)
+var<storage, read_write> input: array<Board>;
+@group(0) @binding(2)
+var<storage, read_write> output: array<Board>;
+@group(0) @binding(3)
+var<storage, read_write> out_index: atomic<u32>;
+
+@compute @workgroup_size(64)
+fn legal_pass(
+  @builtin(global_invocation_id)
+  global_id : vec3u,
+
+  @builtin(local_invocation_id)
+  local_id : vec3u,
+) {
+  // Avoid accessing the buffer out of bounds
+  if (global_id.x >= globals.input_size) {
+    return;
+  }
+  var board = input[global_id.x + globals.buf_offset_1];
+  // The side that made the move which resulted in this board
+  let last_moved = globals.to_move;
+
+  var kings = vec2(0u, 0u); // Black, white
+  var king_x = 0u;
+  var king_y = 0u;
+  for (var x = 0u; x < 8u; x++) {
+    for (var y = 0u; y < 8u; y++) {
+      let piece = getPiece(&board, x, y);
+      if ((piece & 0x7u) == King) {
+        kings[piece >> 3u] += 1u;
+        if ((piece & 0x8u) == last_moved) {
+          king_x = x;
+          king_y = y;
+        }
+      }
+    }
+  }
+
+  if (kings.x != 1u || kings.y != 1u) {
+    return;
+  }
+  // The king that last moved cannot be in check
+  if (isAttacked(&board, king_x, king_y, last_moved ^ 0x8u)) {
+    return;
+  }
+
+  let out = atomicAdd(&out_index, 1u);
+  output[out + globals.buf_offset_2] = board;
+}
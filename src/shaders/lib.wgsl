@@ -5,8 +5,48 @@ const Rook = 4u;
 const Horsy = 5u;
 const Pawn = 6u;
 
+// Castling rights, stored in the metadata word
+const WhiteKingside = 0x1u;
+const WhiteQueenside = 0x2u;
+const BlackKingside = 0x4u;
+const BlackQueenside = 0x8u;
+// En passant target square, stored in the metadata word as 0byyyxxx0000.
+// It's never on the first rank, so there isn't one if the rank is 0
+const EnPassantMask = 0x3F0u;
+const EnPassantRank = 0x380u;
+// Starting files of the king, kingside rook and queenside rook, 3 bits each from bit 10 for white and bit 19 for black.
+// They only differ from e, h and a in Chess960
+const CastleFilesShift = 10u;
+// The checks each side has given in Three-check, 2 bits each from bit 28 for white and bit 30 for black
+const ChecksShift = 28u;
+
+// The variant that's played, has to match `Variant::gpu_representation`
+const Standard = 0u;
+const KingOfTheHill = 1u;
+const ThreeCheck = 2u;
+// Winning by a rule of the variant is worth more than any material
+const VariantWinScore = 1000000;
+
+// The move that produced a board, stored in word 10 as 0bffffpppttttttssssss, with the squares as y * 8 + x.
+// Castling is written as the king moving onto its own rook, the promotion is the piece type
+const MoveToShift = 6u;
+const MovePromotionShift = 12u;
+const MoveCapture = 0x8000u;
+const MoveEnPassant = 0x10000u;
+const MoveCastle = 0x20000u;
+const MoveDoublePush = 0x40000u;
+
+// The tablebase result of a leaf board, stored in word 11 by the cpu as the result for the side to move plus 3, from 1 for a loss to 5 for a win.
+// 0 means it wasn't found, or wasn't probed
+const TablebaseWord = 11u;
+// A tablebase win is worth more than any material, but less than taking the king
+const TablebaseWinScore = 50000;
+
 struct Board {
-  pieces: array<u32, 9>
+  // 0..8: one row of nibbles each, 8: index of the parent board, 9: metadata, 10: the move from the parent, 11: the tablebase result
+  pieces: array<u32, 12>,
+  // The zobrist key of the position, as (low, high) words
+  hash: vec2<u32>,
 }
 
 struct GlobalData {
@@ -17,6 +57,7 @@ struct GlobalData {
   buf_offset_1: u32,
   buf_offset_2: u32,
   buf_offset_3: u32,
+  variant: u32,
 }
 
 fn getPiece(board: ptr<function, Board>, x: u32, y: u32) -> u32 {
@@ -27,6 +68,142 @@ fn getPrev(board: ptr<function, Board>, index: u32) -> u32 {
   return (*board).pieces[8];
 }
 
+fn getCastles(board: ptr<function, Board>) -> u32 {
+  return (*board).pieces[9] & 0xFu;
+}
+
+// The starting files of the king and rooks of a side, as (king, kingside rook, queenside rook)
+fn getCastleFiles(board: ptr<function, Board>, side: u32) -> vec3<u32> {
+  var shift = CastleFilesShift + 9u;
+  if (side == 0x8u) {
+    shift = CastleFilesShift;
+  }
+  let files = (*board).pieces[9] >> shift;
+  return vec3(files & 0x7u, (files >> 3u) & 0x7u, (files >> 6u) & 0x7u);
+}
+
+fn hasEnPassant(board: ptr<function, Board>) -> bool {
+  return ((*board).pieces[9] & EnPassantRank) != 0u;
+}
+
+// The square a pawn can move to when capturing en passant, only meaningful if `hasEnPassant` is true
+fn getEnPassant(board: ptr<function, Board>) -> vec2<u32> {
+  let metadata = (*board).pieces[9];
+  return vec2((metadata >> 4u) & 0x7u, (metadata >> 7u) & 0x7u);
+}
+
+// How many times a side has given check, only counted in Three-check
+fn getChecks(board: ptr<function, Board>, side: u32) -> u32 {
+  var shift = ChecksShift + 2u;
+  if (side == 0x8u) {
+    shift = ChecksShift;
+  }
+  return ((*board).pieces[9] >> shift) & 0x3u;
+}
+
+fn hasPiece(board: ptr<function, Board>, piece: u32, x: u32, y: u32) -> bool {
+  // Coordinates that went "negative" have wrapped around, so they're caught by this check as well
+  if (x >= 8u || y >= 8u) { return false; }
+  return getPiece(board, x, y) == piece;
+}
+
+// Checks if the first piece in a direction is either `a` or `b`
+fn slideHits(board: ptr<function, Board>, x: u32, y: u32, dx: i32, dy: i32, a: u32, b: u32) -> bool {
+  var xNew = x + u32(dx);
+  var yNew = y + u32(dy);
+  loop {
+    if (xNew >= 8u || yNew >= 8u) { return false; }
+    let p = getPiece(board, xNew, yNew);
+    if (p != 0u) {
+      return p == a || p == b;
+    }
+    xNew = xNew + u32(dx);
+    yNew = yNew + u32(dy);
+  }
+  return false;
+}
+
+// Whether the square can be captured by any piece of the colour `by`
+fn isAttacked(board: ptr<function, Board>, x: u32, y: u32, by: u32) -> bool {
+  let horsy = Horsy | by;
+  if (hasPiece(board, horsy, x + 1u, y + 2u) || hasPiece(board, horsy, x - 1u, y + 2u) ||
+      hasPiece(board, horsy, x + 1u, y - 2u) || hasPiece(board, horsy, x - 1u, y - 2u) ||
+      hasPiece(board, horsy, x + 2u, y + 1u) || hasPiece(board, horsy, x - 2u, y + 1u) ||
+      hasPiece(board, horsy, x + 2u, y - 1u) || hasPiece(board, horsy, x - 2u, y - 1u)) {
+    return true;
+  }
+
+  let king = King | by;
+  for (var dx = 0u; dx < 3u; dx++) {
+    for (var dy = 0u; dy < 3u; dy++) {
+      if (hasPiece(board, king, x + dx - 1u, y + dy - 1u)) {
+        return true;
+      }
+    }
+  }
+
+  // White pawns attack upwards, so they're located below the square
+  let pawn = Pawn | by;
+  var pawn_y = y + 1u;
+  if (by == 0x8u) {
+    pawn_y = y - 1u;
+  }
+  if (hasPiece(board, pawn, x + 1u, pawn_y) || hasPiece(board, pawn, x - 1u, pawn_y)) {
+    return true;
+  }
+
+  let rook = Rook | by;
+  let bishop = Bishop | by;
+  let queen = Queen | by;
+  return slideHits(board, x, y, 1, 0, rook, queen) || slideHits(board, x, y, -1, 0, rook, queen) ||
+    slideHits(board, x, y, 0, 1, rook, queen) || slideHits(board, x, y, 0, -1, rook, queen) ||
+    slideHits(board, x, y, 1, 1, bishop, queen) || slideHits(board, x, y, 1, -1, bishop, queen) ||
+    slideHits(board, x, y, -1, 1, bishop, queen) || slideHits(board, x, y, -1, -1, bishop, queen);
+}
+
+// The score of a board where the game was won by a rule of the variant, or 0 if it hasn't been
+fn variantWinScore(board: ptr<function, Board>, variant: u32) -> i32 {
+  if (variant == KingOfTheHill) {
+    for (var x = 3u; x < 5u; x++) {
+      for (var y = 3u; y < 5u; y++) {
+        let piece = getPiece(board, x, y);
+        if ((piece & 0x7u) == King) {
+          if ((piece & 0x8u) == 0u) {
+            return -VariantWinScore;
+          }
+          return VariantWinScore;
+        }
+      }
+    }
+  } else if (variant == ThreeCheck) {
+    if (getChecks(board, 0x8u) >= 3u) {
+      return VariantWinScore;
+    }
+    if (getChecks(board, 0x0u) >= 3u) {
+      return -VariantWinScore;
+    }
+  }
+  return 0;
+}
+
+// The score of a board that has a tablebase result, where `to_move` is the side to move on the board.
+// Cursed wins and blessed losses are drawn by the 50 move rule, but are still a little better than a draw
+fn tablebaseScore(board: ptr<function, Board>, to_move: u32) -> i32 {
+  let wdl = i32((*board).pieces[TablebaseWord]) - 3;
+  var score = 0;
+  if (wdl == 2) {
+    score = TablebaseWinScore;
+  } else if (wdl == -2) {
+    score = -TablebaseWinScore;
+  } else {
+    score = wdl;
+  }
+  if (to_move == 0x0u) {
+    score *= -1;
+  }
+  return score;
+}
+
 fn evalPosition(board: ptr<function, Board>) -> i32 {
   var eval_score = i32(0);
 
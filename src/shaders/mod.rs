@@ -1,5 +1,5 @@
 use bytemuck::{Pod, Zeroable};
-use wgpu::{Device, PipelineLayoutDescriptor, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, ComputePipeline, include_wgsl, ShaderModuleDescriptor, BindGroupLayout, BindGroup, BindGroupDescriptor, BindGroupEntry, DynamicOffset};
+use wgpu::{Buffer, Device, PipelineLayoutDescriptor, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, ComputePipeline, include_wgsl, ShaderModuleDescriptor, BindGroupLayout, BindGroup, BindGroupDescriptor, BindGroupEntry, DynamicOffset};
 
 use crate::{gpu::{GpuGlobalData, GpuAllocations}, buffers::AllocToken, chess::{GpuBoard, EvalScore}};
 
@@ -14,6 +14,16 @@ macro_rules! include_shader {
     };
 }
 
+/// Like `include_shader`, with the code that the retrograde passes share in between
+macro_rules! include_retrograde_shader {
+    ($($token:tt)*) => {
+        ShaderModuleDescriptor {
+            label: Some($($token)*),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(concat!(include_str!("lib.wgsl"), include_str!("retrograde.wgsl"), include_str!($($token)*))))
+        }
+    };
+}
+
 pub struct Shader(pub BindGroupLayout, pub ComputePipeline);
 
 pub fn expand(device: &Device) -> Shader {
@@ -61,6 +71,16 @@ pub fn expand(device: &Device) -> Shader {
                     },
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
             ],
         }
     );
@@ -285,6 +305,130 @@ pub fn filter(device: &Device) -> Shader {
     return Shader(bind_group_layout, pipeline);
 }
 
+pub fn legal(device: &Device) -> Shader {
+    let bind_group_layout = device.create_bind_group_layout(
+        &BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+            ],
+        }
+    );
+
+    let pipeline = device.create_compute_pipeline(
+        &wgpu::ComputePipelineDescriptor {
+            label: Some("Legal"),
+            layout: Some(&device.create_pipeline_layout(
+                &PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&bind_group_layout],
+                    push_constant_ranges: &[]
+                }
+            )),
+            module: &device.create_shader_module(include_shader!("legal.wgsl")),
+            entry_point: "legal_pass"
+        }
+    );
+
+    return Shader(bind_group_layout, pipeline);
+}
+
+pub fn checks(device: &Device) -> Shader {
+    let bind_group_layout = device.create_bind_group_layout(
+        &BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+            ],
+        }
+    );
+
+    let pipeline = device.create_compute_pipeline(
+        &wgpu::ComputePipelineDescriptor {
+            label: Some("Checks"),
+            layout: Some(&device.create_pipeline_layout(
+                &PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&bind_group_layout],
+                    push_constant_ranges: &[]
+                }
+            )),
+            module: &device.create_shader_module(include_shader!("checks.wgsl")),
+            entry_point: "checks_pass"
+        }
+    );
+
+    return Shader(bind_group_layout, pipeline);
+}
+
 pub fn fill_max(device: &Device) -> Shader {
     let bind_group_layout = device.create_bind_group_layout(
         &BindGroupLayoutDescriptor {
@@ -332,6 +476,101 @@ pub fn fill_max(device: &Device) -> Shader {
     return Shader(bind_group_layout, pipeline);
 }
 
+/// The passes that generate DTM tables, which share their bindings: the parameters, the results, the exits, the candidates
+/// and the amount of positions that changed
+pub fn retrograde(device: &Device, file: RetrogradePass) -> Shader {
+    let storage = |binding, read_only| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None
+        },
+        count: None,
+    };
+    let bind_group_layout = device.create_bind_group_layout(
+        &BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None,
+                },
+                storage(1, false),
+                storage(2, true),
+                storage(3, false),
+                storage(4, false),
+            ],
+        }
+    );
+
+    let (label, module, entry_point) = match file {
+        RetrogradePass::Resolve => ("Retrograde Resolve", include_retrograde_shader!("retro_resolve.wgsl"), "resolve_pass"),
+        RetrogradePass::Unmove => ("Unmove", include_retrograde_shader!("unmove.wgsl"), "unmove_pass"),
+    };
+    let pipeline = device.create_compute_pipeline(
+        &wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(&device.create_pipeline_layout(
+                &PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&bind_group_layout],
+                    push_constant_ranges: &[]
+                }
+            )),
+            module: &device.create_shader_module(module),
+            entry_point
+        }
+    );
+
+    return Shader(bind_group_layout, pipeline);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetrogradePass {
+    /// Gives results to the positions that are as many plies from mate as the pass
+    Resolve,
+    /// Marks the positions before the ones that just got a result
+    Unmove,
+}
+
+pub struct RetrogradeBindGroupMngr {
+
+}
+
+pub struct RetrogradeBuffers<'a> {
+    pub params: &'a Buffer,
+    pub results: &'a Buffer,
+    pub exits: &'a Buffer,
+    pub candidates: &'a Buffer,
+    pub changed: &'a Buffer,
+}
+
+impl RetrogradeBindGroupMngr {
+    pub fn create(device: &Device, shader: &Shader, buffers: RetrogradeBuffers) -> BindGroup {
+        let entries = [buffers.params, buffers.results, buffers.exits, buffers.candidates, buffers.changed].into_iter().enumerate()
+            .map(|(i, buffer)| BindGroupEntry {
+                binding: i as u32,
+                resource: wgpu::BindingResource::Buffer(buffer.as_entire_buffer_binding())
+            })
+            .collect::<Vec<_>>();
+        return device.create_bind_group(
+            &BindGroupDescriptor {
+                label: None,
+                layout: &shader.0,
+                entries: &entries,
+            }
+        );
+    }
+}
+
 pub struct ExpansionBindGroupMngr {
     
 }
@@ -364,6 +603,10 @@ impl ExpansionBindGroupMngr {
                         binding: 3,
                         resource: wgpu::BindingResource::Buffer(engine.out_index.as_entire_buffer_binding())
                     },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::Buffer(engine.zobrist_keys.as_entire_buffer_binding())
+                    },
                 ]
             }
         );
@@ -553,6 +796,91 @@ impl FilterBindGroupMngr {
     }
 }
 
+pub struct LegalBindGroupMngr {
+    
+}
+
+pub struct LegalBuffers<'a> {
+    pub input: &'a AllocToken<GpuBoard>,
+    pub output: &'a AllocToken<GpuBoard>,
+}
+
+impl LegalBindGroupMngr {
+    pub fn create(engine: &GpuGlobalData, alloc: &GpuAllocations, buffers: LegalBuffers) -> BindOut<2> {
+        let legal_bind = engine.device.create_bind_group(
+            &BindGroupDescriptor {
+                label: None,
+                layout: &engine.legal_shader.0,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(engine.global_data.as_entire_buffer_binding())
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer(buffers.input.buffer(&alloc.boards).as_entire_buffer_binding())
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Buffer(buffers.output.buffer(&alloc.boards).as_entire_buffer_binding())
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Buffer(engine.out_index.as_entire_buffer_binding())
+                    },
+                ]
+            }
+        );
+        let o = BuffOffsets {
+            buf_offset_0: 0,
+            buf_offset_1: buffers.input.start_elem(),
+            buf_offset_2: buffers.output.start_elem(),
+            buf_offset_3: 0,
+        };
+        return BindOut(legal_bind, o);
+    }
+}
+
+pub struct ChecksBindGroupMngr {
+    
+}
+
+pub struct ChecksBuffers<'a> {
+    pub boards: &'a AllocToken<GpuBoard>,
+}
+
+impl ChecksBindGroupMngr {
+    pub fn create(engine: &GpuGlobalData, alloc: &GpuAllocations, buffers: ChecksBuffers) -> BindOut<2> {
+        let checks_bind = engine.device.create_bind_group(
+            &BindGroupDescriptor {
+                label: None,
+                layout: &engine.checks_shader.0,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(engine.global_data.as_entire_buffer_binding())
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer(buffers.boards.buffer(&alloc.boards).as_entire_buffer_binding())
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Buffer(engine.zobrist_keys.as_entire_buffer_binding())
+                    },
+                ]
+            }
+        );
+        let o = BuffOffsets {
+            buf_offset_0: 0,
+            buf_offset_1: buffers.boards.start_elem(),
+            buf_offset_2: 0,
+            buf_offset_3: 0,
+        };
+        return BindOut(checks_bind, o);
+    }
+}
+
 #[derive(Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
 pub struct BuffOffsets {
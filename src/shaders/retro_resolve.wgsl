@@ -0,0 +1,189 @@
+
+// What the moves of the position lead to, collected by `visitChild`
+var<private> any_move: bool;
+var<private> wins: bool;
+var<private> all_lost: bool;
+
+// Gives positions their result when it's this many plies from mate. The first pass finds the invalid positions, and the ones
+// without moves. After that a position wins if a move leads to a loss found in an earlier pass, and loses if every move
+// leads to a win found in an earlier pass, so the passes go through the results in order of their distance
+@compute @workgroup_size(64)
+fn resolve_pass(
+  @builtin(global_invocation_id)
+  global_id : vec3u,
+) {
+  let index = positionIndex(global_id);
+  if (index >= params.size || atomicLoad(&results[index]) != Unknown) {
+    return;
+  }
+  let ply = params.ply;
+  let exit = exits[index];
+  // Only positions where a move got a result are looked at again, and the ones that can leave the table because that
+  // result can be any distance away
+  if (ply > 0u && exit == Unknown && !isCandidate(index)) {
+    return;
+  }
+
+  var pos = decodePosition(index);
+  if (ply == 0u && !isValid(&pos)) {
+    atomicStore(&results[index], Invalid);
+    return;
+  }
+  var board = positionBoard(&pos);
+
+  any_move = exit != Unknown;
+  wins = false;
+  all_lost = true;
+  visitMoves(&pos, &board);
+
+  // Exits already count the move that leaves the table
+  let exit_kind = resultKind(exit);
+  if (exit_kind == Win && resultPly(exit) <= ply) {
+    wins = true;
+  } else if (exit_kind == Win || exit_kind == Draw || (exit_kind == Loss && resultPly(exit) > ply)) {
+    all_lost = false;
+  }
+
+  var result = Unknown;
+  if (ply == 0u) {
+    if (!any_move) {
+      let king = kingSquare(&pos, pos.to_move);
+      result = makeResult(Draw, 0u);
+      if (isAttacked(&board, king & 7u, king >> 3u, pos.to_move ^ 0x8u)) {
+        result = makeResult(Loss, 0u);
+      }
+    }
+  } else if (wins) {
+    result = makeResult(Win, ply);
+  } else if (all_lost && any_move) {
+    result = makeResult(Loss, ply);
+  }
+  if (result != Unknown) {
+    atomicStore(&results[index], result);
+    atomicAdd(&changed, 1u);
+  }
+}
+
+// Whether the position can come up in a game: no two pieces on the same square, no pawns on the first or last rank,
+// and the side that just moved isn't in check
+fn isValid(pos: ptr<function, Position>) -> bool {
+  for (var i = 0u; i < params.num_pieces; i++) {
+    let square = (*pos).squares[i];
+    let rank = square >> 3u;
+    if ((tablePiece(i) & 0x7u) == Pawn && (rank == 0u || rank == 7u)) {
+      return false;
+    }
+    for (var j = 0u; j < i; j++) {
+      if ((*pos).squares[j] == square) {
+        return false;
+      }
+    }
+  }
+  var board = positionBoard(pos);
+  let king = kingSquare(pos, (*pos).to_move ^ 0x8u);
+  return !isAttacked(&board, king & 7u, king >> 3u, (*pos).to_move);
+}
+
+// Goes through the legal moves that stay in the table, so the ones that don't capture or promote
+fn visitMoves(pos: ptr<function, Position>, board: ptr<function, Board>) {
+  let to_move = (*pos).to_move;
+  for (var i = 0u; i < params.num_pieces; i++) {
+    let piece = tablePiece(i);
+    if ((piece & 0x8u) != to_move) {
+      continue;
+    }
+    let x = (*pos).squares[i] & 7u;
+    let y = (*pos).squares[i] >> 3u;
+    let piece_type = piece & 0x7u;
+    if (piece_type == Pawn) {
+      var dy = 1u;
+      var start_rank = 1u;
+      var last_rank = 7u;
+      if (to_move == 0x0u) {
+        dy = 0xFFFFFFFFu;
+        start_rank = 6u;
+        last_rank = 0u;
+      }
+      if (y + dy != last_rank && getPiece(board, x, y + dy) == 0u) {
+        visitChild(pos, board, i, x, y + dy);
+        if (y == start_rank && getPiece(board, x, y + dy * 2u) == 0u) {
+          visitChild(pos, board, i, x, y + dy * 2u);
+        }
+      }
+    } else if (piece_type == King) {
+      step(pos, board, i, x, y, -1, -1);
+      step(pos, board, i, x, y, 0, -1);
+      step(pos, board, i, x, y, 1, -1);
+      step(pos, board, i, x, y, -1, 0);
+      step(pos, board, i, x, y, 1, 0);
+      step(pos, board, i, x, y, -1, 1);
+      step(pos, board, i, x, y, 0, 1);
+      step(pos, board, i, x, y, 1, 1);
+    } else if (piece_type == Horsy) {
+      step(pos, board, i, x, y, 2, -1);
+      step(pos, board, i, x, y, 2, 1);
+      step(pos, board, i, x, y, -2, -1);
+      step(pos, board, i, x, y, -2, 1);
+      step(pos, board, i, x, y, -1, 2);
+      step(pos, board, i, x, y, 1, 2);
+      step(pos, board, i, x, y, -1, -2);
+      step(pos, board, i, x, y, 1, -2);
+    }
+    if (piece_type == Rook || piece_type == Queen) {
+      slide(pos, board, i, x, y, 1, 0);
+      slide(pos, board, i, x, y, -1, 0);
+      slide(pos, board, i, x, y, 0, 1);
+      slide(pos, board, i, x, y, 0, -1);
+    }
+    if (piece_type == Bishop || piece_type == Queen) {
+      slide(pos, board, i, x, y, 1, 1);
+      slide(pos, board, i, x, y, 1, -1);
+      slide(pos, board, i, x, y, -1, 1);
+      slide(pos, board, i, x, y, -1, -1);
+    }
+  }
+}
+
+fn step(pos: ptr<function, Position>, board: ptr<function, Board>, i: u32, x: u32, y: u32, dx: i32, dy: i32) {
+  let xNew = x + u32(dx);
+  let yNew = y + u32(dy);
+  if (xNew < 8u && yNew < 8u && getPiece(board, xNew, yNew) == 0u) {
+    visitChild(pos, board, i, xNew, yNew);
+  }
+}
+
+fn slide(pos: ptr<function, Position>, board: ptr<function, Board>, i: u32, x: u32, y: u32, dx: i32, dy: i32) {
+  var xNew = x + u32(dx);
+  var yNew = y + u32(dy);
+  loop {
+    if (xNew >= 8u || yNew >= 8u || getPiece(board, xNew, yNew) != 0u) {
+      return;
+    }
+    visitChild(pos, board, i, xNew, yNew);
+    xNew = xNew + u32(dx);
+    yNew = yNew + u32(dy);
+  }
+}
+
+// Moves piece `i` to an empty square, and looks up the result of the position if that's legal
+fn visitChild(pos: ptr<function, Position>, board: ptr<function, Board>, i: u32, xNew: u32, yNew: u32) {
+  let to_move = (*pos).to_move;
+  var next = *pos;
+  next.squares[i] = yNew * 8u + xNew;
+  next.to_move = to_move ^ 0x8u;
+  var next_board = positionBoard(&next);
+  let king = kingSquare(&next, to_move);
+  if (isAttacked(&next_board, king & 7u, king >> 3u, next.to_move)) {
+    return;
+  }
+  any_move = true;
+
+  // Results from this pass don't count yet, so it doesn't matter which threads got to theirs first
+  let child = atomicLoad(&results[encodePosition(&next)]);
+  let kind = resultKind(child);
+  if (kind == Loss && resultPly(child) < params.ply) {
+    wins = true;
+  } else if (kind != Win || resultPly(child) >= params.ply) {
+    all_lost = false;
+  }
+}
@@ -0,0 +1,122 @@
+// Shared by the passes that generate DTM tables, see `retrograde.rs`.
+// Positions are indices into the table: the side to move, the square of the white king on the left half of the board
+// (the rest is mirrored), then the squares of the other pieces, all as y * 8 + x. See `Layout::index`
+
+// A result is the distance to mate in plies << 3 | the kind
+const Unknown = 0u;
+const Win = 1u;
+const Loss = 2u;
+const Draw = 3u;
+const Invalid = 4u;
+
+struct TableParams {
+  // Positions in the table
+  size: u32,
+  // Positions per row of the dispatch, which is two dimensional for tables that don't fit in one
+  row_size: u32,
+  // The pass, positions that get a result in it are this many plies from mate
+  ply: u32,
+  num_pieces: u32,
+  // One nibble per piece, in the order of the index
+  pieces: u32,
+}
+
+struct Position {
+  squares: array<u32, 4>,
+  to_move: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: TableParams;
+@group(0) @binding(1)
+var<storage, read_write> results: array<atomic<u32>>;
+// The best result of the moves that capture or promote, which leave the table. It's worked out by the cpu for the
+// side to move and uses the same encoding, with `Unknown` if there aren't any such moves
+@group(0) @binding(2)
+var<storage, read> exits: array<u32>;
+// A bit per position, set when one of its moves got a result in the last pass
+@group(0) @binding(3)
+var<storage, read_write> candidates: array<atomic<u32>>;
+// The amount of positions that got a result in this pass
+@group(0) @binding(4)
+var<storage, read_write> changed: atomic<u32>;
+
+fn positionIndex(global_id: vec3u) -> u32 {
+  return global_id.y * params.row_size + global_id.x;
+}
+
+fn tablePiece(i: u32) -> u32 {
+  return (params.pieces >> (i * 4u)) & 0xFu;
+}
+
+fn resultKind(result: u32) -> u32 {
+  return result & 0x7u;
+}
+
+fn resultPly(result: u32) -> u32 {
+  return result >> 3u;
+}
+
+fn makeResult(kind: u32, ply: u32) -> u32 {
+  return (ply << 3u) | kind;
+}
+
+fn decodePosition(index: u32) -> Position {
+  var pos: Position;
+  var rest = index;
+  for (var i = params.num_pieces - 1u; i > 0u; i--) {
+    pos.squares[i] = rest % 64u;
+    rest = rest / 64u;
+  }
+  let king = rest % 32u;
+  pos.squares[0] = (king / 4u) * 8u + king % 4u;
+  pos.to_move = 0x8u;
+  if (rest >= 32u) {
+    pos.to_move = 0x0u;
+  }
+  return pos;
+}
+
+fn encodePosition(pos: ptr<function, Position>) -> u32 {
+  var mirror = 0u;
+  if (((*pos).squares[0] & 7u) >= 4u) {
+    mirror = 7u;
+  }
+  let king = (*pos).squares[0] ^ mirror;
+  var index = (king >> 3u) * 4u + (king & 7u);
+  if ((*pos).to_move == 0x0u) {
+    index += 32u;
+  }
+  for (var i = 1u; i < params.num_pieces; i++) {
+    index = index * 64u + ((*pos).squares[i] ^ mirror);
+  }
+  return index;
+}
+
+// Only meaningful if no two pieces are on the same square
+fn positionBoard(pos: ptr<function, Position>) -> Board {
+  var board: Board;
+  for (var i = 0u; i < params.num_pieces; i++) {
+    let square = (*pos).squares[i];
+    board.pieces[square >> 3u] |= tablePiece(i) << ((square & 7u) * 4u);
+  }
+  return board;
+}
+
+// The square of the king of a side
+fn kingSquare(pos: ptr<function, Position>, side: u32) -> u32 {
+  for (var i = 0u; i < params.num_pieces; i++) {
+    if (tablePiece(i) == (King | side)) {
+      return (*pos).squares[i];
+    }
+  }
+  return 0u;
+}
+
+fn isCandidate(index: u32) -> bool {
+  return ((atomicLoad(&candidates[index >> 5u]) >> (index & 31u)) & 1u) != 0u;
+}
+
+fn markCandidate(index: u32) {
+  atomicOr(&candidates[index >> 5u], 1u << (index & 31u));
+}
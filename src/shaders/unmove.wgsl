@@ -0,0 +1,110 @@
+
+// Takes back the last move of every position that got its result in this pass, and marks the positions that come
+// before it as candidates for the next pass. Only moves that stay in the table are taken back, the others are covered
+// by the exits. Whether a marked position is valid, or already has a result, is left to the resolve pass
+@compute @workgroup_size(64)
+fn unmove_pass(
+  @builtin(global_invocation_id)
+  global_id : vec3u,
+) {
+  let index = positionIndex(global_id);
+  if (index >= params.size) {
+    return;
+  }
+  let result = atomicLoad(&results[index]);
+  let kind = resultKind(result);
+  if ((kind != Win && kind != Loss) || resultPly(result) != params.ply) {
+    return;
+  }
+
+  var pos = decodePosition(index);
+  var board = positionBoard(&pos);
+  // The side that made the last move, so the one whose pieces go back
+  let moved = pos.to_move ^ 0x8u;
+  for (var i = 0u; i < params.num_pieces; i++) {
+    let piece = tablePiece(i);
+    if ((piece & 0x8u) != moved) {
+      continue;
+    }
+    let x = pos.squares[i] & 7u;
+    let y = pos.squares[i] >> 3u;
+    let piece_type = piece & 0x7u;
+    if (piece_type == Pawn) {
+      // Back towards its own side, without reaching the first rank. Pawns that just made a double push go back two squares
+      if (moved == 0x8u) {
+        if (y >= 2u && getPiece(&board, x, y - 1u) == 0u) {
+          markPredecessor(&pos, i, x, y - 1u);
+          if (y == 3u && getPiece(&board, x, 1u) == 0u) {
+            markPredecessor(&pos, i, x, 1u);
+          }
+        }
+      } else {
+        if (y <= 5u && getPiece(&board, x, y + 1u) == 0u) {
+          markPredecessor(&pos, i, x, y + 1u);
+          if (y == 4u && getPiece(&board, x, 6u) == 0u) {
+            markPredecessor(&pos, i, x, 6u);
+          }
+        }
+      }
+    } else if (piece_type == King) {
+      unstep(&pos, &board, i, x, y, -1, -1);
+      unstep(&pos, &board, i, x, y, 0, -1);
+      unstep(&pos, &board, i, x, y, 1, -1);
+      unstep(&pos, &board, i, x, y, -1, 0);
+      unstep(&pos, &board, i, x, y, 1, 0);
+      unstep(&pos, &board, i, x, y, -1, 1);
+      unstep(&pos, &board, i, x, y, 0, 1);
+      unstep(&pos, &board, i, x, y, 1, 1);
+    } else if (piece_type == Horsy) {
+      unstep(&pos, &board, i, x, y, 2, -1);
+      unstep(&pos, &board, i, x, y, 2, 1);
+      unstep(&pos, &board, i, x, y, -2, -1);
+      unstep(&pos, &board, i, x, y, -2, 1);
+      unstep(&pos, &board, i, x, y, -1, 2);
+      unstep(&pos, &board, i, x, y, 1, 2);
+      unstep(&pos, &board, i, x, y, -1, -2);
+      unstep(&pos, &board, i, x, y, 1, -2);
+    }
+    if (piece_type == Rook || piece_type == Queen) {
+      unslide(&pos, &board, i, x, y, 1, 0);
+      unslide(&pos, &board, i, x, y, -1, 0);
+      unslide(&pos, &board, i, x, y, 0, 1);
+      unslide(&pos, &board, i, x, y, 0, -1);
+    }
+    if (piece_type == Bishop || piece_type == Queen) {
+      unslide(&pos, &board, i, x, y, 1, 1);
+      unslide(&pos, &board, i, x, y, 1, -1);
+      unslide(&pos, &board, i, x, y, -1, 1);
+      unslide(&pos, &board, i, x, y, -1, -1);
+    }
+  }
+}
+
+// Pieces other than pawns move back the same way they move forward
+fn unstep(pos: ptr<function, Position>, board: ptr<function, Board>, i: u32, x: u32, y: u32, dx: i32, dy: i32) {
+  let xOld = x + u32(dx);
+  let yOld = y + u32(dy);
+  if (xOld < 8u && yOld < 8u && getPiece(board, xOld, yOld) == 0u) {
+    markPredecessor(pos, i, xOld, yOld);
+  }
+}
+
+fn unslide(pos: ptr<function, Position>, board: ptr<function, Board>, i: u32, x: u32, y: u32, dx: i32, dy: i32) {
+  var xOld = x + u32(dx);
+  var yOld = y + u32(dy);
+  loop {
+    if (xOld >= 8u || yOld >= 8u || getPiece(board, xOld, yOld) != 0u) {
+      return;
+    }
+    markPredecessor(pos, i, xOld, yOld);
+    xOld = xOld + u32(dx);
+    yOld = yOld + u32(dy);
+  }
+}
+
+fn markPredecessor(pos: ptr<function, Position>, i: u32, xOld: u32, yOld: u32) {
+  var prev = *pos;
+  prev.squares[i] = yOld * 8u + xOld;
+  prev.to_move = (*pos).to_move ^ 0x8u;
+  markCandidate(encodePosition(&prev));
+}
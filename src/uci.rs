@@ -2,7 +2,12 @@ use std::{io, sync::{atomic::{AtomicBool, AtomicU16, AtomicU64}, Mutex, Arc}, rc
 
 use pollster::FutureExt;
 
-use crate::chess::{GameState, Move, EvalScore, Side};
+use crate::chess::{GameState, GameStatus, Move, EvalScore, Side, Variant, polyglot::{Book, BookSelection}, syzygy::{self, Tablebases, Wdl}, dtm::DtmTables};
+
+/// Book moves are played up to this move number, unless `BookDepth` is set
+const DEFAULT_BOOK_DEPTH: u32 = 20;
+/// Fixed, so the same position always gets the same weighted book move and games can be reproduced
+const BOOK_SEED: u64 = 0;
 
 pub fn start_loop(mut engine: impl EngineComs) -> ! {
     let mut buffer = String::new();
@@ -14,6 +19,17 @@ pub fn start_loop(mut engine: impl EngineComs) -> ! {
         Some("uci") => {
             println!("id name {} (version {})", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
             println!("id author {}", env!("CARGO_PKG_AUTHORS"));
+            println!("option name UCI_Chess960 type check default false");
+            let variants: Vec<_> = Variant::ALL.iter().map(|v| format!("var {}", v.uci_name())).collect();
+            println!("option name UCI_Variant type combo default {} {}", Variant::Standard.uci_name(), variants.join(" "));
+            println!("option name OwnBook type check default false");
+            println!("option name BookFile type string default <empty>");
+            println!("option name BookDepth type spin default {DEFAULT_BOOK_DEPTH} min 0 max 1000");
+            let selections: Vec<_> = BookSelection::ALL.iter().map(|s| format!("var {}", s.uci_name())).collect();
+            println!("option name BookSelection type combo default {} {}", BookSelection::default().uci_name(), selections.join(" "));
+            println!("option name SyzygyPath type string default <empty>");
+            println!("option name SyzygyProbeLimit type spin default {0} min 0 max {0}", syzygy::MAX_PIECES);
+            println!("option name DtmPath type string default <empty>");
             println!("uciok :3");
         }
         _ => {
@@ -23,6 +39,15 @@ pub fn start_loop(mut engine: impl EngineComs) -> ! {
 
     let mut gamestate = None;
     let mut current_search = None;
+    let mut chess960 = false;
+    let mut variant = Variant::Standard;
+    let mut book: Option<Book> = None;
+    let mut own_book = false;
+    let mut book_depth = DEFAULT_BOOK_DEPTH;
+    let mut book_selection = BookSelection::default();
+    let mut tablebases: Option<Arc<Tablebases>> = None;
+    let mut probe_limit = syzygy::MAX_PIECES;
+    let mut dtm_tables: Option<Arc<DtmTables>> = None;
     
 
     loop {
@@ -32,27 +57,68 @@ pub fn start_loop(mut engine: impl EngineComs) -> ! {
         match cmd.next() {
             Some("position") => {
                 let Some(pos_type) = cmd.next() else { panic!("Invalid position command") };
+                // The fen can have 4 to 6 fields, so read until the move list
+                let fen_fields: Vec<_> = cmd.by_ref().take_while(|t| *t != "moves").collect();
                 let fen;
                 if pos_type == "startpos" {
-                    fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_owned();
+                    fen = variant.start_fen().to_owned();
                 } else {
-                    fen = cmd.by_ref().take(6).collect::<Vec<_>>().join(" ");
+                    fen = fen_fields.join(" ");
                 }
 
-                let mut state = GameState::from_fen(&fen);
+                let mut state = match GameState::parse_fen(&fen) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        println!("info string invalid fen \"{fen}\": {e}");
+                        continue;
+                    }
+                };
 
-                if matches!(cmd.next(), Some("moves")) {
-                    for move_str in cmd {
-                        let m = Move::from_str(move_str);
-                        state.play(m);
-                    } 
+                // Castling is sent as the king moving onto its rook in Chess960 mode
+                if chess960 {
+                    state.set_chess960(true);
+                }
+                state.set_variant(variant);
+
+                // Anything after "moves" was left in the iterator
+                let mut moves_ok = true;
+                for move_str in cmd {
+                    let Some(m) = Move::try_from_str(move_str) else {
+                        println!("info string invalid move \"{move_str}\"");
+                        moves_ok = false;
+                        break;
+                    };
+                    if let Err(e) = state.try_play(m) {
+                        println!("info string {e} in position \"{}\"", state.to_fen());
+                        moves_ok = false;
+                        break;
+                    }
+                }
+                if !moves_ok {
+                    continue;
                 }
 
                 gamestate = Some(state);
             }
             Some("go") => {
-                for sub_cmd in cmd {
-                    match sub_cmd {
+                let sub_cmds: Vec<_> = cmd.collect();
+                if sub_cmds.first() == Some(&"perft") {
+                    // Not part of uci, but most engines support it for debugging move generation
+                    // Bad input from the gui is reported, and the engine waits for the next command
+                    let Some(depth) = sub_cmds.get(1).and_then(|d| d.parse().ok()) else {
+                        println!("info string go perft needs a depth");
+                        continue;
+                    };
+                    let Some(ref state) = gamestate else {
+                        println!("info string can't run perft without a position");
+                        continue;
+                    };
+                    engine.start_perft(state.clone(), depth).block_on();
+                    continue;
+                }
+
+                for sub_cmd in &sub_cmds {
+                    match *sub_cmd {
                         "ponder" => panic!("pondering not supported"),
                         "searchmoves" => panic!("searches cannot be restricted"),
                         "depth" => panic!("depth cannot be restricted"),
@@ -64,13 +130,45 @@ pub fn start_loop(mut engine: impl EngineComs) -> ! {
                 if (&gamestate).is_none() {
                     panic!("Can't search if you don't give me a position D:");
                 }
+                let status = gamestate.as_ref().unwrap().status();
+                if matches!(status, GameStatus::Checkmate { .. } | GameStatus::VariantWin { .. } | GameStatus::Stalemate) {
+                    // There's nothing to search, 0000 is the null move
+                    println!("info string {status}");
+                    println!("bestmove 0000");
+                    continue;
+                }
+                if let GameStatus::Draw(_) = status {
+                    // Moves can still be played, it's up to the gui to end the game
+                    println!("info string {status}");
+                }
+
+                // Polyglot books are only made for standard chess
+                let state = gamestate.as_ref().unwrap();
+                if let Some(book) = book.as_ref().filter(|_| state.get_variant() == Variant::Standard) {
+                    let moves = book.moves(state);
+                    if !moves.is_empty() {
+                        let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+                        let listed: Vec<_> = moves.iter().map(|(m, weight)| format!("{m} ({}%)", *weight as u32 * 100 / total)).collect();
+                        println!("info string book {}", listed.join(" "));
+                    }
+                    // When analysing, the book moves are only reported and the position is still searched
+                    let analysing = sub_cmds.contains(&"infinite");
+                    if own_book && !analysing && state.get_fullmove_number() <= book_depth && let Some(m) = book.choose(state, book_selection, BOOK_SEED) {
+                        println!("bestmove {m}");
+                        continue;
+                    }
+                }
 
                 let coms = Arc::new(UciEvalSession {
                     to_move: gamestate.as_ref().unwrap().to_move,
                     stopped: AtomicBool::new(false),
                     depth: AtomicU16::new(0),
                     nodes: AtomicU64::new(0),
+                    tbhits: AtomicU64::new(0),
                     best: Mutex::new(None),
+                    tablebases: tablebases.clone(),
+                    probe_limit,
+                    dtm_tables: dtm_tables.clone(),
                 });
                 current_search = Some(coms.clone());
 
@@ -80,6 +178,67 @@ pub fn start_loop(mut engine: impl EngineComs) -> ! {
                 let Some(ref coms) = current_search else { panic!("no active search") };
                 coms.stop();
             }
+            Some("setoption") => {
+                // Option names can contain spaces: setoption name <name> [value <value>]
+                let tokens: Vec<_> = cmd.collect();
+                let value_start = tokens.iter().position(|t| *t == "value").unwrap_or(tokens.len());
+                let name = tokens.get(1..value_start).unwrap_or_default().join(" ");
+                let value = tokens.get(value_start + 1..).unwrap_or_default().join(" ");
+                match name.as_str() {
+                    "UCI_Chess960" => chess960 = value == "true",
+                    "UCI_Variant" => match Variant::from_uci_name(&value) {
+                        Some(v) => variant = v,
+                        None => println!("info string unknown variant \"{value}\""),
+                    },
+                    "OwnBook" => own_book = value == "true",
+                    "BookFile" if value.is_empty() || value == "<empty>" => book = None,
+                    "BookFile" => match Book::open(&value) {
+                        Ok(b) => {
+                            println!("info string loaded {} book entries from \"{value}\"", b.len());
+                            book = Some(b);
+                        },
+                        Err(e) => {
+                            println!("info string can't read book \"{value}\": {e}");
+                            book = None;
+                        },
+                    },
+                    "BookDepth" => match value.parse() {
+                        Ok(depth) => book_depth = depth,
+                        Err(_) => println!("info string invalid book depth \"{value}\""),
+                    },
+                    "BookSelection" => match BookSelection::from_uci_name(&value) {
+                        Some(s) => book_selection = s,
+                        None => println!("info string unknown book selection \"{value}\""),
+                    },
+                    "SyzygyPath" if value.is_empty() || value == "<empty>" => tablebases = None,
+                    "SyzygyPath" => match Tablebases::open(&value) {
+                        Ok(tb) => {
+                            println!("info string found {} syzygy tables in \"{value}\"", tb.len());
+                            tablebases = Some(Arc::new(tb));
+                        },
+                        Err(e) => {
+                            println!("info string can't read syzygy tables in \"{value}\": {e}");
+                            tablebases = None;
+                        },
+                    },
+                    "SyzygyProbeLimit" => match value.parse() {
+                        Ok(limit) => probe_limit = limit,
+                        Err(_) => println!("info string invalid probe limit \"{value}\""),
+                    },
+                    "DtmPath" if value.is_empty() || value == "<empty>" => dtm_tables = None,
+                    "DtmPath" => match DtmTables::open(&value) {
+                        Ok(tables) => {
+                            println!("info string found {} dtm tables in \"{value}\"", tables.len());
+                            dtm_tables = Some(Arc::new(tables));
+                        },
+                        Err(e) => {
+                            println!("info string can't read dtm tables in \"{value}\": {e}");
+                            dtm_tables = None;
+                        },
+                    },
+                    _ => println!("info string unknown option \"{name}\""),
+                }
+            }
             Some("isready") => {
                 println!("readyok");
             }
@@ -95,7 +254,13 @@ pub struct UciEvalSession {
     stopped: AtomicBool,
     depth: AtomicU16,
     nodes: AtomicU64,
-    best: Mutex<Option<Move>>
+    /// Positions that were found in the tablebases
+    tbhits: AtomicU64,
+    best: Mutex<Option<Move>>,
+    tablebases: Option<Arc<Tablebases>>,
+    /// Positions with more pieces aren't probed in the Syzygy tables
+    probe_limit: usize,
+    dtm_tables: Option<Arc<DtmTables>>,
 }
 
 impl UciEvalSession {
@@ -106,13 +271,48 @@ impl UciEvalSession {
         *self.best.lock().unwrap() = Some(m);
         let depth = self.depth.load(std::sync::atomic::Ordering::Relaxed);
         let nodes = self.nodes.load(std::sync::atomic::Ordering::Relaxed);
-        println!("info score cp {} depth {depth} nodes {nodes} pv {m}", score.centipawn_relative(self.to_move));
+        let tbhits = self.tbhits.load(std::sync::atomic::Ordering::Relaxed);
+        println!("info score cp {} depth {depth} nodes {nodes} tbhits {tbhits} pv {m}", score.centipawn_relative(self.to_move));
     }
 
     pub fn report_depth_and_nodes(&self, depth: u16, nodes: u64) {
         self.depth.fetch_max(depth, std::sync::atomic::Ordering::Relaxed);
         let n = self.nodes.fetch_add(nodes, std::sync::atomic::Ordering::Relaxed);
-        println!("info depth {} nodes {}", depth, n+nodes);
+        let tbhits = self.tbhits.load(std::sync::atomic::Ordering::Relaxed);
+        println!("info depth {} nodes {} tbhits {tbhits}", depth, n+nodes);
+    }
+
+    pub fn report_tbhits(&self, tbhits: u64) {
+        self.tbhits.fetch_add(tbhits, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// The most pieces, kings included, that a position can have to be probed. 0 if there aren't any tables
+    pub fn probe_limit(&self) -> usize {
+        let syzygy = self.tablebases.as_ref().map_or(0, |tablebases| self.probe_limit.min(tablebases.max_pieces()));
+        let dtm = self.dtm_tables.as_ref().map_or(0, |tables| tables.max_pieces());
+        return syzygy.max(dtm);
+    }
+
+    /// The result of the position in the DTM tables, or else in the Syzygy tables, which also know when a capture resets the 50 move rule
+    pub fn probe_wdl(&self, state: &GameState) -> Option<Wdl> {
+        if let Some(tables) = &self.dtm_tables && let Some(wdl) = tables.probe_wdl(state) {
+            return Some(wdl);
+        }
+        return self.syzygy(state)?.probe_wdl(state);
+    }
+
+    /// The root moves that keep the best result, from the DTM tables if they have the position because those mate fastest
+    pub fn root_moves(&self, state: &GameState) -> Option<Vec<Move>> {
+        if let Some(tables) = &self.dtm_tables && let Some(moves) = tables.root_moves(state) {
+            return Some(moves);
+        }
+        return self.syzygy(state)?.root_moves(state);
+    }
+
+    /// The Syzygy tables, if the position doesn't have too many pieces for them
+    fn syzygy(&self, state: &GameState) -> Option<&Tablebases> {
+        let pieces = state.get_board().occupied().count_ones() as usize;
+        return self.tablebases.as_deref().filter(|_| pieces <= self.probe_limit);
     }
 
     pub fn stop(&self) {
@@ -140,4 +340,50 @@ impl UciEvalSession {
 
 pub trait EngineComs {
     async fn start_session(&mut self, coms: Arc<UciEvalSession>, state: GameState);
-}
\ No newline at end of file
+
+    /// Counts the nodes at the given depth, printing the count for each root move
+    async fn start_perft(&mut self, state: GameState, depth: u32);
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::{AtomicBool, AtomicU16, AtomicU64}, Arc, Mutex};
+
+    use crate::chess::{GameState, Side, dtm::{Dtm, DtmTable, DtmTables, Layout}, syzygy::{self, TableKind, Tablebases, Wdl}};
+
+    use super::UciEvalSession;
+
+    /// A search with a DTM table where every KQvK position is a mate in 21, and maybe Syzygy tables
+    fn session(tablebases: Option<Tablebases>) -> UciEvalSession {
+        let layout = Layout::from_name("KQvK").unwrap();
+        let size = layout.size();
+        let mut dtm_tables = DtmTables::default();
+        dtm_tables.insert(DtmTable::new(layout, (0..size).map(|_| Dtm::Win(41))).unwrap());
+        return UciEvalSession {
+            to_move: Side::White,
+            stopped: AtomicBool::new(false),
+            depth: AtomicU16::new(0),
+            nodes: AtomicU64::new(0),
+            tbhits: AtomicU64::new(0),
+            best: Mutex::new(None),
+            tablebases: tablebases.map(Arc::new),
+            probe_limit: syzygy::MAX_PIECES,
+            dtm_tables: Some(Arc::new(dtm_tables)),
+        };
+    }
+
+    #[test]
+    fn probe_wdl() {
+        // The Syzygy table has every KQvK position as a win as well
+        let mut tablebases = Tablebases::default();
+        tablebases.insert("KQvK", TableKind::Wdl, syzygy::test::single_value_table(TableKind::Wdl, &[0x66, 0x55, 0xEE], &[4, 0]));
+
+        let in_time = GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 59 80");
+        let too_late = GameState::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 60 80");
+        let session_both = session(Some(tablebases));
+        assert_eq!(session_both.probe_wdl(&in_time), Some(Wdl::Win));
+        // The DTM table can't tell if the mate comes in time, so Syzygy answers
+        assert_eq!(session_both.probe_wdl(&too_late), Some(Wdl::Win));
+        assert_eq!(session(None).probe_wdl(&too_late), None);
+    }
+}